@@ -0,0 +1,60 @@
+//! Scaling filters on a synthetic 8K frame down to a 128 pixel thumbnail.
+//!
+//! Only the colour and resize stages are measured, decoding the frame is the
+//! same for every filter. Run with `cargo bench --bench resize`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use exr::prelude::*;
+use exr_thumbnailer::timing::Stage;
+use exr_thumbnailer::{Filter, ThumbnailRequest, ThumbnailSize};
+use std::io::Cursor;
+use std::time::Duration;
+
+const WIDTH: usize = 7680;
+const HEIGHT: usize = 4320;
+
+/// An uncompressed half float frame with a zone plate, whose rings get finer
+/// towards the edges and alias when undersampled, and a few HDR highlights.
+fn synthetic_8k_exr() -> Vec<u8> {
+    let channels = SpecificChannels::rgba(|position: Vec2<usize>| {
+        let x = position.x() as f32 - WIDTH as f32 / 2.0;
+        let y = position.y() as f32 - HEIGHT as f32 / 2.0;
+        let ring = 0.5 + 0.5 * ((x * x + y * y) * 0.0004).sin();
+        let highlight = if position.x() % 997 < 8 && position.y() % 541 < 8 { 20.0 } else { 0.0 };
+        (
+            f16::from_f32(ring + highlight),
+            f16::from_f32(ring * 0.8),
+            f16::from_f32(1.0 - ring),
+            f16::ONE,
+        )
+    });
+    let layer = Layer::new((WIDTH, HEIGHT), LayerAttributes::default(), Encoding::UNCOMPRESSED, channels);
+    let mut bytes = Vec::new();
+    Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+    bytes
+}
+
+fn filters(c: &mut Criterion) {
+    let exr = synthetic_8k_exr();
+    let mut group = c.benchmark_group("8k_to_128");
+    // Every iteration decodes the whole frame, so keep the sample count low
+    group.sample_size(10).warm_up_time(Duration::from_millis(1)).measurement_time(Duration::from_secs(25));
+
+    for filter in [Filter::Nearest, Filter::Triangle, Filter::Lanczos3, Filter::Box, Filter::Area] {
+        let request = ThumbnailRequest::default().size(ThumbnailSize::Height(128)).filter(filter);
+        group.bench_with_input(BenchmarkId::from_parameter(filter.as_str()), &request, |b, request| {
+            b.iter_custom(|iterations| {
+                (0..iterations)
+                    .map(|_| {
+                        let timings = request.thumbnail_from_bytes(&exr).unwrap().timings;
+                        timings.get(Stage::Colour) + timings.get(Stage::Resize)
+                    })
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, filters);
+criterion_main!(benches);
@@ -10,12 +10,8 @@ def main():
     # Parametry dla exr_thumbnailer.exe z nowymi funkcjonalnościami
     cmd = [
         exe_path, 
-        "-s", "data",           # source folder
-        "-d", "thumb",          # destination folder
-        "-t", "130",            # thumbnail height
-        "-l",                   # enable linear tone mapping
-        "-g", "2.2",            # gamma correction
-        "-f", "lanczos3"        # scaling filter algorithm
+        "--config", "thumbs.toml",   # wspólne ustawienia (folder źródłowy i docelowy, tone mapping, gamma)
+        "--preset", "web"            # wysokość miniatury i filtr skalowania z sekcji [preset.web]
     ]
     
     try:
@@ -4,18 +4,19 @@ REM Skrypt do uruchamiania aplikacji z przykladowa konfiguracja.
 REM --- ZMIEN TE WARTOSCI ---
 set SOURCE_FOLDER="C:\_cloud\___EXRuster_tools\data"
 set DEST_FOLDER="C:\_cloud\___EXRuster_tools\thumb"
-set THUMB_HEIGHT=256
-set INFO_FILENAME="stats.txt"
+set CONFIG_FILE="thumbs.toml"
+set PRESET=example
 REM --- KONIEC KONFIGURACJI ---
 
 echo [INFO] Uruchamianie aplikacji z ponizszymi ustawieniami:
 set | findstr "SOURCE_FOLDER="
 set | findstr "DEST_FOLDER="
-set | findstr "THUMB_HEIGHT="
-set | findstr "INFO_FILENAME="
+set | findstr "CONFIG_FILE="
+set | findstr "PRESET="
 echo.
 
-.\target\release\exr_thumbnailer.exe --source-folder %SOURCE_FOLDER% --dest-folder %DEST_FOLDER% --height %THUMB_HEIGHT% --info %INFO_FILENAME%
+REM Wysokosc miniatury i plik statystyk z sekcji [preset.example], foldery z wiersza polecen maja pierwszenstwo
+.\target\release\exr_thumbnailer.exe --config %CONFIG_FILE% --preset %PRESET% --source-folder %SOURCE_FOLDER% --dest-folder %DEST_FOLDER%
 
 echo.
 
@@ -6,12 +6,8 @@ def main():
     # Parametry dla polecenia cargo run z nowymi funkcjonalnościami
     cmd = [
         "cargo", "run", "--",
-        "-s", "data",           # source folder
-        "-d", "thumb",          # destination folder
-        "-t", "200",            # thumbnail height
-        "-l",                   # enable linear tone mapping
-        "-g", "2.2",            # gamma correction
-        "-f", "gaussian"        # scaling filter algorithm (gaussian dla szybszego skalowania)
+        "--config", "thumbs.toml",   # wspólne ustawienia (folder źródłowy i docelowy, tone mapping, gamma)
+        "--preset", "review"         # wysokość miniatury i filtr skalowania z sekcji [preset.review]
     ]
     
     try:
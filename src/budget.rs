@@ -0,0 +1,103 @@
+//! Limit on the memory used by the files converted at the same time.
+
+use std::sync::{Condvar, Mutex};
+
+/// Bytes shared by the conversions running in parallel
+pub struct MemoryBudget {
+    limit: u64,
+    in_use: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Memory reserved for one file, given back when dropped
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until `bytes` fit into the budget. A file larger than the whole
+    /// budget waits until nothing else is reserved and then runs alone.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use > 0 && *in_use + bytes > self.limit {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += bytes;
+        Reservation { budget: self, bytes }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn in_use(budget: &MemoryBudget) -> u64 {
+        *budget.in_use.lock().unwrap()
+    }
+
+    /// Whether reserving `bytes` on another thread waits until `release` runs
+    fn waits_for(budget: &MemoryBudget, bytes: u64, release: impl FnOnce()) -> bool {
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            scope.spawn(move || {
+                let _reservation = budget.reserve(bytes);
+                sender.send(()).unwrap();
+            });
+            let waited = receiver.recv_timeout(Duration::from_millis(100)).is_err();
+            release();
+            receiver.recv_timeout(Duration::from_secs(10)).expect("reserved after the release");
+            waited
+        })
+    }
+
+    #[test]
+    fn reservations_within_the_limit_do_not_wait() {
+        let budget = MemoryBudget::new(100);
+        let first = budget.reserve(40);
+        let second = budget.reserve(60);
+        assert_eq!(in_use(&budget), 100);
+        drop(first);
+        assert_eq!(in_use(&budget), 60);
+        drop(second);
+        assert_eq!(in_use(&budget), 0);
+    }
+
+    #[test]
+    fn reservations_over_the_limit_wait_for_a_release() {
+        let budget = MemoryBudget::new(100);
+        let first = budget.reserve(60);
+        assert!(waits_for(&budget, 50, || drop(first)));
+        assert_eq!(in_use(&budget), 0);
+    }
+
+    #[test]
+    fn files_larger_than_the_budget_run_alone() {
+        let budget = MemoryBudget::new(100);
+        let large = budget.reserve(500);
+        assert_eq!(in_use(&budget), 500);
+        assert!(waits_for(&budget, 1, || drop(large)));
+
+        let small = budget.reserve(10);
+        assert!(waits_for(&budget, 500, || drop(small)));
+        assert_eq!(in_use(&budget), 0);
+    }
+}
@@ -0,0 +1,425 @@
+//! Burn-in overlays: lines of text built from templates and drawn into a
+//! corner of the thumbnail, for slates on review thumbnails.
+//!
+//! A burn-in is written as `[corner:]template`, e.g.
+//! `top-right:{filename} {resolution}`. The template is plain text with
+//! placeholders in braces:
+//!
+//! | Placeholder    | Replaced by                                          |
+//! |----------------|------------------------------------------------------|
+//! | `{filename}`   | File name of the source                              |
+//! | `{frame}`      | Frame number at the end of the file name, e.g. `1001` |
+//! | `{resolution}` | Source resolution, e.g. `1920x1080`                  |
+//! | `{width}`      | Source width                                         |
+//! | `{height}`     | Source height                                        |
+//! | `{layer}`      | Name of the layer shown                              |
+//! | `{date}`       | Current date in UTC, e.g. `2024-05-31`               |
+//! | `{attr:NAME}`  | EXR header attribute, e.g. `{attr:renderTime}`       |
+//!
+//! `{{` and `}}` stand for literal braces. Unknown values, such as the file
+//! name of an in-memory source or a missing attribute, are left empty.
+
+use ::exr::meta::attribute::AttributeValue;
+use ::exr::meta::MetaData;
+use image::{Pixel, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ThumbError;
+use crate::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 160]);
+const TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Thumbnail height per font pixel: the 7 pixel font is drawn at 2x from
+/// 256 pixels high, 4x from 512
+const HEIGHT_PER_FONT_PIXEL: u32 = 128;
+
+/// Corner of the thumbnail a burn-in is drawn into
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    const ALL: [Corner; 4] = [Corner::TopLeft, Corner::TopRight, Corner::BottomLeft, Corner::BottomRight];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Corner::TopLeft => "top-left",
+            Corner::TopRight => "top-right",
+            Corner::BottomLeft => "bottom-left",
+            Corner::BottomRight => "bottom-right",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Filename,
+    Frame,
+    Resolution,
+    Width,
+    Height,
+    Layer,
+    Date,
+    Attribute(String),
+}
+
+/// A line of text drawn into a corner of the thumbnail. Several burn-ins in
+/// the same corner are stacked in the order given.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Burnin {
+    corner: Corner,
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl Burnin {
+    /// Parse `template` for the given corner, see the [module](self)
+    /// documentation for the placeholders.
+    pub fn new(corner: Corner, template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| format!("unclosed '{{' in '{}'", template))?;
+                    let name = &rest[..end];
+                    chars = rest[end + 1..].chars();
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(match name {
+                        "filename" => Segment::Filename,
+                        "frame" => Segment::Frame,
+                        "resolution" => Segment::Resolution,
+                        "width" => Segment::Width,
+                        "height" => Segment::Height,
+                        "layer" => Segment::Layer,
+                        "date" => Segment::Date,
+                        _ => match name.strip_prefix("attr:") {
+                            Some(attribute) if !attribute.is_empty() => Segment::Attribute(attribute.to_string()),
+                            _ => {
+                                return Err(format!(
+                                    "unknown placeholder '{{{}}}', expected filename, frame, resolution, width, \
+                                     height, layer, date or attr:NAME",
+                                    name
+                                ))
+                            }
+                        },
+                    });
+                }
+                '}' => return Err(format!("unmatched '}}' in '{}', write '}}}}' for a brace", template)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self {
+            corner,
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    pub fn corner(&self) -> Corner {
+        self.corner
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Whether the template has `{attr:NAME}` placeholders, which need the
+    /// EXR headers to be read.
+    pub fn uses_attributes(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Attribute(_)))
+    }
+
+    fn text(&self, context: &BurninContext) -> String {
+        let mut text = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(literal) => text += literal,
+                Segment::Filename => text += &context.file_name().unwrap_or_default(),
+                Segment::Frame => text += context.frame().unwrap_or_default(),
+                Segment::Resolution => text += &format!("{}x{}", context.width, context.height),
+                Segment::Width => text += &context.width.to_string(),
+                Segment::Height => text += &context.height.to_string(),
+                Segment::Layer => text += context.layer.unwrap_or_default(),
+                Segment::Date => text += &today(),
+                Segment::Attribute(name) => text += context.attributes.get(name).map_or("", String::as_str),
+            }
+        }
+        text
+    }
+}
+
+impl FromStr for Burnin {
+    type Err = String;
+
+    /// Parse `[corner:]template`, in the bottom left corner by default.
+    fn from_str(spec: &str) -> Result<Self, String> {
+        for corner in Corner::ALL {
+            if let Some(template) = spec.strip_prefix(corner.as_str()).and_then(|rest| rest.strip_prefix(':')) {
+                return Burnin::new(corner, template);
+            }
+        }
+        Burnin::new(Corner::default(), spec)
+    }
+}
+
+impl TryFrom<String> for Burnin {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, String> {
+        spec.parse()
+    }
+}
+
+impl From<Burnin> for String {
+    fn from(burnin: Burnin) -> Self {
+        burnin.to_string()
+    }
+}
+
+impl fmt::Display for Burnin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.corner.as_str(), self.template)
+    }
+}
+
+/// Values the placeholders are replaced by
+pub(crate) struct BurninContext<'a> {
+    pub source: Option<&'a Path>,
+    pub width: u32,
+    pub height: u32,
+    pub layer: Option<&'a str>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl BurninContext<'_> {
+    fn file_name(&self) -> Option<String> {
+        Some(self.source?.file_name()?.to_string_lossy().into_owned())
+    }
+
+    /// Digits at the end of the file stem
+    fn frame(&self) -> Option<&str> {
+        let stem = self.source?.file_stem()?.to_str()?;
+        let frame = &stem[stem.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+        (!frame.is_empty()).then_some(frame)
+    }
+}
+
+/// Attributes of the header of `layer`, or of the first header, as text.
+/// The file must start at the current position of `reader`.
+pub(crate) fn header_attributes(reader: impl Read, layer: Option<&str>) -> Result<BTreeMap<String, String>, ThumbError> {
+    let meta = MetaData::read_from_buffered(reader, false).map_err(ThumbError::from_exr_header)?;
+    let header = layer
+        .and_then(|name| {
+            meta.headers
+                .iter()
+                .find(|header| header.own_attributes.layer_name.as_ref().is_some_and(|layer| layer.eq(name)))
+        })
+        .or(meta.headers.first());
+    Ok(header
+        .map(|header| {
+            header
+                .all_named_attributes()
+                .map(|(name, value)| (String::from_utf8_lossy(name).into_owned(), attribute_text(&value)))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn attribute_text(value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::Text(text) => text.to_string(),
+        AttributeValue::TextVector(texts) => texts.iter().map(|text| text.to_string()).collect::<Vec<_>>().join(", "),
+        AttributeValue::F32(value) => value.to_string(),
+        AttributeValue::F64(value) => value.to_string(),
+        AttributeValue::I32(value) => value.to_string(),
+        AttributeValue::IntVec2(vec) => format!("{} {}", vec.x(), vec.y()),
+        AttributeValue::FloatVec2(vec) => format!("{} {}", vec.x(), vec.y()),
+        AttributeValue::Rational((numerator, denominator)) => format!("{}/{}", numerator, denominator),
+        AttributeValue::TimeCode(time) => {
+            format!("{:02}:{:02}:{:02}:{:02}", time.hours, time.minutes, time.seconds, time.frame)
+        }
+        AttributeValue::Compression(compression) => format!("{:?}", compression),
+        AttributeValue::LineOrder(line_order) => format!("{:?}", line_order),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Current date in UTC as `YYYY-MM-DD`
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400;
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Draw the burn-ins into their corners of `image`, on a translucent box
+/// with the font scaled to the height of the image.
+pub(crate) fn draw_burnins(image: &mut RgbaImage, burnins: &[Burnin], context: &BurninContext) {
+    let scale = (image.height() / HEIGHT_PER_FONT_PIXEL).max(1);
+    // Every glyph has a pixel of space to its right and every line one above
+    // and below, which is also the padding of the box
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let line_height = (GLYPH_HEIGHT + 2) * scale;
+    let margin = 2 * scale;
+
+    for corner in Corner::ALL {
+        let lines: Vec<Vec<char>> = burnins
+            .iter()
+            .filter(|burnin| burnin.corner == corner)
+            .map(|burnin| burnin.text(context).chars().collect())
+            .collect();
+        let Some(longest) = lines.iter().map(Vec::len).max().filter(|&longest| longest > 0) else {
+            continue;
+        };
+        let box_width = longest as u32 * advance + scale;
+        let box_height = lines.len() as u32 * line_height;
+        let left = match corner {
+            Corner::TopLeft | Corner::BottomLeft => margin as i64,
+            Corner::TopRight | Corner::BottomRight => image.width() as i64 - (margin + box_width) as i64,
+        };
+        let top = match corner {
+            Corner::TopLeft | Corner::TopRight => margin as i64,
+            Corner::BottomLeft | Corner::BottomRight => image.height() as i64 - (margin + box_height) as i64,
+        };
+
+        for y in top..top + box_height as i64 {
+            for x in left..left + box_width as i64 {
+                if let Some(pixel) = pixel_mut(image, x, y) {
+                    pixel.blend(&BACKGROUND);
+                }
+            }
+        }
+        for (row, line) in lines.iter().enumerate() {
+            let line_top = top + (row as u32 * line_height + scale) as i64;
+            for (index, &c) in line.iter().enumerate() {
+                let glyph_left = left + (index as u32 * advance + scale) as i64;
+                for (column, bits) in glyph(c).into_iter().enumerate() {
+                    for glyph_row in (0..GLYPH_HEIGHT).filter(|glyph_row| bits & (1 << glyph_row) != 0) {
+                        for dy in 0..scale {
+                            for dx in 0..scale {
+                                let x = glyph_left + (column as u32 * scale + dx) as i64;
+                                let y = line_top + (glyph_row * scale + dy) as i64;
+                                if let Some(pixel) = pixel_mut(image, x, y) {
+                                    *pixel = TEXT;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The pixel at `(x, y)`, or `None` outside of the image
+fn pixel_mut(image: &mut RgbaImage, x: i64, y: i64) -> Option<&mut Rgba<u8>> {
+    let (x, y) = (u32::try_from(x).ok()?, u32::try_from(y).ok()?);
+    (x < image.width() && y < image.height()).then(|| image.get_pixel_mut(x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREY: Rgba<u8> = Rgba([100, 100, 100, 255]);
+
+    fn context(source: Option<&Path>) -> BurninContext<'_> {
+        BurninContext {
+            source,
+            width: 64,
+            height: 32,
+            layer: Some("beauty"),
+            attributes: BTreeMap::from([("renderTime".to_string(), "12.5".to_string())]),
+        }
+    }
+
+    #[test]
+    fn specs_parse_and_print_back() {
+        let burnin: Burnin = "top-left:H {{x}} {resolution}".parse().unwrap();
+        assert_eq!(burnin.corner(), Corner::TopLeft);
+        assert_eq!(burnin.template(), "H {{x}} {resolution}");
+        assert_eq!(burnin.to_string().parse::<Burnin>().unwrap(), burnin);
+        assert_eq!("{frame}".parse::<Burnin>().unwrap().corner(), Corner::BottomLeft);
+        assert!("{attr:renderTime}".parse::<Burnin>().unwrap().uses_attributes());
+    }
+
+    #[test]
+    fn malformed_templates_are_errors() {
+        assert!("{nope}".parse::<Burnin>().unwrap_err().starts_with("unknown placeholder '{nope}'"));
+        assert!("{attr:}".parse::<Burnin>().is_err());
+        assert_eq!("{filename".parse::<Burnin>().unwrap_err(), "unclosed '{' in '{filename'");
+        assert!("a}b".parse::<Burnin>().unwrap_err().starts_with("unmatched '}' in 'a}b'"));
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let burnin: Burnin = "{filename} {frame} {resolution} {width}x{height} {layer} {attr:renderTime}|{attr:missing}|{{x}}"
+            .parse()
+            .unwrap();
+        let source = Path::new("renders/shot_0042.exr");
+        assert_eq!(burnin.text(&context(Some(source))), "shot_0042.exr 0042 64x32 64x32 beauty 12.5||{x}");
+        // In-memory sources have no file name or frame
+        assert_eq!(burnin.text(&context(None)), "  64x32 64x32 beauty 12.5||{x}");
+    }
+
+    #[test]
+    fn lines_are_stacked_in_their_corner() {
+        let mut image = RgbaImage::from_pixel(512, 256, GREY);
+        let burnins: Vec<Burnin> = ["top-left:H", "top-left:H", "bottom-right:H"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+        draw_burnins(&mut image, &burnins, &context(None));
+
+        // 256 pixels high draws the font at 2x, 4 pixels from the edge and
+        // with 2 pixels of padding
+        let line_height = (GLYPH_HEIGHT + 2) * 2;
+        assert_eq!(*image.get_pixel(6, 6), TEXT);
+        assert_eq!(*image.get_pixel(6, 6 + line_height), TEXT);
+        let box_width = (GLYPH_WIDTH + 1) * 2 + 2;
+        assert_eq!(*image.get_pixel(512 - 4 - box_width + 2, 256 - 4 - line_height + 2), TEXT);
+        // The box darkens the padding, the margin and the rest are untouched
+        assert!(image.get_pixel(5, 5)[0] < GREY[0]);
+        assert_eq!(*image.get_pixel(3, 3), GREY);
+        assert_eq!(*image.get_pixel(511, 255), GREY);
+        assert_eq!(*image.get_pixel(256, 128), GREY);
+    }
+}
@@ -0,0 +1,54 @@
+//! Ctrl-C handling for batch and watch runs.
+//!
+//! The first Ctrl-C stops starting new files and lets the files in progress
+//! finish, so the report is still written and marks the run as cancelled.
+//! After the grace period, or on a second Ctrl-C, the files in progress are
+//! abandoned between blocks and counted as remaining too. A third Ctrl-C
+//! exits at once, removing the temporary files of the writes in progress.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{output, Exit};
+
+/// Set by the first Ctrl-C
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the run was interrupted, so no new files are started.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Install the Ctrl-C handler and return the flag that abandons the files in
+/// progress, for [`exr_thumbnailer::ThumbnailRequest::cancel`].
+pub fn install(grace_period: Duration) -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let abandon = Arc::new(AtomicBool::new(false));
+    let handler_abandon = abandon.clone();
+    let mut interrupts = 0;
+    ctrlc::set_handler(move || {
+        interrupts += 1;
+        match interrupts {
+            1 => {
+                REQUESTED.store(true, Ordering::SeqCst);
+                eprintln!(
+                    "\nInterrupted: finishing the files in progress for up to {}s, press Ctrl-C again to abandon them",
+                    grace_period.as_secs()
+                );
+                let abandon = handler_abandon.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(grace_period);
+                    if !abandon.swap(true, Ordering::SeqCst) {
+                        eprintln!("\nGrace period over: abandoning the files in progress");
+                    }
+                });
+            }
+            2 => {
+                handler_abandon.store(true, Ordering::SeqCst);
+                eprintln!("\nAbandoning the files in progress, press Ctrl-C again to exit immediately");
+            }
+            _ => output::exit_removing_temp_files(Exit::Cancelled as i32),
+        }
+    })?;
+    Ok(abandon)
+}
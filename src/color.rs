@@ -0,0 +1,72 @@
+//! Conversion of linear HDR pixels to 8 bit display values.
+
+use serde::{Deserialize, Serialize};
+
+/// Tone mapping applied to linear values before gamma correction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMap {
+    /// Clip values above 1.0
+    #[default]
+    None,
+    /// Reinhard `x / (1 + x)`, keeps detail in highlights
+    Reinhard,
+}
+
+impl ToneMap {
+    /// Name used on the command line and in configuration files
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ToneMap::None => "none",
+            ToneMap::Reinhard => "reinhard",
+        }
+    }
+}
+
+/// Color processing configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorConfig {
+    pub tone_map: ToneMap,
+    /// Display gamma, 2.2 for sRGB-like output
+    pub gamma: f32,
+}
+
+impl ColorConfig {
+    pub fn new(tone_map: ToneMap, gamma: f32) -> Self {
+        Self { tone_map, gamma }
+    }
+
+    /// Tone map, gamma correct and quantise linear RGBA pixels to 8 bit.
+    pub fn to_rgba8(&self, pixels: &[[f32; 4]]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(pixels.len() * 4);
+        for &[r, g, b, a] in pixels {
+            // Process pixel with the color config
+            let (r, g, b) = match self.tone_map {
+                ToneMap::None => (r, g, b),
+                ToneMap::Reinhard => {
+                    // Reinhard tone mapping dla HDR
+                    let tone_map = |x: f32| x / (1.0 + x);
+                    (tone_map(r), tone_map(g), tone_map(b))
+                }
+            };
+
+            // Gamma correction
+            let gamma_correct = |x: f32| x.powf(1.0 / self.gamma);
+
+            out.extend_from_slice(&[
+                (gamma_correct(r.clamp(0.0, 1.0)) * 255.0) as u8,
+                (gamma_correct(g.clamp(0.0, 1.0)) * 255.0) as u8,
+                (gamma_correct(b.clamp(0.0, 1.0)) * 255.0) as u8,
+                (a.clamp(0.0, 1.0) * 255.0) as u8,
+            ]);
+        }
+        out
+    }
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self::new(ToneMap::None, 2.2)
+    }
+}
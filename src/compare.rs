@@ -0,0 +1,353 @@
+//! Comparison of two EXR files in linear light, for render regression tests.
+//!
+//! The reference is decoded at full resolution and the candidate is compared
+//! with it while it is decoded, so only one full size image is held in
+//! memory. The difference image is reduced like a thumbnail.
+
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use crate::decode::{decode_layer, decoded, DecodedExr, Deadline, PixelSink};
+use crate::error::ThumbError;
+use crate::qc::DEFAULT_FIREFLY_THRESHOLD;
+use crate::resample::{lanczos_resize, BoxReducer};
+use crate::thumbnail::{ThumbnailRequest, ThumbnailSize};
+use crate::timing::StageTimings;
+
+/// Peak signal of the PSNR, display white in linear light
+const PSNR_PEAK: f64 = 1.0;
+
+/// Colours of the heatmap from no difference to the largest one
+const HEATMAP: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 255.0],
+    [255.0, 0.0, 0.0],
+    [255.0, 255.0, 0.0],
+    [255.0, 255.0, 255.0],
+];
+
+/// Kind of difference image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffImage {
+    /// The reference and the candidate thumbnails next to each other
+    SideBySide,
+    /// The largest absolute RGB difference per pixel, from black through
+    /// blue, red and yellow to white
+    #[default]
+    Heatmap,
+}
+
+impl DiffImage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiffImage::SideBySide => "side-by-side",
+            DiffImage::Heatmap => "heatmap",
+        }
+    }
+}
+
+/// Errors of one channel in linear values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ChannelDiff {
+    /// Root mean square error
+    pub rmse: f64,
+    /// Peak signal to noise ratio in dB for a peak of 1.0, infinite for
+    /// identical channels
+    pub psnr: f64,
+    /// Largest absolute difference
+    pub max_abs: f32,
+}
+
+/// Result of comparing two images
+#[derive(Clone, Debug)]
+pub struct ImageDiff {
+    pub width: u32,
+    pub height: u32,
+    /// Name of the layer compared, if it has one
+    pub layer: Option<String>,
+    /// R, G, B and A
+    pub channels: [ChannelDiff; 4],
+    /// The difference image at the requested thumbnail size
+    pub image: RgbaImage,
+    /// Absolute difference drawn as white in a heatmap
+    pub heatmap_max: f32,
+}
+
+impl ImageDiff {
+    /// Largest RMSE of all channels
+    pub fn rmse(&self) -> f64 {
+        self.channels.iter().map(|c| c.rmse).fold(0.0, f64::max)
+    }
+
+    /// Smallest PSNR of all channels
+    pub fn psnr(&self) -> f64 {
+        self.channels.iter().map(|c| c.psnr).fold(f64::INFINITY, f64::min)
+    }
+
+    /// Largest absolute difference of all channels
+    pub fn max_abs(&self) -> f32 {
+        self.channels.iter().map(|c| c.max_abs).fold(0.0, f32::max)
+    }
+}
+
+/// Settings for comparing a candidate EXR file with a reference.
+#[derive(Clone, Debug)]
+pub struct DiffRequest {
+    reference: PathBuf,
+    candidate: PathBuf,
+    settings: ThumbnailRequest,
+    image: DiffImage,
+}
+
+impl DiffRequest {
+    pub fn new(reference: impl Into<PathBuf>, candidate: impl Into<PathBuf>) -> Self {
+        Self {
+            reference: reference.into(),
+            candidate: candidate.into(),
+            settings: ThumbnailRequest::default(),
+            image: DiffImage::Heatmap,
+        }
+    }
+
+    /// Size, layer, colour processing, filter and decode threads of the
+    /// comparison. The source and output format are ignored.
+    pub fn settings(mut self, settings: ThumbnailRequest) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn image(mut self, image: DiffImage) -> Self {
+        self.image = image;
+        self
+    }
+
+    pub fn reference(&self) -> &Path {
+        &self.reference
+    }
+
+    pub fn candidate(&self) -> &Path {
+        &self.candidate
+    }
+
+    /// Decode both files and compare them.
+    pub fn diff(&self) -> Result<ImageDiff, ThumbError> {
+        let open = |path: &Path| File::open(path).map(BufReader::new);
+        self.diff_readers(open(&self.reference)?, open(&self.candidate)?)
+    }
+
+    fn diff_readers(&self, reference: impl Read + Seek, candidate: impl Read + Seek) -> Result<ImageDiff, ThumbError> {
+        let settings = &self.settings;
+        let layer = settings.layer.as_deref();
+        // Each file gets the timeout of the settings
+        let deadline = || Deadline::new(settings.timeout, settings.cancel.clone());
+
+        let (reference, layer_name) =
+            decode_layer(reference, layer, &settings.decode_pool, deadline(), FullImage::new)?;
+        let (sink, _) = decode_layer(candidate, layer, &settings.decode_pool, deadline(), |width, height| {
+            DiffSink::new(&reference, width, height, settings.size)
+        })?;
+        let (width, height) = (reference.width, reference.height);
+        if let Some((candidate_width, candidate_height)) = sink.mismatch {
+            return Err(ThumbError::SizeMismatch(format!(
+                "{}x{} reference, {}x{} candidate",
+                width, height, candidate_width, candidate_height
+            )));
+        }
+
+        let count = (width as f64 * height as f64).max(1.0);
+        let channels = std::array::from_fn(|c| {
+            let rmse = (sink.squares[c] / count).sqrt();
+            ChannelDiff {
+                rmse,
+                psnr: 20.0 * (PSNR_PEAK / rmse).log10(),
+                max_abs: sink.max_abs[c],
+            }
+        });
+
+        let mut timings = StageTimings::default();
+        let (image, heatmap_max) = match self.image {
+            DiffImage::Heatmap => heatmap(decoded(sink.difference, None), settings.size),
+            DiffImage::SideBySide => {
+                let mut reduced = BoxReducer::for_thumbnail(width, height, settings.size, DEFAULT_FIREFLY_THRESHOLD);
+                for (index, pixel) in reference.pixels.iter().enumerate() {
+                    reduced.add(index % width as usize, index / width as usize, *pixel);
+                }
+                let left = settings.render(decoded(reduced, None), &mut timings)?;
+                let right = settings.render(decoded(sink.candidate, None), &mut timings)?;
+                let mut image = RgbaImage::new(left.width() + right.width(), left.height());
+                imageops::replace(&mut image, &left, 0, 0);
+                imageops::replace(&mut image, &right, left.width() as i64, 0);
+                (image, 0.0)
+            }
+        };
+
+        Ok(ImageDiff {
+            width,
+            height,
+            layer: layer_name,
+            channels,
+            image,
+            heatmap_max,
+        })
+    }
+}
+
+/// Full resolution pixels of the reference
+struct FullImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl FullImage {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width as usize * height as usize],
+        }
+    }
+}
+
+impl PixelSink for FullImage {
+    fn add(&mut self, x: usize, y: usize, pixel: [f32; 4]) {
+        self.pixels[y * self.width as usize + x] = pixel;
+    }
+}
+
+/// Compares the candidate pixels with the reference as they are decoded
+struct DiffSink<'r> {
+    reference: &'r FullImage,
+    /// Size of the candidate if it differs from the reference
+    mismatch: Option<(u32, u32)>,
+    squares: [f64; 4],
+    max_abs: [f32; 4],
+    /// Reduced candidate for the side by side image
+    candidate: BoxReducer,
+    /// Reduced absolute differences for the heatmap
+    difference: BoxReducer,
+}
+
+impl<'r> DiffSink<'r> {
+    fn new(reference: &'r FullImage, width: u32, height: u32, size: ThumbnailSize) -> Self {
+        let mismatch = ((width, height) != (reference.width, reference.height)).then_some((width, height));
+        let reducer = || BoxReducer::for_thumbnail(width, height, size, DEFAULT_FIREFLY_THRESHOLD);
+        Self {
+            reference,
+            mismatch,
+            squares: [0.0; 4],
+            max_abs: [0.0; 4],
+            candidate: reducer(),
+            difference: reducer(),
+        }
+    }
+}
+
+impl PixelSink for DiffSink<'_> {
+    fn add(&mut self, x: usize, y: usize, pixel: [f32; 4]) {
+        if self.mismatch.is_some() {
+            return;
+        }
+        // Invalid values count as 0, as in the thumbnails
+        let finite = |value: f32| if value.is_finite() { value } else { 0.0 };
+        let reference = self.reference.pixels[y * self.reference.width as usize + x];
+        let mut difference = [0.0; 4];
+        for c in 0..4 {
+            let abs = (finite(pixel[c]) - finite(reference[c])).abs();
+            self.squares[c] += abs as f64 * abs as f64;
+            self.max_abs[c] = self.max_abs[c].max(abs);
+            difference[c] = abs;
+        }
+        self.candidate.add(x, y, pixel);
+        self.difference.add(x, y, difference);
+    }
+}
+
+/// Colour the largest RGB difference of each pixel, scaled to the largest
+/// difference in the image, and return the image with that difference.
+fn heatmap(difference: DecodedExr, size: ThumbnailSize) -> (RgbaImage, f32) {
+    let (width, height) = size.dimensions(difference.source_width, difference.source_height);
+    let largest: Vec<[f32; 4]> = difference
+        .pixels
+        .iter()
+        .map(|pixel| {
+            let value = pixel[0].max(pixel[1]).max(pixel[2]);
+            [value, value, value, 1.0]
+        })
+        .collect();
+    let resized = lanczos_resize(largest, difference.width, difference.height, width, height);
+    let max = resized.iter().map(|pixel| pixel[0]).fold(0.0, f32::max);
+    let image = RgbaImage::from_fn(width, height, |x, y| {
+        let value = resized[(y * width + x) as usize][0];
+        let position = if max > 0.0 { (value / max).clamp(0.0, 1.0) } else { 0.0 } * (HEATMAP.len() - 1) as f32;
+        let index = (position as usize).min(HEATMAP.len() - 2);
+        let t = position - index as f32;
+        let [from, to] = [HEATMAP[index], HEATMAP[index + 1]];
+        let channel = |c: usize| (from[c] + (to[c] - from[c]) * t).round() as u8;
+        Rgba([channel(0), channel(1), channel(2), 255])
+    });
+    (image, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::exr::prelude as exr;
+    use exr::traits::*;
+    use std::io::Cursor;
+
+    /// 8 pixels wide grey image with `red` in the top left pixel
+    fn exr_bytes(height: usize, red: f32) -> Vec<u8> {
+        let channels = exr::SpecificChannels::rgba(|position: exr::Vec2<usize>| {
+            let r = if position == exr::Vec2(0, 0) { red } else { 0.5 };
+            (r, 0.5_f32, 0.5_f32, 1.0_f32)
+        });
+        let mut bytes = Vec::new();
+        exr::Image::from_channels((8, height), channels)
+            .write()
+            .to_buffered(Cursor::new(&mut bytes))
+            .unwrap();
+        bytes
+    }
+
+    fn diff(reference: Vec<u8>, candidate: Vec<u8>) -> Result<ImageDiff, ThumbError> {
+        let settings = ThumbnailRequest::default().size(ThumbnailSize::Height(4));
+        DiffRequest::new("reference.exr", "candidate.exr")
+            .settings(settings)
+            .diff_readers(Cursor::new(reference), Cursor::new(candidate))
+    }
+
+    #[test]
+    fn a_known_difference_is_measured() {
+        let diff = diff(exr_bytes(8, 0.5), exr_bytes(8, 0.75)).unwrap();
+        assert_eq!((diff.width, diff.height), (8, 8));
+        // One of 64 pixels differs by 0.25 in red
+        let red = diff.channels[0];
+        assert_eq!(red.rmse, (0.25_f64 * 0.25 / 64.0).sqrt());
+        assert!((red.psnr - 30.103).abs() < 0.001, "{}", red.psnr);
+        assert_eq!(red.max_abs, 0.25);
+        assert_eq!(diff.channels[1], ChannelDiff { rmse: 0.0, psnr: f64::INFINITY, max_abs: 0.0 });
+        assert_eq!((diff.rmse(), diff.psnr(), diff.max_abs()), (red.rmse, red.psnr, red.max_abs));
+        assert!(diff.heatmap_max > 0.0);
+    }
+
+    #[test]
+    fn identical_images_have_an_infinite_psnr() {
+        let diff = diff(exr_bytes(8, 0.5), exr_bytes(8, 0.5)).unwrap();
+        assert_eq!(diff.rmse(), 0.0);
+        assert_eq!(diff.max_abs(), 0.0);
+        assert_eq!(diff.psnr(), f64::INFINITY);
+        assert!(diff.channels.iter().all(|channel| channel.psnr == f64::INFINITY));
+    }
+
+    #[test]
+    fn images_of_different_sizes_are_an_error() {
+        let error = diff(exr_bytes(8, 0.5), exr_bytes(4, 0.5)).unwrap_err();
+        assert_eq!(error.to_string(), "size mismatch: 8x8 reference, 8x4 candidate");
+    }
+}
@@ -0,0 +1,351 @@
+//! Settings from a TOML configuration file with named presets.
+//!
+//! The file mirrors the command line options, using the long option names:
+//!
+//! ```toml
+//! source-folder = "data"
+//! dest-folder = "thumb"
+//! tone-map = "reinhard"
+//!
+//! [preset.review]
+//! height = 200
+//! filter = "gaussian"
+//! ```
+//!
+//! Settings are layered: built-in defaults, then the top level of the file,
+//! then the selected preset, then `EXR_THUMBNAILER_*` environment variables
+//! and finally the command line.
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use exr_thumbnailer::burnin::Burnin;
+use exr_thumbnailer::{Filter, OutputFormat, ToneMap};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::output::Overwrite;
+use crate::report::{HistogramOutput, ReportFormat};
+use crate::Args;
+
+/// Options that can be set in a configuration file or preset
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    source_folder: Option<PathBuf>,
+    dest_folder: Option<PathBuf>,
+    height: Option<u32>,
+    info: Option<String>,
+    report_format: Option<ReportFormat>,
+    tone_map: Option<ToneMap>,
+    linear_tone_mapping: Option<bool>,
+    #[serde(serialize_with = "serialize_shortest")]
+    gamma: Option<f32>,
+    filter: Option<Filter>,
+    flag_invalid: Option<bool>,
+    qc_border: Option<bool>,
+    #[serde(serialize_with = "serialize_shortest")]
+    firefly_threshold: Option<f32>,
+    format: Option<OutputFormat>,
+    layer: Option<String>,
+    slowest: Option<usize>,
+    memory_limit: Option<u64>,
+    timeout_per_file: Option<u64>,
+    grace_period: Option<u64>,
+    jobs: Option<usize>,
+    decode_threads: Option<usize>,
+    nice: Option<bool>,
+    fail_on_invalid: Option<bool>,
+    #[serde(serialize_with = "serialize_shortest")]
+    jump_threshold: Option<f32>,
+    stats: Option<bool>,
+    histogram: Option<HistogramOutput>,
+    burnin: Option<Vec<Burnin>>,
+    overwrite: Option<Overwrite>,
+    no_provenance: Option<bool>,
+    incremental: Option<bool>,
+    watch: Option<bool>,
+    debounce_secs: Option<u64>,
+    summary_interval_secs: Option<u64>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+}
+
+/// A parsed configuration file
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    settings: Settings,
+    presets: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("invalid configuration file {}: {}", path.display(), e.trim_end()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let presets: BTreeMap<String, Settings> = match table.remove("preset") {
+            Some(presets) => presets.try_into().map_err(|e: toml::de::Error| e.to_string())?,
+            None => BTreeMap::new(),
+        };
+        let settings: Settings = toml::Value::Table(table).try_into().map_err(|e| e.to_string())?;
+
+        settings.validate()?;
+        for (name, preset) in &presets {
+            preset.validate().map_err(|e| format!("preset '{}': {}", name, e))?;
+        }
+        Ok(Self { settings, presets })
+    }
+}
+
+/// Fill the arguments that were not given on the command line or in the
+/// environment from the configuration file and preset selected in `args`.
+pub fn apply(args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+    let config = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None if args.preset.is_some() => return Err("--preset requires a --config file".to_string()),
+        None => return Ok(()),
+    };
+    let preset = match &args.preset {
+        Some(name) => Some(config.presets.get(name).ok_or_else(|| {
+            let known: Vec<&str> = config.presets.keys().map(String::as_str).collect();
+            format!("no preset '{}' in the configuration file (available: {})", name, known.join(", "))
+        })?),
+        None => None,
+    };
+
+    config.settings.apply(args, matches);
+    if let Some(preset) = preset {
+        preset.apply(args, matches);
+    }
+    Ok(())
+}
+
+impl Settings {
+    /// The settings `args` resolve to, for `--print-config`.
+    pub fn effective(args: &Args) -> Self {
+        let render = &args.render;
+        Self {
+            source_folder: args.source_folder.clone(),
+            dest_folder: args.dest_folder.clone(),
+            height: args.height,
+            info: args.info.clone(),
+            report_format: Some(args.report_format),
+            tone_map: Some(render.tone_map()),
+            linear_tone_mapping: None,
+            gamma: Some(render.gamma),
+            filter: Some(render.filter),
+            flag_invalid: Some(render.flag_invalid),
+            qc_border: Some(render.qc_border),
+            firefly_threshold: Some(render.firefly_threshold),
+            format: Some(args.format),
+            layer: args.layer.clone(),
+            slowest: Some(args.slowest),
+            memory_limit: args.memory_limit,
+            timeout_per_file: args.timeout_per_file,
+            grace_period: Some(args.grace_period),
+            jobs: args.jobs,
+            decode_threads: args.decode_threads,
+            nice: Some(args.nice),
+            fail_on_invalid: Some(args.fail_on_invalid),
+            jump_threshold: Some(args.jump_threshold),
+            stats: Some(args.stats),
+            histogram: Some(args.histogram),
+            burnin: Some(args.burnin.clone()),
+            overwrite: Some(args.overwrite),
+            no_provenance: Some(args.no_provenance),
+            incremental: Some(args.incremental),
+            watch: Some(args.watch),
+            debounce_secs: Some(args.debounce_secs),
+            summary_interval_secs: Some(args.summary_interval_secs),
+            quiet: Some(args.quiet),
+            verbose: Some(args.verbose),
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("settings serialize to TOML")
+    }
+
+    /// Apply the range checks the command line does for these values.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(height) = self.height {
+            crate::check_height(height)?;
+        }
+        if let Some(gamma) = self.gamma {
+            crate::check_gamma(gamma)?;
+        }
+        for (name, value) in [("firefly-threshold", self.firefly_threshold), ("jump-threshold", self.jump_threshold)] {
+            if let Some(threshold) = value {
+                crate::check_threshold(threshold).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        for (name, value) in [
+            ("memory-limit", self.memory_limit),
+            ("jobs", self.jobs.map(|jobs| jobs as u64)),
+            ("decode-threads", self.decode_threads.map(|threads| threads as u64)),
+        ] {
+            if value == Some(0) {
+                return Err(format!("{} must be at least 1", name));
+            }
+        }
+        if self.summary_interval_secs == Some(0) {
+            return Err("summary-interval-secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        let explicit = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        fill(&mut args.source_folder, self.source_folder.clone().map(Some), explicit("source_folder"));
+        fill(&mut args.dest_folder, self.dest_folder.clone().map(Some), explicit("dest_folder"));
+        fill(&mut args.height, self.height.map(Some), explicit("height"));
+        fill(&mut args.info, self.info.clone().map(Some), explicit("info"));
+        fill(&mut args.report_format, self.report_format, explicit("report_format"));
+        // --linear-tone-mapping is shorthand for --tone-map, so either one overrides both
+        let tone_map_explicit = explicit("tone_map") || explicit("linear_tone_mapping");
+        fill(&mut args.render.tone_map, self.tone_map, tone_map_explicit);
+        fill(&mut args.render.linear_tone_mapping, self.linear_tone_mapping, tone_map_explicit);
+        fill(&mut args.render.gamma, self.gamma, explicit("gamma"));
+        fill(&mut args.render.filter, self.filter, explicit("filter"));
+        fill(&mut args.render.flag_invalid, self.flag_invalid, explicit("flag_invalid"));
+        fill(&mut args.render.qc_border, self.qc_border, explicit("qc_border"));
+        fill(&mut args.render.firefly_threshold, self.firefly_threshold, explicit("firefly_threshold"));
+        fill(&mut args.format, self.format, explicit("format"));
+        fill(&mut args.layer, self.layer.clone().map(Some), explicit("layer"));
+        fill(&mut args.slowest, self.slowest, explicit("slowest"));
+        fill(&mut args.memory_limit, self.memory_limit.map(Some), explicit("memory_limit"));
+        fill(&mut args.timeout_per_file, self.timeout_per_file.map(Some), explicit("timeout_per_file"));
+        fill(&mut args.grace_period, self.grace_period, explicit("grace_period"));
+        fill(&mut args.jobs, self.jobs.map(Some), explicit("jobs"));
+        fill(&mut args.decode_threads, self.decode_threads.map(Some), explicit("decode_threads"));
+        fill(&mut args.nice, self.nice, explicit("nice"));
+        fill(&mut args.fail_on_invalid, self.fail_on_invalid, explicit("fail_on_invalid"));
+        fill(&mut args.jump_threshold, self.jump_threshold, explicit("jump_threshold"));
+        fill(&mut args.stats, self.stats, explicit("stats"));
+        fill(&mut args.histogram, self.histogram, explicit("histogram"));
+        fill(&mut args.burnin, self.burnin.clone(), explicit("burnin"));
+        fill(&mut args.overwrite, self.overwrite, explicit("overwrite"));
+        fill(&mut args.no_provenance, self.no_provenance, explicit("no_provenance"));
+        fill(&mut args.incremental, self.incremental, explicit("incremental"));
+        fill(&mut args.watch, self.watch, explicit("watch"));
+        fill(&mut args.debounce_secs, self.debounce_secs, explicit("debounce_secs"));
+        fill(&mut args.summary_interval_secs, self.summary_interval_secs, explicit("summary_interval_secs"));
+
+        // -q and -v exclude each other, so either one given explicitly overrides both
+        let verbosity_explicit = explicit("quiet") || explicit("verbose");
+        fill(&mut args.quiet, self.quiet, verbosity_explicit);
+        fill(&mut args.verbose, self.verbose, verbosity_explicit);
+    }
+}
+
+/// Write an `f32` as the shortest decimal that reads back to it, `2.2`
+/// rather than `2.200000047683716`.
+fn serialize_shortest<S: Serializer>(value: &Option<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+    value
+        .map(|value| value.to_string().parse::<f64>().unwrap_or(value as f64))
+        .serialize(serializer)
+}
+
+fn fill<T>(target: &mut T, value: Option<T>, explicit: bool) {
+    if let (false, Some(value)) = (explicit, value) {
+        *target = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    const CONFIG: &str = r#"
+source-folder = "data"
+height = 100
+gamma = 1.8
+tone-map = "reinhard"
+
+[preset.review]
+height = 200
+filter = "gaussian"
+"#;
+
+    /// Parse `cli` with `config` written to a temporary configuration file.
+    fn parse(name: &str, config: &str, cli: &[&str]) -> Result<Args, String> {
+        let path = std::env::temp_dir().join(format!("exr_thumbnailer_config_{}_{}.toml", name, std::process::id()));
+        fs::write(&path, config).unwrap();
+        let mut command_line = vec!["exr_thumbnailer", "--config", path.to_str().unwrap()];
+        command_line.extend(cli);
+        let matches = Args::command().try_get_matches_from(command_line).map_err(|e| e.to_string())?;
+        let mut args = Args::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+        let result = apply(&mut args, &matches);
+        fs::remove_file(&path).unwrap();
+        result.map(|()| args)
+    }
+
+    #[test]
+    fn defaults_apply_where_nothing_is_set() {
+        let args = parse("defaults", "", &[]).unwrap();
+        assert_eq!(args.source_folder, None);
+        assert_eq!(args.height, None);
+        assert_eq!(args.render.gamma, 2.2);
+        assert_eq!(args.render.tone_map(), ToneMap::None);
+        assert_eq!(args.render.filter, Filter::Lanczos3);
+    }
+
+    #[test]
+    fn the_file_overrides_the_defaults() {
+        let args = parse("file", CONFIG, &[]).unwrap();
+        assert_eq!(args.source_folder, Some(PathBuf::from("data")));
+        assert_eq!(args.height, Some(100));
+        assert_eq!(args.render.gamma, 1.8);
+        assert_eq!(args.render.tone_map(), ToneMap::Reinhard);
+        assert_eq!(args.render.filter, Filter::Lanczos3);
+    }
+
+    #[test]
+    fn the_preset_overrides_the_file() {
+        let args = parse("preset", CONFIG, &["--preset", "review"]).unwrap();
+        assert_eq!(args.height, Some(200));
+        assert_eq!(args.render.filter, Filter::Gaussian);
+        assert_eq!(args.render.gamma, 1.8);
+        assert_eq!(args.source_folder, Some(PathBuf::from("data")));
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_preset() {
+        let args = parse("cli", CONFIG, &["--preset", "review", "--height", "300", "--filter", "nearest"]).unwrap();
+        assert_eq!(args.height, Some(300));
+        assert_eq!(args.render.filter, Filter::Nearest);
+        assert_eq!(args.render.gamma, 1.8);
+    }
+
+    #[test]
+    fn command_line_values_equal_to_the_default_still_override() {
+        let args = parse("cli_default", CONFIG, &["--gamma", "2.2", "--tone-map", "none"]).unwrap();
+        assert_eq!(args.render.gamma, 2.2);
+        assert_eq!(args.render.tone_map(), ToneMap::None);
+    }
+
+    #[test]
+    fn an_unknown_preset_is_an_error() {
+        let error = parse("unknown", CONFIG, &["--preset", "web"]).unwrap_err();
+        assert_eq!(error, "no preset 'web' in the configuration file (available: review)");
+    }
+
+    #[test]
+    fn presets_are_validated() {
+        let error = ConfigFile::parse("[preset.tiny]\nheight = 0\n").unwrap_err();
+        assert!(error.starts_with("preset 'tiny': "), "{}", error);
+        let error = ConfigFile::parse("[preset.typo]\nhieght = 200\n").unwrap_err();
+        assert!(error.contains("unknown field `hieght`"), "{}", error);
+    }
+}
@@ -0,0 +1,490 @@
+//! Decoding of EXR files into linear RGBA pixels.
+
+use ::exr::block::chunk::TileCoordinates;
+use ::exr::block::reader::ChunksReader;
+use ::exr::block::{BlockIndex, UncompressedBlock};
+use ::exr::compression::Compression;
+use ::exr::error::UnitResult;
+use ::exr::image::read::image::{LayersReader, ReadLayers};
+use ::exr::image::read::layers::{ChannelsReader, ReadChannels};
+use ::exr::image::{Blocks, Encoding};
+use ::exr::meta::header::Header;
+use ::exr::meta::BlockDescription;
+use exr::prelude as exr;
+use exr::traits::*;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::error::ThumbError;
+use crate::invalid::InvalidValues;
+use crate::qc::FrameStats;
+use crate::resample::BoxReducer;
+use crate::stats::PixelStats;
+use crate::thumbnail::ThumbnailSize;
+
+/// Linear RGBA pixels of a decoded EXR layer, reduced for the thumbnail
+pub(crate) struct DecodedExr {
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Size of `pixels`, between the source and twice the thumbnail size
+    pub width: u32,
+    pub height: u32,
+    pub layer: Option<String>,
+    /// Invalid values are replaced by 0
+    pub pixels: Vec<[f32; 4]>,
+    pub invalid: InvalidValues,
+    /// Invalid value flags per pixel, empty if there are none
+    pub invalid_flags: Vec<u8>,
+    pub stats: FrameStats,
+    pub pixel_stats: Option<PixelStats>,
+}
+
+/// How often a deadline is checked while waiting for a decompressed block
+const DEADLINE_POLL: Duration = Duration::from_millis(100);
+
+/// When decoding stops: at a point in time with [`ThumbError::Timeout`], or
+/// once a flag is set with [`ThumbError::Cancelled`]
+#[derive(Clone, Debug)]
+pub(crate) struct Deadline {
+    at: Option<(Instant, Duration)>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Deadline {
+    /// Stop after `timeout` from now or when `cancel` is set, `None` if
+    /// neither is given.
+    pub fn new(timeout: Option<Duration>, cancel: Option<Arc<AtomicBool>>) -> Option<Self> {
+        if timeout.is_none() && cancel.is_none() {
+            return None;
+        }
+        Some(Self {
+            at: timeout.map(|timeout| (Instant::now() + timeout, timeout)),
+            cancel,
+        })
+    }
+
+    fn check(deadline: Option<&Deadline>) -> Result<(), ThumbError> {
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+        if deadline.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(ThumbError::Cancelled);
+        }
+        match deadline.at {
+            Some((at, timeout)) if Instant::now() >= at => Err(ThumbError::Timeout(format!(
+                "decoding took longer than {}s",
+                timeout.as_secs_f64()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Threads decompressing the blocks of files, started on first use and
+/// shared by all clones, so a batch or server decoding many files with one
+/// request does not start threads for every file
+#[derive(Clone, Debug)]
+pub(crate) struct DecodePool {
+    /// Threads per file, 0 uses one per CPU and 1 decompresses in the
+    /// calling thread
+    threads: usize,
+    /// Files decoded at the same time, whose threads are pooled
+    files: usize,
+    pool: Arc<OnceLock<Option<rayon_core::ThreadPool>>>,
+}
+
+impl DecodePool {
+    pub fn new(threads: usize, files: usize) -> Self {
+        Self {
+            threads,
+            files: files.max(1),
+            pool: Arc::default(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Blocks of a file decompressed at the same time.
+    pub fn blocks_in_flight(&self) -> usize {
+        match self.threads {
+            1 => 1,
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()) + 2,
+            threads => threads + 2,
+        }
+    }
+
+    /// The threads, `None` if blocks are decompressed in the calling thread.
+    fn get(&self) -> Option<&rayon_core::ThreadPool> {
+        if self.threads == 1 {
+            return None;
+        }
+        let pool = self.pool.get_or_init(|| {
+            rayon_core::ThreadPoolBuilder::new()
+                // 0 is one thread per CPU for all files together
+                .num_threads(self.threads * self.files)
+                .thread_name(|index| format!("EXR decode thread #{}", index))
+                .build()
+                .ok()
+        });
+        pool.as_ref()
+    }
+}
+
+impl Default for DecodePool {
+    fn default() -> Self {
+        Self::new(0, 1)
+    }
+}
+
+/// Destination of the pixels of a decoded layer
+pub(crate) trait PixelSink {
+    fn add(&mut self, x: usize, y: usize, pixel: [f32; 4]);
+}
+
+impl PixelSink for BoxReducer {
+    fn add(&mut self, x: usize, y: usize, pixel: [f32; 4]) {
+        BoxReducer::add(self, x, y, pixel);
+    }
+}
+
+/// Decode an EXR file into linear f32 pixels. The file must start at the
+/// beginning of `reader`, which should be buffered.
+///
+/// The blocks are decoded one at a time and box averaged on the fly into an
+/// image at least twice the thumbnail `size`, so memory use does not grow
+/// with the resolution of the source.
+///
+/// Pixels with a luminance above `firefly_threshold` are candidates for the
+/// firefly count of the frame statistics. With `pixel_stats` the channel
+/// statistics and luminance histogram are gathered as well.
+///
+/// The blocks are decompressed by the threads of `pool`. Decoding stops
+/// between blocks once the `deadline` has passed or been cancelled.
+pub(crate) fn decode_exr(
+    reader: impl Read + Seek,
+    layer: Option<&str>,
+    size: ThumbnailSize,
+    firefly_threshold: f32,
+    pixel_stats: bool,
+    pool: &DecodePool,
+    deadline: Option<Deadline>,
+) -> Result<DecodedExr, ThumbError> {
+    let (reducer, layer) = decode_layer(reader, layer, pool, deadline, |width, height| {
+        let reducer = BoxReducer::for_thumbnail(width, height, size, firefly_threshold);
+        if pixel_stats {
+            reducer.with_stats()
+        } else {
+            reducer
+        }
+    })?;
+    Ok(decoded(reducer, layer))
+}
+
+/// Decode a layer of an EXR file into the sink `create` makes for its width
+/// and height, and return the sink with the name of the layer.
+///
+/// Without a `layer` the first RGBA layer is used. A named layer is either a
+/// part of a multi-part file with that name or a set of `<layer>.R`,
+/// `<layer>.G`, `<layer>.B` (and optionally `<layer>.A`) channels.
+pub(crate) fn decode_layer<S: PixelSink>(
+    mut reader: impl Read + Seek,
+    layer: Option<&str>,
+    pool: &DecodePool,
+    deadline: Option<Deadline>,
+    create: impl Fn(u32, u32) -> S,
+) -> Result<(S, Option<String>), ThumbError> {
+    // The headers are read separately first to tell damaged headers from
+    // damaged pixel data.
+    let meta = exr::MetaData::read_from_buffered(&mut reader, false).map_err(ThumbError::from_exr_header)?;
+    reader.seek(SeekFrom::Start(0))?;
+    let read = exr::read().no_deep_data().largest_resolution_level();
+    // A function that generates the pixel data for the image
+    let create_pixels = |resolution: exr::Vec2<usize>, _: &_| create(resolution.width() as u32, resolution.height() as u32);
+
+    let Some(name) = layer else {
+        let read = read.rgba_channels(create_pixels, set_pixel).first_valid_layer();
+        let layer_data = read_layers(&read, reader, pool, deadline)?;
+        let layer = layer_data.attributes.layer_name.as_ref().map(|name| name.to_string());
+        return Ok((layer_data.channel_data.pixels, layer));
+    };
+
+    if meta.headers.iter().any(|header| is_part_named(header, name)) {
+        let read = ReadNamedPart {
+            read_channels: read.rgba_channels(create_pixels, set_pixel),
+            name,
+        };
+        let layer_data = read_layers(&read, reader, pool, deadline)?;
+        return Ok((layer_data.channel_data.pixels, Some(name.to_string())));
+    }
+
+    let channel = |suffix: &str| exr::Text::new_or_none(format!("{}.{}", name, suffix));
+    let (Some(r), Some(g), Some(b), Some(a)) = (channel("R"), channel("G"), channel("B"), channel("A")) else {
+        return Err(ThumbError::MissingLayer(format!("invalid layer name '{}'", name)));
+    };
+    let has_channels = meta
+        .headers
+        .iter()
+        .any(|header| [&r, &g, &b].iter().all(|name| header.channels.find_index_of_channel(name).is_some()));
+    if !has_channels {
+        return Err(ThumbError::MissingLayer(format!("no layer named '{}'", name)));
+    }
+    let read = read
+        .specific_channels()
+        .required(r)
+        .required(g)
+        .required(b)
+        .optional(a, 1.0)
+        .collect_pixels(create_pixels, set_pixel)
+        .first_valid_layer();
+    let layer_data = read_layers(&read, reader, pool, deadline)?;
+    Ok((layer_data.channel_data.pixels, Some(name.to_string())))
+}
+
+/// Read the layers selected by `read`, like `ReadImage::from_buffered` but
+/// with the threads of `pool` instead of one thread per CPU.
+fn read_layers<'s, L: ReadLayers<'s>>(
+    read: &'s L,
+    reader: impl Read + Seek,
+    pool: &DecodePool,
+    deadline: Option<Deadline>,
+) -> Result<L::Layers, ThumbError> {
+    let error = ThumbError::from_exr_data;
+    let chunks = ::exr::block::read(reader, false).map_err(error)?;
+    let mut layers_reader = read.create_layers_reader(chunks.headers()).map_err(ThumbError::from_exr_layers)?;
+    let mut blocks = chunks
+        .filter_chunks(false, |meta, tile, block| layers_reader.filter_block(meta, tile, block))
+        .map_err(error)?;
+    let meta = blocks.meta_data().clone();
+
+    // Uncompressed files are always read sequentially
+    let uncompressed = meta.headers.iter().all(|header| header.compression == Compression::Uncompressed);
+    let max_in_flight = pool.blocks_in_flight();
+    let Some(pool) = pool.get().filter(|_| !uncompressed) else {
+        for chunk in blocks {
+            Deadline::check(deadline.as_ref())?;
+            let block = UncompressedBlock::decompress_chunk(chunk.map_err(error)?, &meta, false)
+                .map_err(ThumbError::from_exr_decompression)?;
+            layers_reader.read_block(&meta.headers, block).map_err(error)?;
+        }
+        return Ok(layers_reader.into_layers());
+    };
+
+    // Like exr's ParallelBlockDecompressor, with about one block per thread
+    // in flight, but a panic while decompressing is passed on to the calling
+    // thread, where it can be caught, instead of aborting the process.
+    let meta = Arc::new(meta);
+    let (sender, receiver) = mpsc::channel();
+    let mut in_flight = 0;
+    loop {
+        Deadline::check(deadline.as_ref())?;
+        while in_flight < max_in_flight {
+            let Some(chunk) = blocks.next() else {
+                break;
+            };
+            let chunk = chunk.map_err(error)?;
+            let (sender, meta) = (sender.clone(), meta.clone());
+            pool.spawn(move || {
+                let block = panic::catch_unwind(AssertUnwindSafe(|| UncompressedBlock::decompress_chunk(chunk, &meta, false)));
+                // The receiver is gone if another block failed
+                let _ = sender.send(block);
+            });
+            in_flight += 1;
+        }
+        if in_flight == 0 {
+            return Ok(layers_reader.into_layers());
+        }
+        // A block stuck in decompression must not outlast the deadline
+        let block = loop {
+            match receiver.recv_timeout(DEADLINE_POLL) {
+                Ok(block) => break block,
+                Err(RecvTimeoutError::Timeout) => Deadline::check(deadline.as_ref())?,
+                Err(RecvTimeoutError::Disconnected) => unreachable!("a decompression job holds a sender"),
+            }
+        };
+        in_flight -= 1;
+        match block {
+            Ok(block) => {
+                let block = block.map_err(ThumbError::from_exr_decompression)?;
+                layers_reader.read_block(&meta.headers, block).map_err(error)?
+            }
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+pub(crate) fn decoded(mut reducer: BoxReducer, layer: Option<String>) -> DecodedExr {
+    let (source_width, source_height) = reducer.source_size();
+    let (width, height) = reducer.size();
+    let invalid = reducer.invalid();
+    let invalid_flags = reducer.take_flags();
+    let pixel_stats = reducer.take_pixel_stats();
+    let (pixels, stats) = reducer.finish();
+    DecodedExr {
+        source_width,
+        source_height,
+        width,
+        height,
+        layer,
+        pixels,
+        invalid,
+        invalid_flags,
+        stats,
+        pixel_stats,
+    }
+}
+
+// A function that fills the previously generated pixel data
+fn set_pixel<S: PixelSink>(sink: &mut S, position: exr::Vec2<usize>, (r, g, b, a): (f32, f32, f32, f32)) {
+    sink.add(position.x(), position.y(), [r, g, b, a]);
+}
+
+fn is_part_named(header: &Header, name: &str) -> bool {
+    header.own_attributes.layer_name.as_ref().is_some_and(|layer| layer.eq(name))
+}
+
+/// Read only the part of a multi-part file with the given name
+struct ReadNamedPart<'n, C> {
+    read_channels: C,
+    name: &'n str,
+}
+
+struct NamedPartReader<C> {
+    channels_reader: C,
+    index: usize,
+    header: Header,
+}
+
+impl<'s, C: ReadChannels<'s>> ReadLayers<'s> for ReadNamedPart<'_, C> {
+    type Layers = exr::Layer<<C::Reader as ChannelsReader>::Channels>;
+    type Reader = NamedPartReader<C::Reader>;
+
+    fn create_layers_reader(&'s self, headers: &[Header]) -> exr::Result<Self::Reader> {
+        let (index, header) = headers
+            .iter()
+            .enumerate()
+            .find(|(_, header)| is_part_named(header, self.name))
+            .ok_or_else(|| exr::Error::Invalid("no layer in the image matched your specified requirements".into()))?;
+        Ok(NamedPartReader {
+            channels_reader: self.read_channels.create_channels_reader(header)?,
+            index,
+            header: header.clone(),
+        })
+    }
+}
+
+impl<C: ChannelsReader> LayersReader for NamedPartReader<C> {
+    type Layers = exr::Layer<C::Channels>;
+
+    fn filter_block(&self, _: &exr::MetaData, tile: TileCoordinates, block: BlockIndex) -> bool {
+        block.layer == self.index && self.channels_reader.filter_block(tile)
+    }
+
+    fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult {
+        self.channels_reader.read_block(&headers[self.index], block)
+    }
+
+    fn into_layers(self) -> Self::Layers {
+        let header = self.header;
+        exr::Layer {
+            channel_data: self.channels_reader.into_channels(),
+            attributes: header.own_attributes,
+            size: header.layer_size,
+            encoding: Encoding {
+                compression: header.compression,
+                line_order: header.line_order,
+                blocks: match header.blocks {
+                    BlockDescription::ScanLines => Blocks::ScanLines,
+                    BlockDescription::Tiles(tiles) => Blocks::Tiles(tiles.tile_size),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Sink that never returns, like a decoder stuck inside a block
+    struct StalledSink;
+
+    impl PixelSink for StalledSink {
+        fn add(&mut self, _: usize, _: usize, _: [f32; 4]) {
+            std::thread::sleep(Duration::from_secs(3600));
+        }
+    }
+
+    fn exr_bytes() -> Vec<u8> {
+        let channels = exr::SpecificChannels::rgba(|_| (0.5_f32, 0.5_f32, 0.5_f32, 1.0_f32));
+        let mut bytes = Vec::new();
+        exr::Image::from_channels((8, 8), channels)
+            .write()
+            .to_buffered(Cursor::new(&mut bytes))
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn clones_share_the_decode_threads() {
+        let pool = DecodePool::new(2, 3);
+        let clone = pool.clone();
+        let threads = pool.get().unwrap();
+        assert!(std::ptr::eq(threads, clone.get().unwrap()));
+        assert_eq!(threads.current_num_threads(), 6);
+        assert_eq!(pool.blocks_in_flight(), 4);
+        assert!(DecodePool::new(1, 3).get().is_none());
+    }
+
+    #[test]
+    fn a_stalled_decode_is_abandoned_at_the_timeout() {
+        let exr = exr_bytes();
+        let start = Instant::now();
+        let result = crate::with_timeout(Duration::from_millis(100), move || {
+            decode_layer(Cursor::new(exr), None, &DecodePool::new(1, 1), None, |_, _| StalledSink).map(|_| ())
+        });
+        assert!(matches!(result, Err(ThumbError::Timeout(_))));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn decoding_checks_the_cancel_flag() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let deadline = Deadline::new(None, Some(cancel));
+        let result = decode_layer(Cursor::new(exr_bytes()), None, &DecodePool::new(1, 1), deadline, |_, _| StalledSink);
+        assert!(matches!(result, Err(ThumbError::Cancelled)));
+    }
+
+    #[test]
+    fn a_file_without_rgb_channels_is_missing_a_layer() {
+        let channels = exr::SpecificChannels::build().with_channel("Z").with_pixel_fn(|_| (1.0_f32,));
+        let mut bytes = Vec::new();
+        exr::Image::from_channels((8, 8), channels)
+            .write()
+            .to_buffered(Cursor::new(&mut bytes))
+            .unwrap();
+        let result = decode_layer(Cursor::new(bytes), None, &DecodePool::new(1, 1), None, |_, _| StalledSink);
+        assert!(matches!(result, Err(ThumbError::MissingLayer(_))));
+    }
+
+    #[test]
+    fn a_compression_exr_cannot_decode_is_unsupported() {
+        let mut bytes = exr_bytes();
+        // Relabel the blocks as HTJ2K, which exr cannot decompress
+        let attribute = b"compression\0compression\0\x01\0\0\0";
+        let at = bytes.windows(attribute.len()).position(|window| window == attribute).unwrap();
+        bytes[at + attribute.len()] = 10;
+        let result = decode_layer(Cursor::new(bytes), None, &DecodePool::new(1, 1), None, |_, _| StalledSink);
+        assert!(matches!(result, Err(ThumbError::UnsupportedCompression(_))), "{:?}", result.err());
+    }
+}
@@ -0,0 +1,320 @@
+//! `diff` subcommand: compare EXR files, or the files with the same name in
+//! two folders, for render regression tests.
+
+use exr_thumbnailer::compare::{ChannelDiff, DiffRequest, ImageDiff};
+use exr_thumbnailer::{ThumbError, ThumbnailSize};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::inspect::PrintFormat;
+use crate::output;
+use crate::{is_exr_file, render_request, DiffArgs, Exit};
+
+const CHANNEL_NAMES: [&str; 4] = ["R", "G", "B", "A"];
+
+#[derive(Debug, Serialize)]
+struct PairResult {
+    name: String,
+    reference: String,
+    candidate: String,
+    /// Whether the pair is within the thresholds
+    passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    layer: Option<String>,
+    /// R, G, B and A; a PSNR of null means the channels are identical
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    channels: Vec<ChannelDiff>,
+    /// Thresholds the pair exceeds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exceeded: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+}
+
+pub fn diff(args: &DiffArgs) -> io::Result<Exit> {
+    let pairs = match pair_files(&args.reference, &args.candidate) {
+        Ok(pairs) => pairs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Exit::NoInput);
+        }
+    };
+    if pairs.is_empty() {
+        eprintln!("Error: No EXR files found.");
+        return Ok(Exit::NoInput);
+    }
+    if let Some(output) = &args.output {
+        fs::create_dir_all(output)?;
+    }
+
+    // Several pairs are compared in parallel, each decoding in one thread
+    let decode_threads = if pairs.len() > 1 { 1 } else { 0 };
+    let settings = render_request(&args.render)
+        .size(ThumbnailSize::Height(args.height))
+        .maybe_layer(args.layer.clone())
+        .decode_threads(decode_threads);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .map_err(io::Error::other)?;
+    let results: Vec<PairResult> = pool.install(|| {
+        pairs
+            .par_iter()
+            .map(|(name, reference, candidate)| {
+                let request = DiffRequest::new(reference, candidate).settings(settings.clone()).image(args.image);
+                compare(args, name, &request)
+            })
+            .collect()
+    });
+
+    match args.format {
+        PrintFormat::Text => {
+            for result in &results {
+                print!("{}", result.to_text());
+            }
+        }
+        PrintFormat::Json => println!("{}", serde_json::to_string_pretty(&results).map_err(io::Error::other)?),
+    }
+
+    let failed = results.iter().filter(|result| !result.passed).count();
+    Ok(match failed {
+        0 => Exit::Success,
+        failed if failed == results.len() => Exit::TotalFailure,
+        _ => Exit::PartialFailure,
+    })
+}
+
+/// Pair the reference and candidate files as `(name, reference, candidate)`.
+///
+/// Two folders are paired by file name and a file with a folder by looking up
+/// its name in the folder. A file only on one side is an error, as a missing
+/// render is a regression too.
+fn pair_files(reference: &Path, candidate: &Path) -> io::Result<Vec<(String, PathBuf, PathBuf)>> {
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    match (reference.is_dir(), candidate.is_dir()) {
+        (true, true) => {
+            let exr_names = |folder: &Path| -> io::Result<BTreeSet<String>> {
+                Ok(fs::read_dir(folder)?
+                    .filter_map(|entry| entry.ok().map(|e| e.path()).filter(|path| is_exr_file(path)))
+                    .map(|path| name(&path))
+                    .collect())
+            };
+            let (references, candidates) = (exr_names(reference)?, exr_names(candidate)?);
+            let unpaired: Vec<&String> = references.symmetric_difference(&candidates).collect();
+            if let Some(first) = unpaired.first() {
+                let side = if references.contains(*first) { candidate } else { reference };
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{} is missing from {}{}",
+                        first,
+                        side.display(),
+                        match unpaired.len() {
+                            1 => String::new(),
+                            n => format!(" ({} unpaired files)", n),
+                        }
+                    ),
+                ));
+            }
+            Ok(references
+                .into_iter()
+                .map(|name| (name.clone(), reference.join(&name), candidate.join(&name)))
+                .collect())
+        }
+        (true, false) => Ok(vec![(name(candidate), reference.join(name(candidate)), candidate.to_path_buf())]),
+        (false, true) => Ok(vec![(name(reference), reference.to_path_buf(), candidate.join(name(reference)))]),
+        (false, false) => Ok(vec![(name(candidate), reference.to_path_buf(), candidate.to_path_buf())]),
+    }
+}
+
+fn compare(args: &DiffArgs, name: &str, request: &DiffRequest) -> PairResult {
+    let mut result = PairResult {
+        name: name.to_string(),
+        reference: request.reference().display().to_string(),
+        candidate: request.candidate().display().to_string(),
+        passed: false,
+        error: None,
+        layer: None,
+        channels: Vec::new(),
+        exceeded: Vec::new(),
+        image: None,
+    };
+    let diff = match request.diff() {
+        Ok(diff) => diff,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+    result.layer = diff.layer.clone();
+    result.channels = diff.channels.to_vec();
+    result.exceeded = exceeded(args, &diff);
+    result.passed = result.exceeded.is_empty();
+
+    if let Some(output) = &args.output {
+        let stem = Path::new(name).file_stem().unwrap_or_default().to_string_lossy();
+        let path = output.join(format!("{}_diff.png", stem));
+        match output::save_atomic(diff.image, &path) {
+            Ok(()) => result.image = Some(path.display().to_string()),
+            Err(e) => {
+                result.error = Some(ThumbError::from(e).to_string());
+                result.passed = false;
+            }
+        }
+    }
+    result
+}
+
+/// Descriptions of the thresholds `diff` exceeds.
+fn exceeded(args: &DiffArgs, diff: &ImageDiff) -> Vec<String> {
+    let mut exceeded = Vec::new();
+    if let Some(max_rmse) = args.max_rmse.filter(|max| diff.rmse() > *max) {
+        exceeded.push(format!("RMSE {:.6} > {}", diff.rmse(), max_rmse));
+    }
+    if let Some(min_psnr) = args.min_psnr.filter(|min| diff.psnr() < *min) {
+        exceeded.push(format!("PSNR {:.2} dB < {} dB", diff.psnr(), min_psnr));
+    }
+    if let Some(max_abs) = args.max_abs.filter(|max| diff.max_abs() > *max) {
+        exceeded.push(format!("max abs {:.6} > {}", diff.max_abs(), max_abs));
+    }
+    exceeded
+}
+
+impl PairResult {
+    fn to_text(&self) -> String {
+        let verdict = match (&self.error, self.passed) {
+            (Some(e), _) => format!("ERROR: {}", e),
+            (None, true) => "ok".to_string(),
+            (None, false) => format!("FAIL: {}", self.exceeded.join(", ")),
+        };
+        let mut text = format!("{}: {}\n", self.name, verdict);
+        if let Some(layer) = &self.layer {
+            text += &format!("  layer {}\n", layer);
+        }
+        for (name, channel) in CHANNEL_NAMES.iter().zip(&self.channels) {
+            text += &format!(
+                "  {}  RMSE {:.6}  PSNR {}  max abs {:.6}\n",
+                name,
+                channel.rmse,
+                psnr_text(channel.psnr),
+                channel.max_abs
+            );
+        }
+        if let Some(image) = &self.image {
+            text += &format!("  image {}\n", image);
+        }
+        text
+    }
+}
+
+fn psnr_text(psnr: f64) -> String {
+    if psnr.is_finite() {
+        format!("{:.2} dB", psnr)
+    } else {
+        "inf dB".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::*;
+
+    /// Reference and candidate folders in a temporary folder, removed when dropped
+    struct Folders {
+        base: PathBuf,
+        reference: PathBuf,
+        candidate: PathBuf,
+    }
+
+    impl Folders {
+        fn new(name: &str) -> Self {
+            let base = std::env::temp_dir().join(format!("exr_thumbnailer_diff_{}_{}", name, std::process::id()));
+            let folders = Self {
+                reference: base.join("reference"),
+                candidate: base.join("candidate"),
+                base,
+            };
+            fs::create_dir_all(&folders.reference).unwrap();
+            fs::create_dir_all(&folders.candidate).unwrap();
+            folders
+        }
+    }
+
+    impl Drop for Folders {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn write_exr(path: &Path) {
+        let channels = SpecificChannels::rgba(|_| (0.5_f32, 0.25_f32, 0.125_f32, 1.0_f32));
+        Image::from_channels((8, 8), channels).write().to_file(path).unwrap();
+    }
+
+    #[test]
+    fn folders_are_paired_by_file_name() {
+        let folders = Folders::new("paired");
+        for name in ["a.exr", "b.exr"] {
+            write_exr(&folders.reference.join(name));
+            write_exr(&folders.candidate.join(name));
+        }
+        fs::write(folders.candidate.join("notes.txt"), b"").unwrap();
+        let pairs = pair_files(&folders.reference, &folders.candidate).unwrap();
+        let names: Vec<&str> = pairs.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.exr", "b.exr"]);
+        assert_eq!(pairs[1].1, folders.reference.join("b.exr"));
+        assert_eq!(pairs[1].2, folders.candidate.join("b.exr"));
+
+        let file = folders.candidate.join("a.exr");
+        let pairs = pair_files(&folders.reference, &file).unwrap();
+        assert_eq!(pairs, [("a.exr".to_string(), folders.reference.join("a.exr"), file)]);
+    }
+
+    #[test]
+    fn unpaired_files_are_an_error() {
+        let folders = Folders::new("unpaired");
+        write_exr(&folders.reference.join("a.exr"));
+        write_exr(&folders.reference.join("b.exr"));
+        write_exr(&folders.candidate.join("a.exr"));
+        write_exr(&folders.candidate.join("c.exr"));
+        let error = pair_files(&folders.reference, &folders.candidate).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            error.to_string(),
+            format!("b.exr is missing from {} (2 unpaired files)", folders.candidate.display())
+        );
+    }
+
+    #[test]
+    fn identical_files_have_a_null_psnr_in_json() {
+        let folders = Folders::new("identical");
+        let (reference, candidate) = (folders.reference.join("a.exr"), folders.candidate.join("a.exr"));
+        write_exr(&reference);
+        write_exr(&candidate);
+        let diff = DiffRequest::new(&reference, &candidate).diff().unwrap();
+        let result = PairResult {
+            name: "a.exr".to_string(),
+            reference: reference.display().to_string(),
+            candidate: candidate.display().to_string(),
+            passed: true,
+            error: None,
+            layer: diff.layer,
+            channels: diff.channels.to_vec(),
+            exceeded: Vec::new(),
+            image: None,
+        };
+        let json = serde_json::to_value(&result).unwrap();
+        for channel in json["channels"].as_array().unwrap() {
+            assert_eq!(channel["psnr"], serde_json::Value::Null);
+            assert_eq!(channel["rmse"], 0.0);
+        }
+        assert!(result.to_text().contains("  R  RMSE 0.000000  PSNR inf dB  max abs 0.000000\n"));
+    }
+}
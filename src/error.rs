@@ -0,0 +1,173 @@
+//! Classified conversion errors.
+
+use exr::error::Error as ExrError;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+/// Category of a conversion failure, as written to the report
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Io,
+    UnsupportedCompression,
+    UnsupportedFeature,
+    CorruptHeader,
+    CorruptData,
+    MissingLayer,
+    Encode,
+    InvalidPixels,
+    SizeMismatch,
+    Timeout,
+    Cancelled,
+    Panic,
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Io => "io",
+            ErrorKind::UnsupportedCompression => "unsupported_compression",
+            ErrorKind::UnsupportedFeature => "unsupported_feature",
+            ErrorKind::CorruptHeader => "corrupt_header",
+            ErrorKind::CorruptData => "corrupt_data",
+            ErrorKind::MissingLayer => "missing_layer",
+            ErrorKind::Encode => "encode",
+            ErrorKind::InvalidPixels => "invalid_pixels",
+            ErrorKind::SizeMismatch => "size_mismatch",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Panic => "panic",
+        }
+    }
+}
+
+/// Error converting a single EXR file to a thumbnail
+#[derive(Debug)]
+pub enum ThumbError {
+    /// Reading the source or writing the thumbnail failed
+    Io(io::Error),
+    /// The file uses a compression method the decoder does not support
+    UnsupportedCompression(String),
+    /// The file uses another feature the decoder does not support, e.g. deep data
+    UnsupportedFeature(String),
+    /// The file is not an EXR or its headers are damaged
+    CorruptHeader(String),
+    /// The headers are valid but the pixel data could not be decoded
+    CorruptData(String),
+    /// No layer with RGB(A) channels was found
+    MissingLayer(String),
+    /// The thumbnail could not be encoded
+    Encode(image::ImageError),
+    /// The pixels contain NaN or infinite values and the caller treats that
+    /// as a failure
+    InvalidPixels(String),
+    /// Two images compared with each other differ in size
+    SizeMismatch(String),
+    /// Generating the thumbnail took longer than the timeout of the request
+    Timeout(String),
+    /// Decoding was stopped by the cancel flag of the request
+    Cancelled,
+    /// The decoder or the image processing panicked; only returned by
+    /// callers that catch panics
+    Panic(String),
+}
+
+impl ThumbError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ThumbError::Io(_) => ErrorKind::Io,
+            ThumbError::UnsupportedCompression(_) => ErrorKind::UnsupportedCompression,
+            ThumbError::UnsupportedFeature(_) => ErrorKind::UnsupportedFeature,
+            ThumbError::CorruptHeader(_) => ErrorKind::CorruptHeader,
+            ThumbError::CorruptData(_) => ErrorKind::CorruptData,
+            ThumbError::MissingLayer(_) => ErrorKind::MissingLayer,
+            ThumbError::Encode(_) => ErrorKind::Encode,
+            ThumbError::InvalidPixels(_) => ErrorKind::InvalidPixels,
+            ThumbError::SizeMismatch(_) => ErrorKind::SizeMismatch,
+            ThumbError::Timeout(_) => ErrorKind::Timeout,
+            ThumbError::Cancelled => ErrorKind::Cancelled,
+            ThumbError::Panic(_) => ErrorKind::Panic,
+        }
+    }
+
+    /// Classify an error returned while reading the EXR headers.
+    pub fn from_exr_header(error: ExrError) -> Self {
+        match error {
+            ExrError::Invalid(message) => ThumbError::CorruptHeader(message.into_owned()),
+            other => Self::from_exr_data(other),
+        }
+    }
+
+    /// Classify an error returned while selecting the layer to decode, where
+    /// an invalid image is one without a layer with the requested channels.
+    pub fn from_exr_layers(error: ExrError) -> Self {
+        match error {
+            ExrError::Invalid(message) => ThumbError::MissingLayer(message.into_owned()),
+            other => Self::from_exr_data(other),
+        }
+    }
+
+    /// Classify an error returned while decompressing a block. exr reports
+    /// every compression method or variant it cannot decode as unsupported.
+    pub fn from_exr_decompression(error: ExrError) -> Self {
+        match error {
+            ExrError::NotSupported(message) => ThumbError::UnsupportedCompression(message.into_owned()),
+            other => Self::from_exr_data(other),
+        }
+    }
+
+    /// Classify an error returned while decoding the EXR pixel data.
+    pub fn from_exr_data(error: ExrError) -> Self {
+        match error {
+            ExrError::Io(error) => ThumbError::Io(error),
+            ExrError::NotSupported(message) => ThumbError::UnsupportedFeature(message.into_owned()),
+            ExrError::Invalid(message) => ThumbError::CorruptData(message.into_owned()),
+            ExrError::Aborted => ThumbError::CorruptData("decoding aborted".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ThumbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbError::Io(e) => write!(f, "I/O error: {}", e),
+            ThumbError::UnsupportedCompression(msg) => write!(f, "unsupported compression: {}", msg),
+            ThumbError::UnsupportedFeature(msg) => write!(f, "unsupported feature: {}", msg),
+            ThumbError::CorruptHeader(msg) => write!(f, "corrupt header: {}", msg),
+            ThumbError::CorruptData(msg) => write!(f, "corrupt pixel data: {}", msg),
+            ThumbError::MissingLayer(msg) => write!(f, "missing layer: {}", msg),
+            ThumbError::Encode(e) => write!(f, "encode failure: {}", e),
+            ThumbError::InvalidPixels(msg) => write!(f, "invalid pixels: {}", msg),
+            ThumbError::SizeMismatch(msg) => write!(f, "size mismatch: {}", msg),
+            ThumbError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            ThumbError::Cancelled => write!(f, "cancelled"),
+            ThumbError::Panic(msg) => write!(f, "panic: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ThumbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ThumbError::Io(e) => Some(e),
+            ThumbError::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ThumbError {
+    fn from(error: io::Error) -> Self {
+        ThumbError::Io(error)
+    }
+}
+
+impl From<image::ImageError> for ThumbError {
+    fn from(error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(e) => ThumbError::Io(e),
+            other => ThumbError::Encode(other),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Integration with the freedesktop.org thumbnail cache used by Linux file
+//! managers such as Nautilus and Dolphin.
+//!
+//! See the Thumbnail Managing Standard:
+//! <https://specifications.freedesktop.org/thumbnail-spec/latest/>
+
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::error::ThumbError;
+
+/// MIME type registered for OpenEXR files
+const EXR_MIME_TYPE: &str = "image/x-exr";
+/// File name of the generated thumbnailer entry
+const THUMBNAILER_FILE_NAME: &str = "exr_thumbnailer.thumbnailer";
+
+/// Cache folder and maximum edge length for a requested thumbnail size.
+/// Requests above the largest flavour are served from `x-large`.
+pub fn flavor(size: u32) -> (&'static str, u32) {
+    match size {
+        0..=128 => ("normal", 128),
+        129..=256 => ("large", 256),
+        _ => ("x-large", 512),
+    }
+}
+
+/// The user's cache folder, `$XDG_CACHE_HOME` or `~/.cache`.
+pub fn cache_home() -> io::Result<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_CACHE_HOME nor HOME is set"))
+}
+
+/// Root of the thumbnail cache, `$XDG_CACHE_HOME/thumbnails`.
+pub fn cache_root() -> io::Result<PathBuf> {
+    Ok(cache_home()?.join("thumbnails"))
+}
+
+/// Path of the cached thumbnail of `uri` in the given flavour folder.
+pub fn cache_path(uri: &str, flavor: &str) -> io::Result<PathBuf> {
+    let name = format!("{:x}.png", md5::compute(uri.as_bytes()));
+    Ok(cache_root()?.join(flavor).join(name))
+}
+
+/// Canonical `file://` URI of a local path, escaped the way GLib does so the
+/// MD5 matches the one file managers compute.
+pub fn file_uri(path: &Path) -> io::Result<String> {
+    let absolute = fs::canonicalize(path)?;
+    let mut uri = String::from("file://");
+    for &byte in absolute.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"!$&'()*+,-./:=@_~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Ok(uri)
+}
+
+/// Turn a `file://` URI passed by a file manager back into a path. Anything
+/// else is taken to be a path already.
+pub fn path_from_arg(arg: &Path) -> PathBuf {
+    let Some(escaped) = arg.to_str().and_then(|s| s.strip_prefix("file://")) else {
+        return arg.to_path_buf();
+    };
+    let bytes = escaped.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Write `thumbnail` as PNG with the `Thumb::` metadata required by the
+/// standard. The file is written to a temporary name and renamed so readers
+/// never see a partial thumbnail.
+pub fn write_thumbnail(
+    thumbnail: &image::RgbaImage,
+    out_path: &Path,
+    source: &Path,
+    uri: &str,
+    source_size: (u32, u32),
+) -> Result<(), ThumbError> {
+    let source_meta = fs::metadata(source)?;
+    let mtime = source_meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = out_path.with_extension(format!("png.{}.tmp", std::process::id()));
+    let file = fs::File::create(&temp_path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), thumbnail.width(), thumbnail.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let text_chunks = [
+        ("Thumb::URI", uri.to_string()),
+        ("Thumb::MTime", mtime.to_string()),
+        ("Thumb::Size", source_meta.len().to_string()),
+        ("Thumb::Mimetype", EXR_MIME_TYPE.to_string()),
+        ("Thumb::Image::Width", source_size.0.to_string()),
+        ("Thumb::Image::Height", source_size.1.to_string()),
+        ("Software", format!("exr_thumbnailer {}", env!("CARGO_PKG_VERSION"))),
+    ];
+    let result = text_chunks
+        .into_iter()
+        .try_for_each(|(keyword, text)| encoder.add_text_chunk(keyword.to_string(), text))
+        .and_then(|()| encoder.write_header())
+        .and_then(|mut writer| {
+            writer.write_image_data(thumbnail.as_raw())?;
+            writer.finish()
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(png_error(e));
+    }
+
+    set_private_permissions(&temp_path)?;
+    fs::rename(&temp_path, out_path)?;
+    Ok(())
+}
+
+/// Contents of the `.thumbnailer` entry that makes file managers call
+/// `executable` for EXR files.
+pub fn thumbnailer_entry(executable: &Path) -> String {
+    let exe = executable.to_string_lossy();
+    format!(
+        "[Thumbnailer Entry]\n\
+         TryExec={}\n\
+         Exec={} --freedesktop %i --size %s --output %o\n\
+         MimeType={EXR_MIME_TYPE};\n",
+        escape_value(&exe),
+        escape_value(&quote_exec_argument(&exe))
+    )
+}
+
+/// Quote an argument of an `Exec` key as the Desktop Entry Specification
+/// asks, so paths with spaces or reserved characters stay one argument.
+fn quote_exec_argument(argument: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in argument.chars() {
+        match c {
+            '"' | '`' | '$' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            // A literal percent sign in Exec is written as %%
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escape a string value of a desktop entry, which is unescaped before the
+/// `Exec` quoting is parsed.
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Install the thumbnailer entry into `dir`, by default
+/// `$XDG_DATA_HOME/thumbnailers`, and return its path.
+pub fn install_thumbnailer(dir: Option<&Path>) -> io::Result<PathBuf> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set"))?
+            .join("thumbnailers"),
+    };
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(THUMBNAILER_FILE_NAME);
+    fs::write(&path, thumbnailer_entry(&env::current_exe()?))?;
+    Ok(path)
+}
+
+fn png_error(error: png::EncodingError) -> ThumbError {
+    match error {
+        png::EncodingError::IoError(e) => ThumbError::Io(e),
+        other => ThumbError::Encode(image::ImageError::Encoding(image::error::EncodingError::new(
+            image::ImageFormat::Png.into(),
+            other,
+        ))),
+    }
+}
+
+/// Create a cache folder readable by the owner only, as the standard asks.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+fn set_private_permissions(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_executable_is_quoted_in_exec() {
+        let entry = thumbnailer_entry(Path::new("/opt/EXR tools/exr_thumbnailer"));
+        assert_eq!(
+            entry,
+            "[Thumbnailer Entry]\n\
+             TryExec=/opt/EXR tools/exr_thumbnailer\n\
+             Exec=\"/opt/EXR tools/exr_thumbnailer\" --freedesktop %i --size %s --output %o\n\
+             MimeType=image/x-exr;\n"
+        );
+    }
+
+    #[test]
+    fn reserved_characters_are_escaped_in_exec() {
+        assert_eq!(quote_exec_argument("/a \"b\" $c `d` 100%"), "\"/a \\\"b\\\" \\$c \\`d\\` 100%%\"");
+        // Backslashes are escaped for Exec and again as a string value
+        let entry = thumbnailer_entry(Path::new("/x\\y/exr_thumbnailer"));
+        assert!(entry.contains("TryExec=/x\\\\y/exr_thumbnailer\n"), "{}", entry);
+        assert!(entry.contains("Exec=\"/x\\\\\\\\y/exr_thumbnailer\" "), "{}", entry);
+    }
+
+    #[test]
+    fn cache_names_are_the_md5_of_the_uri() {
+        // Example of the Thumbnail Managing Standard
+        let path = cache_path("file:///home/jens/photos/me.png", "normal").unwrap();
+        assert_eq!(path, cache_root().unwrap().join("normal/c6ee772d9e49320e97ec29a7eb5b1697.png"));
+    }
+
+    #[test]
+    fn thumbnails_record_the_uri_and_mtime_of_the_source() {
+        let dir = std::env::temp_dir().join(format!("exr_thumbnailer_freedesktop_{}", std::process::id()));
+        let source = dir.join("my shot.exr");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&source, b"exr contents").unwrap();
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        fs::File::options().write(true).open(&source).unwrap().set_modified(mtime).unwrap();
+
+        let uri = file_uri(&source).unwrap();
+        assert!(uri.starts_with("file:///") && uri.ends_with("/my%20shot.exr"), "{}", uri);
+        assert_eq!(path_from_arg(Path::new(&uri)), fs::canonicalize(&source).unwrap());
+        let out_path = dir.join("normal").join(cache_path(&uri, "normal").unwrap().file_name().unwrap());
+        write_thumbnail(&image::RgbaImage::new(4, 2), &out_path, &source, &uri, (400, 200)).unwrap();
+
+        let decoder = png::Decoder::new(std::io::BufReader::new(fs::File::open(&out_path).unwrap()));
+        let reader = decoder.read_info().unwrap();
+        let text: std::collections::HashMap<&str, &str> = reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.as_str(), chunk.text.as_str()))
+            .collect();
+        assert_eq!(text["Thumb::URI"], uri);
+        assert_eq!(text["Thumb::MTime"], "1700000000");
+        assert_eq!(text["Thumb::Size"], "12");
+        assert_eq!(text["Thumb::Image::Width"], "400");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Hashing of EXR files while they are decoded, so streamed files are read
+//! only once.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::Xxh3Default;
+
+/// Size of the reads that hash bytes the decoder skipped
+const SKIP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Reader that computes the [`crate::content_hash`] of its contents in file
+/// order while they are read, and measures the time spent reading.
+///
+/// Bytes the decoder skips with a forward seek are read and hashed on the
+/// way, bytes it reads again after seeking back are not hashed twice.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    /// Position of the next read
+    position: u64,
+    /// Bytes hashed so far, always a prefix of the contents
+    hashed: u64,
+    hasher: Option<Xxh3Default>,
+    read_time: Duration,
+}
+
+impl<R: Read + Seek> HashingReader<R> {
+    /// Read and hash `inner`, which must be at its start.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            position: 0,
+            hashed: 0,
+            hasher: Some(Xxh3Default::new()),
+            read_time: Duration::ZERO,
+        }
+    }
+
+    /// Read `inner` without hashing it, only measuring the time.
+    pub fn without_hash(inner: R) -> Self {
+        Self {
+            hasher: None,
+            ..Self::new(inner)
+        }
+    }
+
+    /// Hash the bytes not read yet and return the hash, if there is one, and
+    /// the total time spent reading.
+    pub fn finish(mut self) -> io::Result<(Option<String>, Duration)> {
+        let start = Instant::now();
+        if self.hasher.is_some() {
+            self.hash_until(u64::MAX)?;
+        }
+        let hash = self.hasher.map(|hasher| format!("{:016x}", hasher.digest()));
+        Ok((hash, self.read_time + start.elapsed()))
+    }
+
+    /// Read and hash the bytes from the end of the hashed prefix up to `end`
+    /// or the end of the contents, leaving `inner` where it stopped.
+    fn hash_until(&mut self, end: u64) -> io::Result<()> {
+        let Some(hasher) = &mut self.hasher else {
+            return Ok(());
+        };
+        self.inner.seek(SeekFrom::Start(self.hashed))?;
+        let mut buffer = vec![0; SKIP_BUFFER_SIZE];
+        while self.hashed < end {
+            let len = (end - self.hashed).min(buffer.len() as u64) as usize;
+            match self.inner.read(&mut buffer[..len])? {
+                0 => break,
+                read => {
+                    hasher.update(&buffer[..read]);
+                    self.hashed += read as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let read = self.inner.read(buf)?;
+        let end = self.position + read as u64;
+        if let Some(hasher) = &mut self.hasher {
+            if self.position <= self.hashed && end > self.hashed {
+                hasher.update(&buf[(self.hashed - self.position) as usize..read]);
+                self.hashed = end;
+            }
+        }
+        self.position = end;
+        self.read_time += start.elapsed();
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for HashingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let start = Instant::now();
+        let position = self.inner.seek(pos)?;
+        if self.hasher.is_some() && position > self.hashed {
+            self.hash_until(position)?;
+            self.inner.seek(SeekFrom::Start(position))?;
+        }
+        self.position = position;
+        self.read_time += start.elapsed();
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn hash_covers_skipped_and_unread_bytes() {
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut reader = HashingReader::new(Cursor::new(bytes.as_slice()));
+        let mut buf = [0; 100];
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(150_000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &bytes[150_000..150_100]);
+        let (hash, _) = reader.finish().unwrap();
+        assert_eq!(hash, Some(crate::content_hash(&bytes)));
+    }
+
+    #[test]
+    fn without_hash_only_reads() {
+        let mut reader = HashingReader::without_hash(Cursor::new(vec![1, 2, 3]));
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(reader.finish().unwrap().0, None);
+    }
+}
@@ -0,0 +1,354 @@
+//! `inspect` subcommand: describe EXR files from their headers without
+//! decoding any pixels.
+
+use exr::meta::attribute::{AttributeValue, ChannelDescription, IntegerBounds, LevelMode, SampleType};
+use exr::meta::header::Header;
+use exr::meta::{BlockDescription, MetaData};
+use exr_thumbnailer::ThumbError;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{is_exr_file, Exit, InspectArgs};
+
+/// Output format of `inspect` and `diff`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrintFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Attributes shown in their own fields instead of the attribute list
+const STRUCTURAL_ATTRIBUTES: &[&str] = &[
+    "channels",
+    "chromaticities",
+    "chunkCount",
+    "compression",
+    "dataWindow",
+    "displayWindow",
+    "lineOrder",
+    "name",
+    "tiles",
+    "type",
+];
+
+#[derive(Debug, Serialize)]
+struct FileInfo {
+    path: String,
+    file_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    parts: Vec<PartInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct PartInfo {
+    name: Option<String>,
+    data_window: Window,
+    display_window: Window,
+    compression: String,
+    line_order: String,
+    /// `scanlines` or `tiles WxH`
+    blocks: String,
+    /// `single`, `mipmap` or `ripmap` with the number of levels
+    levels: String,
+    deep: bool,
+    chromaticities: Option<Value>,
+    layers: Vec<LayerInfo>,
+    /// All other attributes, including custom ones
+    attributes: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct Window {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+}
+
+/// Channels sharing a name prefix such as `diffuse.` in `diffuse.R`
+#[derive(Debug, Serialize)]
+struct LayerInfo {
+    /// `None` for channels without a prefix
+    name: Option<String>,
+    channels: Vec<ChannelInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChannelInfo {
+    name: String,
+    sample_type: &'static str,
+    sampling: [usize; 2],
+    quantize_linearly: bool,
+}
+
+pub fn inspect(args: &InspectArgs) -> io::Result<Exit> {
+    let mut files = Vec::new();
+    for path in &args.paths {
+        if path.is_dir() {
+            let mut folder: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()).filter(|path| is_exr_file(path)))
+                .collect();
+            folder.sort();
+            files.extend(folder);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Error: No EXR files found.");
+        return Ok(Exit::NoInput);
+    }
+
+    let infos: Vec<FileInfo> = files.par_iter().map(|path| FileInfo::read(path)).collect();
+    match args.format {
+        PrintFormat::Text => {
+            for info in &infos {
+                print!("{}", info.to_text());
+            }
+        }
+        PrintFormat::Json => println!("{}", serde_json::to_string_pretty(&infos).map_err(io::Error::other)?),
+    }
+
+    let failed = infos.iter().filter(|info| info.error.is_some()).count();
+    Ok(match failed {
+        0 => Exit::Success,
+        failed if failed == infos.len() => Exit::TotalFailure,
+        _ => Exit::PartialFailure,
+    })
+}
+
+impl FileInfo {
+    fn read(path: &Path) -> Self {
+        let mut info = FileInfo {
+            path: path.display().to_string(),
+            file_size: fs::metadata(path).map_or(0, |meta| meta.len()),
+            error: None,
+            parts: Vec::new(),
+        };
+        match MetaData::read_from_file(path, false) {
+            Ok(meta) => info.parts = meta.headers.iter().map(PartInfo::new).collect(),
+            Err(e) => info.error = Some(ThumbError::from_exr_header(e).to_string()),
+        }
+        info
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("{} ({:.1} MB)\n", self.path, self.file_size as f64 / (1024.0 * 1024.0));
+        if let Some(error) = &self.error {
+            text += &format!("  error: {}\n", error);
+        }
+        for (index, part) in self.parts.iter().enumerate() {
+            match &part.name {
+                Some(name) => text += &format!("  part {} \"{}\"\n", index, name),
+                None => text += &format!("  part {}\n", index),
+            }
+            text += &format!("    data window:    {}\n", part.data_window);
+            text += &format!("    display window: {}\n", part.display_window);
+            text += &format!("    compression:    {}\n", part.compression);
+            text += &format!("    blocks:         {}, {}\n", part.blocks, part.line_order);
+            text += &format!("    levels:         {}\n", part.levels);
+            if part.deep {
+                text += "    deep data\n";
+            }
+            if let Some(chromaticities) = &part.chromaticities {
+                text += &format!("    chromaticities: {}\n", chromaticities);
+            }
+            for layer in &part.layers {
+                let channels: Vec<String> = layer.channels.iter().map(ChannelInfo::to_text).collect();
+                let name = layer.name.as_deref().unwrap_or("(default)");
+                text += &format!("    layer {}: {}\n", name, channels.join(", "));
+            }
+            if !part.attributes.is_empty() {
+                text += "    attributes:\n";
+                for (name, value) in &part.attributes {
+                    text += &format!("      {}: {}\n", name, value);
+                }
+            }
+        }
+        text
+    }
+}
+
+impl PartInfo {
+    fn new(header: &Header) -> Self {
+        let mut layers: Vec<LayerInfo> = Vec::new();
+        for channel in &header.channels.list {
+            let name = channel.name.to_string();
+            let prefix = name.rsplit_once('.').map(|(prefix, _)| prefix.to_string());
+            let channel = ChannelInfo::new(channel);
+            match layers.iter_mut().find(|layer| layer.name == prefix) {
+                Some(layer) => layer.channels.push(channel),
+                None => layers.push(LayerInfo {
+                    name: prefix,
+                    channels: vec![channel],
+                }),
+            }
+        }
+
+        let (blocks, levels) = match header.blocks {
+            BlockDescription::ScanLines => ("scanlines".to_string(), "single".to_string()),
+            BlockDescription::Tiles(tiles) => {
+                let size = header.layer_size;
+                let levels = match tiles.level_mode {
+                    LevelMode::Singular => "single".to_string(),
+                    LevelMode::MipMap => format!(
+                        "mipmap, {} levels",
+                        exr::meta::compute_level_count(tiles.rounding_mode, size.width().max(size.height()))
+                    ),
+                    LevelMode::RipMap => format!(
+                        "ripmap, {}x{} levels",
+                        exr::meta::compute_level_count(tiles.rounding_mode, size.width()),
+                        exr::meta::compute_level_count(tiles.rounding_mode, size.height())
+                    ),
+                };
+                (format!("tiles {}x{}", tiles.tile_size.width(), tiles.tile_size.height()), levels)
+            }
+        };
+
+        let attributes = header
+            .all_named_attributes()
+            .map(|(name, value)| (String::from_utf8_lossy(name).into_owned(), value))
+            .filter(|(name, _)| !STRUCTURAL_ATTRIBUTES.contains(&name.as_str()))
+            .map(|(name, value)| (name, attribute_json(&value)))
+            .collect();
+
+        PartInfo {
+            name: header.own_attributes.layer_name.as_ref().map(|name| name.to_string()),
+            data_window: Window::new(header.data_window()),
+            display_window: Window::new(header.shared_attributes.display_window),
+            // DWA compression levels are listed with the attributes
+            compression: format!("{:?}", header.compression).split('(').next().unwrap_or_default().to_string(),
+            line_order: format!("{:?}", header.line_order).to_lowercase(),
+            blocks,
+            levels,
+            deep: header.deep,
+            chromaticities: header
+                .shared_attributes
+                .chromaticities
+                .map(|chromaticities| attribute_json(&AttributeValue::Chromaticities(chromaticities))),
+            layers,
+            attributes,
+        }
+    }
+}
+
+impl Window {
+    fn new(bounds: IntegerBounds) -> Self {
+        Window {
+            x: bounds.position.x(),
+            y: bounds.position.y(),
+            width: bounds.size.width(),
+            height: bounds.size.height(),
+        }
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{} at ({}, {})", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl ChannelInfo {
+    fn new(channel: &ChannelDescription) -> Self {
+        ChannelInfo {
+            name: channel.name.to_string(),
+            sample_type: match channel.sample_type {
+                SampleType::F16 => "f16",
+                SampleType::F32 => "f32",
+                SampleType::U32 => "u32",
+            },
+            sampling: [channel.sampling.x(), channel.sampling.y()],
+            quantize_linearly: channel.quantize_linearly,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let name = self.name.rsplit_once('.').map_or(self.name.as_str(), |(_, name)| name);
+        let mut text = format!("{} {}", name, self.sample_type);
+        if self.sampling != [1, 1] {
+            text += &format!(" subsampled {}x{}", self.sampling[0], self.sampling[1]);
+        }
+        text
+    }
+}
+
+/// JSON for an attribute value. Types without a natural JSON form are
+/// written as their debug representation.
+fn attribute_json(value: &AttributeValue) -> Value {
+    let vec2 = |x: f32, y: f32| json!([x, y]);
+    match value {
+        AttributeValue::Text(text) => json!(text.to_string()),
+        AttributeValue::TextVector(texts) => json!(texts.iter().map(|text| text.to_string()).collect::<Vec<_>>()),
+        AttributeValue::F32(value) => json!(value),
+        AttributeValue::F64(value) => json!(value),
+        AttributeValue::I32(value) => json!(value),
+        AttributeValue::IntVec2(vec) => json!([vec.x(), vec.y()]),
+        AttributeValue::FloatVec2(vec) => vec2(vec.x(), vec.y()),
+        AttributeValue::IntVec3((x, y, z)) => json!([x, y, z]),
+        AttributeValue::FloatVec3((x, y, z)) => json!([x, y, z]),
+        AttributeValue::Rational((numerator, denominator)) => json!(format!("{}/{}", numerator, denominator)),
+        AttributeValue::Matrix3x3(matrix) => json!(matrix),
+        AttributeValue::Matrix4x4(matrix) => json!(matrix),
+        AttributeValue::IntegerBounds(bounds) => json!(Window::new(*bounds)),
+        AttributeValue::FloatRect(rect) => json!({
+            "min": vec2(rect.min.x(), rect.min.y()),
+            "max": vec2(rect.max.x(), rect.max.y()),
+        }),
+        AttributeValue::Chromaticities(c) => json!({
+            "red": vec2(c.red.x(), c.red.y()),
+            "green": vec2(c.green.x(), c.green.y()),
+            "blue": vec2(c.blue.x(), c.blue.y()),
+            "white": vec2(c.white.x(), c.white.y()),
+        }),
+        AttributeValue::TimeCode(time) => json!(format!(
+            "{:02}:{:02}:{:02}:{:02}",
+            time.hours, time.minutes, time.seconds, time.frame
+        )),
+        AttributeValue::Preview(preview) => json!(format!("{}x{} preview image", preview.size.width(), preview.size.height())),
+        AttributeValue::Bytes { type_hint, bytes } => json!(format!("{} bytes ({})", bytes.len(), type_hint)),
+        AttributeValue::Custom { kind, bytes } => json!(format!("{} bytes of type {}", bytes.len(), kind)),
+        other => json!(format!("{:?}", other)),
+    }
+}
+
+/// EXR files shared with the integration tests
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn headers_describe_layers_channels_and_data_window() {
+        let meta = MetaData::read_from_buffered(Cursor::new(common::ramp_exr()), false).unwrap();
+        let parts: Vec<PartInfo> = meta.headers.iter().map(PartInfo::new).collect();
+        assert_eq!(parts.len(), 1);
+        let part = &parts[0];
+
+        assert_eq!(part.data_window.to_string(), format!("{}x{} at (0, 0)", common::WIDTH, common::HEIGHT));
+        assert_eq!(part.blocks, "tiles 64x64");
+        assert_eq!(part.levels, "single");
+        assert!(!part.deep);
+
+        // RGBA channels have no prefix and form the default layer
+        assert_eq!(part.layers.len(), 1);
+        let layer = &part.layers[0];
+        assert_eq!(layer.name, None);
+        let channels: Vec<String> = layer.channels.iter().map(ChannelInfo::to_text).collect();
+        assert_eq!(channels, ["A f32", "B f32", "G f32", "R f32"]);
+        for attribute in STRUCTURAL_ATTRIBUTES {
+            assert!(!part.attributes.contains_key(*attribute), "{}", attribute);
+        }
+    }
+}
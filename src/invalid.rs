@@ -0,0 +1,101 @@
+//! Detection of NaN, infinite and negative pixel values.
+//!
+//! Broken shaders and comp operations produce values the colour processing
+//! would otherwise clamp away silently. They are counted while decoding, and
+//! replaced by 0 so a single bad pixel does not spread through the averaging.
+
+use serde::Serialize;
+use std::fmt;
+
+/// A reduced pixel covers at least one NaN or infinite value
+pub(crate) const NON_FINITE: u8 = 1;
+/// A reduced pixel covers at least one negative value
+pub(crate) const NEGATIVE: u8 = 2;
+
+/// Colour non-finite values are painted in with [`crate::ThumbnailRequest::flag_invalid`]
+pub const NON_FINITE_COLOUR: [u8; 4] = [255, 0, 255, 255];
+/// Colour negative values are painted in with [`crate::ThumbnailRequest::flag_invalid`]
+pub const NEGATIVE_COLOUR: [u8; 4] = [0, 255, 255, 255];
+
+const CHANNELS: [&str; 4] = ["R", "G", "B", "A"];
+
+/// Numbers of invalid values per channel, in R, G, B, A order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct InvalidValues {
+    pub nan: [u64; 4],
+    pub pos_inf: [u64; 4],
+    pub neg_inf: [u64; 4],
+    /// Finite values below zero
+    pub negative: [u64; 4],
+}
+
+impl InvalidValues {
+    pub fn nan_count(&self) -> u64 {
+        self.nan.iter().sum()
+    }
+
+    /// Positive and negative infinities
+    pub fn inf_count(&self) -> u64 {
+        self.pos_inf.iter().chain(&self.neg_inf).sum()
+    }
+
+    pub fn negative_count(&self) -> u64 {
+        self.negative.iter().sum()
+    }
+
+    /// Whether any value is NaN or infinite
+    pub fn has_non_finite(&self) -> bool {
+        self.nan_count() + self.inf_count() > 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.has_non_finite() && self.negative_count() == 0
+    }
+
+    /// Count the invalid values of `pixel` and return its `NON_FINITE` and
+    /// `NEGATIVE` flags.
+    pub(crate) fn check(&mut self, pixel: [f32; 4]) -> u8 {
+        let mut flags = 0;
+        for (c, value) in pixel.into_iter().enumerate() {
+            if value.is_nan() {
+                self.nan[c] += 1;
+                flags |= NON_FINITE;
+            } else if value == f32::INFINITY {
+                self.pos_inf[c] += 1;
+                flags |= NON_FINITE;
+            } else if value == f32::NEG_INFINITY {
+                self.neg_inf[c] += 1;
+                flags |= NON_FINITE;
+            } else if value < 0.0 {
+                self.negative[c] += 1;
+                flags |= NEGATIVE;
+            }
+        }
+        flags
+    }
+}
+
+/// E.g. `12 NaN (R, G), 3 infinite (A) and 40 negative (B) values`
+impl fmt::Display for InvalidValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let infinite: Vec<u64> = self.pos_inf.iter().zip(&self.neg_inf).map(|(pos, neg)| pos + neg).collect();
+        let parts: Vec<String> = [("NaN", &self.nan[..]), ("infinite", &infinite[..]), ("negative", &self.negative[..])]
+            .into_iter()
+            .filter_map(|(name, counts)| {
+                let total: u64 = counts.iter().sum();
+                let channels: Vec<&str> = CHANNELS
+                    .iter()
+                    .zip(counts)
+                    .filter(|(_, &count)| count > 0)
+                    .map(|(channel, _)| *channel)
+                    .collect();
+                (total > 0).then(|| format!("{} {} ({})", total, name, channels.join(", ")))
+            })
+            .collect();
+        match parts.split_last() {
+            None => write!(f, "no invalid values"),
+            Some((last, [])) => write!(f, "{} values", last),
+            Some((last, rest)) => write!(f, "{} and {} values", rest.join(", "), last),
+        }
+    }
+}
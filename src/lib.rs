@@ -0,0 +1,92 @@
+//! Fast EXR to thumbnail conversion with linear color space support.
+//!
+//! The `exr_thumbnailer` command line tool is a thin wrapper around this
+//! library. A thumbnail is described by a [`ThumbnailRequest`] and generated
+//! in memory:
+//!
+//! ```no_run
+//! use exr_thumbnailer::{OutputFormat, ThumbnailRequest, ThumbnailSize};
+//!
+//! let thumbnail = ThumbnailRequest::new("shot_1001.exr")
+//!     .size(ThumbnailSize::Height(128))
+//!     .format(OutputFormat::Jpeg)
+//!     .thumbnail()?;
+//! std::fs::write("shot_1001.jpg", thumbnail.encode()?)?;
+//! # Ok::<(), exr_thumbnailer::ThumbError>(())
+//! ```
+//!
+//! EXR files already in memory, e.g. fetched from object storage, are handled
+//! by [`ThumbnailRequest::thumbnail_from_bytes`] and
+//! [`ThumbnailRequest::thumbnail_from_reader`], or by [`thumbnail_bytes`]
+//! which returns an encoded PNG.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+pub mod burnin;
+mod color;
+mod decode;
+pub mod compare;
+mod font;
+mod hashing;
+pub mod error;
+pub mod freedesktop;
+mod invalid;
+pub mod provenance;
+pub mod qc;
+mod resample;
+pub mod stats;
+mod thumbnail;
+pub mod timing;
+
+pub use color::{ColorConfig, ToneMap};
+pub use error::{ErrorKind, ThumbError};
+pub use invalid::{InvalidValues, NEGATIVE_COLOUR, NON_FINITE_COLOUR};
+pub use thumbnail::{thumbnail, thumbnail_bytes, Filter, OutputFormat, Thumbnail, ThumbnailRequest, ThumbnailSize};
+
+/// Stable hash of the file contents as a hex string.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:016x}", xxhash_rust::xxh3::xxh3_64(bytes))
+}
+
+/// [`content_hash`] of a file, read in chunks instead of all at once.
+pub fn file_content_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = xxhash_rust::xxh3::Xxh3Default::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// Run `work` on a thread of its own and give up with [`ThumbError::Timeout`]
+/// once it takes longer than `timeout`.
+///
+/// Unlike the checks between blocks of [`ThumbnailRequest::timeout`] this
+/// also covers work stuck inside a block, a filter or an encoder. Such a
+/// thread is abandoned and its result dropped whenever it finishes. Panics
+/// of `work` are passed on to the caller.
+pub fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    work: impl FnOnce() -> Result<T, ThumbError> + Send + 'static,
+) -> Result<T, ThumbError> {
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new().name("thumbnail worker".to_string()).spawn(move || {
+        // The receiver is gone once the work was abandoned
+        let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(work)));
+    })?;
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(result)) => result,
+        Ok(Err(payload)) => panic::resume_unwind(payload),
+        Err(_) => Err(ThumbError::Timeout(format!("took longer than {}s", timeout.as_secs_f64()))),
+    }
+}
@@ -1,292 +1,1045 @@
-use clap::Parser;
-use exr::prelude as exr;
+mod budget;
+mod cancel;
+mod config;
+mod diff;
+mod inspect;
+mod output;
+mod manifest;
+mod parallelism;
+mod priority;
+mod progress;
+mod report;
+mod sequence;
+mod serve;
+mod watch;
+
+use budget::MemoryBudget;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use exr_thumbnailer::burnin::Burnin;
+use exr_thumbnailer::compare::DiffImage;
+use output::Overwrite;
+use exr_thumbnailer::timing::{Stage, TimingStats};
+use exr_thumbnailer::{
+    freedesktop, ColorConfig, Filter, OutputFormat, ThumbError, ThumbnailRequest, ThumbnailSize, ToneMap,
+};
+use manifest::{Freshness, Manifest};
+use parallelism::Parallelism;
+use progress::{Progress, Verbosity};
 use rayon::prelude::*;
-use std::fs::{self, File};
-use std::io::{self, Write};
+use report::{FileRecord, FileStatus, HistogramOutput, ReportFormat, RunReport, TimingSummary};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
-use std::time::{Instant, Duration};
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// A fast EXR to thumbnail converter with linear color space support
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_CODES_HELP)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Source folder containing EXR files
-    #[arg(short = 's', long)]
-    source_folder: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Destination folder for thumbnails
-    #[arg(short = 'd', long)]
-    dest_folder: PathBuf,
+    /// Read settings from this TOML file; the command line and environment take precedence
+    #[arg(long, env = "EXR_THUMBNAILER_CONFIG")]
+    config: Option<PathBuf>,
 
-    /// Height of the thumbnail in pixels (width is scaled proportionally)
-    #[arg(short = 't', long)]
-    height: u32,
+    /// Apply the named [preset.<NAME>] section of the configuration file on top of its top level
+    #[arg(long, env = "EXR_THUMBNAILER_PRESET")]
+    preset: Option<String>,
+
+    /// Print the effective settings as TOML and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Source folder containing EXR files (required in batch mode)
+    #[arg(short = 's', long, env = "EXR_THUMBNAILER_SOURCE_FOLDER")]
+    source_folder: Option<PathBuf>,
+
+    /// Destination folder for thumbnails (required in batch mode)
+    #[arg(short = 'd', long, env = "EXR_THUMBNAILER_DEST_FOLDER")]
+    dest_folder: Option<PathBuf>,
+
+    /// Height of the thumbnail in pixels, width is scaled proportionally (required in batch mode)
+    #[arg(short = 't', long, env = "EXR_THUMBNAILER_HEIGHT", value_parser = clap::value_parser!(u32).range(1..=MAX_HEIGHT as i64))]
+    height: Option<u32>,
+
+    /// Write a freedesktop.org cache thumbnail for a single EXR file (path or file:// URI)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["source_folder", "dest_folder", "height", "watch"])]
+    freedesktop: Option<PathBuf>,
+
+    /// Requested size for --freedesktop: up to 128 uses the normal, up to 256 the large and above that the x-large cache
+    #[arg(long, default_value = "256", requires = "freedesktop", value_parser = clap::value_parser!(u32).range(1..=MAX_HEIGHT as i64))]
+    size: u32,
+
+    /// Write the --freedesktop thumbnail to this file instead of into the thumbnail cache
+    #[arg(long, requires = "freedesktop")]
+    output: Option<PathBuf>,
+
+    /// Filename for the conversion statistics report (default: conversion_stats.<txt|json|csv>)
+    #[arg(short, long, env = "EXR_THUMBNAILER_INFO")]
+    info: Option<String>,
+
+    /// Format of the conversion statistics report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text, env = "EXR_THUMBNAILER_REPORT_FORMAT")]
+    report_format: ReportFormat,
+
+    #[command(flatten)]
+    render: RenderArgs,
+
+    /// Image format of the thumbnails
+    #[arg(long, value_enum, default_value_t = OutputFormat::Png, env = "EXR_THUMBNAILER_FORMAT")]
+    format: OutputFormat,
+
+    /// Layer to convert instead of the first RGBA layer: a part name or a channel prefix such as "diffuse"
+    #[arg(long, env = "EXR_THUMBNAILER_LAYER")]
+    layer: Option<String>,
+
+    /// Number of slowest files listed in the statistics report
+    #[arg(long, default_value = "10", env = "EXR_THUMBNAILER_SLOWEST")]
+    slowest: usize,
+
+    /// Convert fewer files at once so their estimated memory use stays below this many megabytes
+    #[arg(long, value_name = "MB", env = "EXR_THUMBNAILER_MEMORY_LIMIT", value_parser = clap::value_parser!(u64).range(1..))]
+    memory_limit: Option<u64>,
+
+    /// Give up on files whose decoding and encoding take longer than this many seconds and record them as failed
+    #[arg(long, value_name = "SECONDS", env = "EXR_THUMBNAILER_TIMEOUT_PER_FILE", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout_per_file: Option<u64>,
+
+    /// Seconds the files in progress get to finish after Ctrl-C before they are abandoned
+    #[arg(long, value_name = "SECONDS", default_value = "10", env = "EXR_THUMBNAILER_GRACE_PERIOD")]
+    grace_period: u64,
+
+    /// Files converted at the same time (default: chosen from the number of CPUs, files and their sizes)
+    #[arg(short = 'j', long, env = "EXR_THUMBNAILER_JOBS", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    jobs: Option<usize>,
+
+    /// Threads decompressing each file (default: the CPUs not used by --jobs)
+    #[arg(long, env = "EXR_THUMBNAILER_DECODE_THREADS", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    decode_threads: Option<usize>,
+
+    /// Run with a lower process priority so other work on the machine takes precedence
+    #[arg(long, env = "EXR_THUMBNAILER_NICE")]
+    nice: bool,
+
+    /// Fail files containing NaN or infinite values without writing their thumbnail
+    #[arg(long, env = "EXR_THUMBNAILER_FAIL_ON_INVALID")]
+    fail_on_invalid: bool,
+
+    /// Add per-channel min, max, mean and standard deviation and a log-luminance histogram to the report
+    #[arg(long, env = "EXR_THUMBNAILER_STATS")]
+    stats: bool,
+
+    /// Also draw the luminance histogram into an image next to the thumbnail or over its corner; implies --stats
+    #[arg(long, value_enum, default_value_t = HistogramOutput::None, env = "EXR_THUMBNAILER_HISTOGRAM")]
+    histogram: HistogramOutput,
+
+    /// Text to draw into a corner of the thumbnails, e.g. "top-right:{filename} {resolution}" (repeatable).
+    /// Corners are top-left, top-right, bottom-left (default) and bottom-right; placeholders are {filename},
+    /// {frame}, {resolution}, {width}, {height}, {layer}, {date} and {attr:NAME} for EXR header attributes
+    #[arg(long, value_name = "[CORNER:]TEMPLATE", env = "EXR_THUMBNAILER_BURNIN")]
+    burnin: Vec<Burnin>,
+
+    /// Whether existing thumbnails are replaced; files whose thumbnail is kept count as skipped
+    #[arg(long, value_enum, default_value_t = Overwrite::Always, env = "EXR_THUMBNAILER_OVERWRITE")]
+    overwrite: Overwrite,
+
+    /// Do not embed the source path, modification time, hash, resolution, layer and colour settings into the thumbnails
+    #[arg(long, env = "EXR_THUMBNAILER_NO_PROVENANCE")]
+    no_provenance: bool,
+
+    /// Flag frames whose mean brightness differs from the previous frame of their sequence by more than this many stops
+    #[arg(long, value_name = "STOPS", default_value = "1", env = "EXR_THUMBNAILER_JUMP_THRESHOLD", value_parser = parse_threshold)]
+    jump_threshold: f32,
 
-    /// Filename for the conversion statistics report
-    #[arg(short, long, default_value = "conversion_stats.txt")]
-    info: String,
+    /// Skip files whose thumbnail is up to date with the source and settings
+    #[arg(long, env = "EXR_THUMBNAILER_INCREMENTAL")]
+    incremental: bool,
 
-    /// Enable linear color space tone mapping
-    #[arg(short = 'l', long)]
+    /// Keep running and convert new or modified EXR files as they appear
+    #[arg(long)]
+    watch: bool,
+
+    /// Seconds a watched file must stay unchanged before it is converted
+    #[arg(long, default_value = "5", requires = "watch")]
+    debounce_secs: u64,
+
+    /// Seconds between the summaries printed in watch mode
+    #[arg(long, default_value = "60", requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+    summary_interval_secs: u64,
+
+    /// Only report fatal errors: no progress display and no per-file messages
+    #[arg(short = 'q', long, conflicts_with = "verbose", env = "EXR_THUMBNAILER_QUIET")]
+    quiet: bool,
+
+    /// Print a message for every converted file, not only for failures
+    #[arg(short = 'v', long, env = "EXR_THUMBNAILER_VERBOSE")]
+    verbose: bool,
+}
+
+/// Colour and scaling options shared by all modes
+#[derive(clap::Args, Debug)]
+struct RenderArgs {
+    /// Tone mapping applied before gamma correction
+    #[arg(long, value_enum, default_value_t = ToneMap::None, env = "EXR_THUMBNAILER_TONE_MAP")]
+    tone_map: ToneMap,
+
+    /// Shorthand for --tone-map reinhard
+    #[arg(short = 'l', long, conflicts_with = "tone_map", env = "EXR_THUMBNAILER_LINEAR_TONE_MAPPING")]
     linear_tone_mapping: bool,
 
-    /// Gamma value for color correction (default: 2.2)
-    #[arg(short = 'g', long, default_value = "2.2")]
+    /// Gamma value for color correction, greater than 0 and at most 10
+    #[arg(short = 'g', long, default_value = "2.2", env = "EXR_THUMBNAILER_GAMMA", value_parser = parse_gamma)]
     gamma: f32,
 
-    /// Scaling filter algorithm (lanczos3, gaussian, cubic, triangle)
-    #[arg(short = 'f', long, default_value = "lanczos3")]
-    filter: String,
-}
+    /// Scaling filter; sources at least 4x the thumbnail size are box averaged while decoding first, so nearest only picks source pixels of smaller ones; area filters the linear HDR data
+    #[arg(short = 'f', long, value_enum, default_value_t = Filter::Lanczos3, env = "EXR_THUMBNAILER_FILTER")]
+    filter: Filter,
+
+    /// Paint pixels with NaN or infinite values magenta and negative ones cyan
+    #[arg(long, env = "EXR_THUMBNAILER_FLAG_INVALID")]
+    flag_invalid: bool,
 
-/// Statistics for timing operations
-struct TimingStats {
-    total_load_time: AtomicU64,    // Total time for loading/creating thumbnails (in nanoseconds)
-    total_save_time: AtomicU64,    // Total time for saving thumbnails (in nanoseconds)
+    /// Draw a red border around thumbnails of frames that fail QC: black, constant, fireflies or brightness jumps
+    #[arg(long, env = "EXR_THUMBNAILER_QC_BORDER")]
+    qc_border: bool,
+
+    /// Luminance above which isolated pixels count as fireflies
+    #[arg(long, value_name = "LUMINANCE", default_value = "100", env = "EXR_THUMBNAILER_FIREFLY_THRESHOLD", value_parser = parse_threshold)]
+    firefly_threshold: f32,
 }
 
-impl TimingStats {
-    fn new() -> Self {
-        Self {
-            total_load_time: AtomicU64::new(0),
-            total_save_time: AtomicU64::new(0),
+impl RenderArgs {
+    fn tone_map(&self) -> ToneMap {
+        if self.linear_tone_mapping {
+            ToneMap::Reinhard
+        } else {
+            self.tone_map
         }
     }
+}
+
+/// Largest accepted thumbnail height
+const MAX_HEIGHT: u32 = 16384;
+
+fn parse_gamma(value: &str) -> Result<f32, String> {
+    let gamma = value.parse::<f32>().map_err(|e| e.to_string())?;
+    check_gamma(gamma)?;
+    Ok(gamma)
+}
+
+/// Gamma values outside of (0, 10] produce black, white or NaN pixels.
+fn check_gamma(gamma: f32) -> Result<(), String> {
+    if gamma > 0.0 && gamma <= 10.0 {
+        Ok(())
+    } else {
+        Err(format!("gamma {} is not in the range (0, 10]", gamma))
+    }
+}
+
+fn parse_threshold(value: &str) -> Result<f32, String> {
+    let threshold = value.parse::<f32>().map_err(|e| e.to_string())?;
+    check_threshold(threshold)?;
+    Ok(threshold)
+}
+
+fn check_threshold(threshold: f32) -> Result<(), String> {
+    if threshold > 0.0 && threshold.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("threshold {} is not a positive number", threshold))
+    }
+}
 
-    fn add_load_time(&self, duration: Duration) {
-        self.total_load_time.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+fn check_height(height: u32) -> Result<(), String> {
+    if (1..=MAX_HEIGHT).contains(&height) {
+        Ok(())
+    } else {
+        Err(format!("height {} is not in the range 1..={}", height, MAX_HEIGHT))
     }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve thumbnails over HTTP: GET /thumb?path=...&h=256&layer=...&format=png|jpeg|webp
+    Serve(ServeArgs),
+    /// Describe EXR files from their headers: parts, layers, channels, windows, compression and attributes
+    Inspect(InspectArgs),
+    /// Compare EXR files, or the files with the same name in two folders, in linear light for render regression tests
+    Diff(DiffArgs),
+    /// Manage the freedesktop.org thumbnailer entry used by Linux file managers
+    Thumbnailer {
+        #[command(subcommand)]
+        action: ThumbnailerAction,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Folder the requested paths are relative to; files outside of it are never served
+    #[arg(long)]
+    root: PathBuf,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: String,
 
-    fn add_save_time(&self, duration: Duration) {
-        self.total_save_time.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    /// Folder of the thumbnail cache (default: $XDG_CACHE_HOME/exr_thumbnailer)
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Size limit of the thumbnail cache in megabytes; least recently used thumbnails are evicted
+    #[arg(long, default_value = "512")]
+    cache_size_mb: u64,
+
+    /// Thumbnails generated at the same time; further requests get 503 (default: number of worker threads)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_concurrent: Option<usize>,
+
+    #[command(flatten)]
+    render: RenderArgs,
+
+    /// Log every request, not only failures
+    #[arg(short = 'v', long)]
+    verbose: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    /// EXR files, or folders whose EXR files are inspected
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = inspect::PrintFormat::Text)]
+    format: inspect::PrintFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Reference EXR file or folder
+    reference: PathBuf,
+
+    /// Candidate EXR file or folder, compared with the reference
+    candidate: PathBuf,
+
+    /// Folder to write the difference images to; none are written without it
+    #[arg(short = 'o', long)]
+    output: Option<PathBuf>,
+
+    /// Kind of difference image
+    #[arg(long, value_enum, default_value_t = DiffImage::Heatmap)]
+    image: DiffImage,
+
+    /// Height of the difference images
+    #[arg(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..=MAX_HEIGHT as i64))]
+    height: u32,
+
+    /// Layer to compare instead of the first RGBA layer
+    #[arg(long)]
+    layer: Option<String>,
+
+    #[command(flatten)]
+    render: RenderArgs,
+
+    /// Fail pairs whose RMSE exceeds this in any channel
+    #[arg(long, value_name = "RMSE")]
+    max_rmse: Option<f64>,
+
+    /// Fail pairs whose PSNR in dB is below this in any channel
+    #[arg(long, value_name = "DB")]
+    min_psnr: Option<f64>,
+
+    /// Fail pairs whose absolute difference exceeds this anywhere
+    #[arg(long, value_name = "VALUE")]
+    max_abs: Option<f32>,
+
+    /// Pairs compared at the same time (default: number of CPUs)
+    #[arg(short = 'j', long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    jobs: Option<usize>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = inspect::PrintFormat::Text)]
+    format: inspect::PrintFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum ThumbnailerAction {
+    /// Install the .thumbnailer entry so file managers preview EXR files
+    Install {
+        /// Folder to install into (default: $XDG_DATA_HOME/thumbnailers)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+
+        /// Print the entry instead of installing it
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+impl Args {
+    // The batch options are checked by `check_batch_args` once the
+    // configuration file has been applied.
+
+    fn source_folder(&self) -> &Path {
+        self.source_folder.as_deref().expect("source folder is required in batch mode")
     }
 
-    fn get_load_time(&self) -> Duration {
-        Duration::from_nanos(self.total_load_time.load(Ordering::SeqCst))
+    fn dest_folder(&self) -> &Path {
+        self.dest_folder.as_deref().expect("destination folder is required in batch mode")
     }
 
-    fn get_save_time(&self) -> Duration {
-        Duration::from_nanos(self.total_save_time.load(Ordering::SeqCst))
+    fn height(&self) -> u32 {
+        self.height.expect("height is required in batch mode")
     }
 
-    fn get_total_time(&self) -> Duration {
-        self.get_load_time() + self.get_save_time()
+    /// Exit with a usage error if a batch option is missing from the command
+    /// line, environment and configuration file.
+    fn check_batch_args(&self) {
+        let missing: Vec<&str> = [
+            ("--source-folder <SOURCE_FOLDER>", self.source_folder.is_none()),
+            ("--dest-folder <DEST_FOLDER>", self.dest_folder.is_none()),
+            ("--height <HEIGHT>", self.height.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(arg, missing)| missing.then_some(arg))
+        .collect();
+        if !missing.is_empty() {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    format!("the following required arguments were not provided:\n  {}", missing.join("\n  ")),
+                )
+                .exit();
+        }
     }
 }
 
-/// Color processing configuration
-struct ColorConfig {
-    linear_tone_mapping: bool,
-    gamma: f32,
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0    all files converted
+  1    I/O error outside of file conversion (e.g. writing the report)
+  2    usage error
+  3    no input: the source folder is missing or contains no EXR files
+  4    partial failure: some files failed to convert, or contain NaN or
+       infinite values with --fail-on-invalid
+  5    total failure: every file failed to convert
+  130  interrupted with Ctrl-C; the report counts the files not converted
+With --watch, 4 and 5 take precedence over 130 once a file has failed.";
+
+/// Process exit status of a conversion run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    Success = 0,
+    IoError = 1,
+    NoInput = 3,
+    PartialFailure = 4,
+    TotalFailure = 5,
+    Cancelled = 130,
 }
 
-impl ColorConfig {
-    fn new(linear_tone_mapping: bool, gamma: f32) -> Self {
-        Self {
-            linear_tone_mapping,
-            gamma,
+impl Exit {
+    /// Status of a run that wrote `report`.
+    fn for_report(report: &RunReport) -> Self {
+        if report.summary.cancelled {
+            Exit::Cancelled
+        } else if report.summary.total_files == 0 {
+            Exit::NoInput
+        } else if report.summary.failed == report.summary.total_files {
+            Exit::TotalFailure
+        } else if report.summary.failed > 0 {
+            Exit::PartialFailure
+        } else {
+            Exit::Success
+        }
+    }
+
+    /// Status of a `--watch` run. It ends with Ctrl-C, so the files that
+    /// failed since it started decide the status.
+    fn for_watch_report(report: &RunReport) -> Self {
+        match report.summary.failed {
+            0 if report.summary.cancelled => Exit::Cancelled,
+            0 => Exit::Success,
+            failed if failed == report.summary.total_files => Exit::TotalFailure,
+            _ => Exit::PartialFailure,
         }
     }
 }
 
-fn process_exr_file(
-    exr_path: &Path,
-    dest_folder: &Path,
-    height: u32,
-    timing_stats: &TimingStats,
-    color_config: &ColorConfig,
-    filter_type: image::imageops::FilterType,
-) -> Result<PathBuf, String> {
-    let file_name = exr_path.file_name().ok_or("Invalid file name")?;
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+/// Path of the thumbnail generated for `exr_path`.
+fn thumbnail_path(exr_path: &Path, dest_folder: &Path, format: OutputFormat) -> io::Result<PathBuf> {
+    let file_name = exr_path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
     let file_name_str = file_name.to_string_lossy();
     let mut out_path = dest_folder.to_path_buf();
     out_path.push(file_name_str.as_ref());
-    out_path.set_extension("png");
-
-    let load_start = Instant::now();
-
-    // Copy color config data to avoid lifetime issues
-    let linear_tone_mapping = color_config.linear_tone_mapping;
-    let gamma = color_config.gamma;
-
-    // Read the EXR file using the existing working API
-    let reader = exr::read_first_rgba_layer_from_file(
-        exr_path,
-        // A function that generates the pixel data for the image
-        |resolution, _| exr::pixel_vec::PixelVec {
-            resolution,
-            pixels: vec![image::Rgba([0u8; 4]); resolution.width() * resolution.height()],
-        },
-        // A function that fills the previously generated pixel data with color processing
-        move |pixel_vec, position, (r, g, b, a): (f32, f32, f32, f32)| {
-            let index = position.y() * pixel_vec.resolution.width() + position.x();
-            
-            // Process pixel with copied color config
-            let (r, g, b) = if linear_tone_mapping {
-                // Reinhard tone mapping dla HDR
-                let tone_map = |x: f32| x / (1.0 + x);
-                (tone_map(r), tone_map(g), tone_map(b))
-            } else {
-                (r, g, b)
-            };
-
-            // Gamma correction
-            let gamma_correct = |x: f32| x.powf(1.0 / gamma);
-            
-            let processed = [
-                (gamma_correct(r.max(0.0).min(1.0)) * 255.0) as u8,
-                (gamma_correct(g.max(0.0).min(1.0)) * 255.0) as u8,
-                (gamma_correct(b.max(0.0).min(1.0)) * 255.0) as u8,
-                (a.max(0.0).min(1.0) * 255.0) as u8,
-            ];
-            
-            pixel_vec.pixels[index] = image::Rgba(processed);
-        },
-    )
-    .map_err(|e| e.to_string())?;
-
-    // Access the pixel data correctly
-    let image_data = reader.layer_data.channel_data.pixels;
-    let (width, img_height) = (
-        image_data.resolution.width() as u32,
-        image_data.resolution.height() as u32,
-    );
-
-    let thumb_width = (width as f32 / img_height as f32 * height as f32) as u32;
-
-    // Create a dynamic image from the raw pixel data
-    let img = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
-        width,
-        img_height,
-        image_data.pixels.into_iter().flat_map(|rgba| rgba.0).collect::<Vec<u8>>(),
+    out_path.set_extension(format.extension());
+    Ok(out_path)
+}
+
+/// Size of the `--histogram image` files
+const HISTOGRAM_WIDTH: u32 = 320;
+const HISTOGRAM_HEIGHT: u32 = 120;
+
+/// `<name>_histogram.png` next to the thumbnail `<name>.<ext>`
+fn histogram_path(thumbnail_path: &Path) -> PathBuf {
+    let stem = thumbnail_path.file_stem().unwrap_or_default().to_string_lossy();
+    thumbnail_path.with_file_name(format!("{}_histogram.png", stem))
+}
+
+/// Settings that affect the thumbnail contents, recorded in the manifest.
+fn settings_fingerprint(args: &Args) -> String {
+    let render = &args.render;
+    let qc_border = if render.qc_border {
+        format!(";qc_border;firefly_threshold={}", render.firefly_threshold)
+    } else {
+        String::new()
+    };
+    let burnin = if args.burnin.is_empty() {
+        String::new()
+    } else {
+        format!(";burnin={}", args.burnin.iter().map(Burnin::to_string).collect::<Vec<_>>().join("|"))
+    };
+    format!(
+        "height={};tone_map={};gamma={};filter={};layer={}{}{}{}{}{}",
+        args.height(),
+        render.tone_map().as_str(),
+        render.gamma,
+        render.filter.as_str(),
+        args.layer.as_deref().unwrap_or(""),
+        if render.flag_invalid { ";flag_invalid" } else { "" },
+        qc_border,
+        if args.histogram == HistogramOutput::Overlay { ";histogram_overlay" } else { "" },
+        burnin,
+        if args.no_provenance { ";no_provenance" } else { "" }
     )
-    .ok_or("Could not create image buffer")?;
+}
 
-    // Resize the image using the specified filter
-    let thumbnail = image::imageops::resize(&img, thumb_width, height, filter_type);
+/// Check whether the thumbnail of `exr_path` is still up to date and return a
+/// skipped record if so. Files whose freshness cannot be determined are
+/// converted again.
+fn skip_if_up_to_date(
+    manifest: &Mutex<Manifest>,
+    exr_path: &Path,
+    dest_folder: &Path,
+    format: OutputFormat,
+    settings: &str,
+) -> Option<FileRecord> {
+    let out_path = thumbnail_path(exr_path, dest_folder, format).ok()?;
+    let recorded = manifest.lock().unwrap().get(exr_path);
+    match manifest::freshness(&recorded, exr_path, &out_path, settings).ok()? {
+        Freshness::Stale => None,
+        Freshness::UpToDate => Some(FileRecord::skipped(exr_path, &out_path)),
+        Freshness::UpToDateTouched(entry) => {
+            manifest.lock().unwrap().insert(exr_path, entry);
+            Some(FileRecord::skipped(exr_path, &out_path))
+        }
+    }
+}
 
-    let load_duration = load_start.elapsed();
-    timing_stats.add_load_time(load_duration);
+/// Run `convert` and turn a panic into [`ThumbError::Panic`], so a malformed
+/// file does not take down the other files of the batch.
+fn catch_panic(convert: impl FnOnce() -> Result<FileRecord, ThumbError>) -> Result<FileRecord, ThumbError> {
+    panic::catch_unwind(AssertUnwindSafe(convert)).unwrap_or_else(|payload| Err(ThumbError::Panic(panic_message(payload))))
+}
 
-    let save_start = Instant::now();
-    thumbnail.save(&out_path).map_err(|e| e.to_string())?;
-    let save_duration = save_start.elapsed();
-    timing_stats.add_save_time(save_duration);
+/// Message of a caught panic.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("unknown cause", |message| message).to_string(),
+    }
+}
 
-    Ok(out_path)
+/// Whether `path` looks like an EXR file.
+fn is_exr_file(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("exr"))
+}
+
+/// Colour and scaling settings of a request, without a source or size.
+fn render_request(args: &RenderArgs) -> ThumbnailRequest {
+    ThumbnailRequest::default()
+        .color(ColorConfig::new(args.tone_map(), args.gamma))
+        .filter(args.filter)
+        .flag_invalid(args.flag_invalid)
+        .qc_border(args.qc_border)
+        .firefly_threshold(args.firefly_threshold)
+}
+
+/// Thumbnail settings shared by every file, without a source.
+fn thumbnail_request(args: &Args, size: ThumbnailSize) -> ThumbnailRequest {
+    render_request(&args.render)
+        .size(size)
+        .maybe_layer(args.layer.clone())
+        .format(args.format)
+        .pixel_stats(args.stats || args.histogram != HistogramOutput::None)
+        .histogram_overlay(args.histogram == HistogramOutput::Overlay)
+        .burnin(args.burnin.clone())
+        .provenance(!args.no_provenance)
+        .timeout(args.timeout_per_file.map(Duration::from_secs))
+}
+
+/// Settings and state shared by all files of a conversion run
+struct Batch<'a> {
+    args: &'a Args,
+    settings: String,
+    manifest: Mutex<Manifest>,
+    timing_stats: TimingStats,
+    request: ThumbnailRequest,
+    memory: Option<MemoryBudget>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(args: &'a Args, parallelism: Parallelism, cancel: Option<Arc<AtomicBool>>) -> Self {
+        Self {
+            args,
+            settings: settings_fingerprint(args),
+            manifest: Mutex::new(Manifest::load(args.dest_folder())),
+            timing_stats: TimingStats::new(),
+            request: thumbnail_request(args, ThumbnailSize::Height(args.height()))
+                .decode_threads(parallelism.decode_threads)
+                .concurrent_files(parallelism.jobs)
+                .cancel(cancel),
+            memory: args.memory_limit.map(|megabytes| MemoryBudget::new(megabytes * 1024 * 1024)),
+        }
+    }
+
+    /// Convert a single file, or skip it if it is up to date in incremental
+    /// mode or its thumbnail is kept by the overwrite policy. Returns `None`
+    /// for files left unconverted because the run was interrupted.
+    fn convert(&self, exr_path: &Path, progress: &Progress) -> Option<FileRecord> {
+        let args = self.args;
+        if cancel::requested() {
+            return None;
+        }
+        if args.incremental {
+            if let Some(record) =
+                skip_if_up_to_date(&self.manifest, exr_path, args.dest_folder(), args.format, &self.settings)
+            {
+                progress.file_done(&record);
+                return Some(record);
+            }
+        }
+        if let Ok(out_path) = thumbnail_path(exr_path, args.dest_folder(), args.format) {
+            if args.overwrite.keeps(exr_path, &out_path) {
+                let record = FileRecord::skipped(exr_path, &out_path);
+                progress.file_done(&record);
+                return Some(record);
+            }
+        }
+        // Files whose headers cannot be read fail quickly and use no memory
+        let _reservation = self.memory.as_ref().map(|memory| {
+            let estimate = self.request.clone().source(exr_path).memory_estimate().unwrap_or(0);
+            memory.reserve(estimate)
+        });
+        // The run may have been interrupted while waiting for memory
+        if cancel::requested() {
+            return None;
+        }
+        let record = match catch_panic(|| self.process(exr_path)) {
+            Ok(record) => record,
+            Err(ThumbError::Cancelled) => return None,
+            Err(e) => FileRecord::failed(exr_path, &e),
+        };
+        if let (FileStatus::Ok, Some(hash)) = (record.status, &record.source_hash) {
+            if let Ok(entry) = manifest::entry_for(exr_path, hash.clone(), &self.settings) {
+                self.manifest.lock().unwrap().insert(exr_path, entry);
+            }
+        }
+        progress.file_done(&record);
+        Some(record)
+    }
+
+    /// Convert a single file and write its thumbnail, unless it contains NaN
+    /// or infinite values with `--fail-on-invalid`.
+    fn process(&self, exr_path: &Path) -> Result<FileRecord, ThumbError> {
+        let args = self.args;
+        let mut record = FileRecord::new(exr_path);
+        let out_path = thumbnail_path(exr_path, args.dest_folder(), args.format)?;
+
+        // A stuck encoder is given up on like a stuck decoder, while the files
+        // are only written by this thread, never by an abandoned one
+        let request = self.request.clone().source(exr_path);
+        let generate = move || {
+            let thumbnail = request.thumbnail()?;
+            let stage_start = Instant::now();
+            let encoded = thumbnail.encode()?;
+            Ok((thumbnail, encoded, stage_start.elapsed()))
+        };
+        let (thumbnail, encoded, encode_time) = match args.timeout_per_file.map(Duration::from_secs) {
+            Some(timeout) => exr_thumbnailer::with_timeout(timeout, generate)?,
+            None => generate()?,
+        };
+        let mut timings = thumbnail.timings;
+        timings.set(Stage::Encode, encode_time);
+
+        if args.fail_on_invalid && thumbnail.invalid.has_non_finite() {
+            record.set_failed(&ThumbError::InvalidPixels(thumbnail.invalid.to_string()));
+        } else {
+            let stage_start = Instant::now();
+            output::write_atomic(&out_path, &encoded)?;
+            if let (HistogramOutput::Image, Some(stats)) = (args.histogram, &thumbnail.pixel_stats) {
+                let histogram_path = histogram_path(&out_path);
+                output::save_atomic(stats.histogram_image(HISTOGRAM_WIDTH, HISTOGRAM_HEIGHT), &histogram_path)?;
+                record.histogram_image = Some(histogram_path.display().to_string());
+            }
+            timings.set(Stage::Write, stage_start.elapsed());
+            record.bytes_out = encoded.len() as u64;
+            record.output = Some(out_path.display().to_string());
+        }
+
+        self.timing_stats.add(&timings);
+
+        record.source_width = thumbnail.source_width;
+        record.source_height = thumbnail.source_height;
+        record.thumb_width = thumbnail.image.width();
+        record.thumb_height = thumbnail.image.height();
+        record.layer = thumbnail.layer;
+        record.set_timings(&timings);
+        record.bytes_in = thumbnail.source_bytes;
+        record.source_hash = thumbnail.source_hash;
+        record.invalid_values = thumbnail.invalid;
+        record.mean_luminance = thumbnail.stats.mean_luminance;
+        record.fireflies = thumbnail.stats.fireflies;
+        record.qc = thumbnail.stats.issues();
+        record.pixel_stats = thumbnail.pixel_stats;
+
+        Ok(record)
+    }
+
+    fn save_manifest(&self) {
+        if let Err(e) = self.manifest.lock().unwrap().save(self.args.dest_folder()) {
+            eprintln!("Warning: could not write the thumbnail manifest: {}", e);
+        }
+    }
+
+    /// Build the run report and write it into the destination folder. An
+    /// interrupted run is marked as cancelled with the `remaining` files that
+    /// were not converted.
+    fn write_report(
+        &self,
+        records: Vec<FileRecord>,
+        remaining: usize,
+        total_duration: Duration,
+    ) -> io::Result<(RunReport, PathBuf)> {
+        let args = self.args;
+        let mut report = RunReport::new(
+            args.source_folder(),
+            args.dest_folder(),
+            args.height(),
+            TimingSummary::new(total_duration, &self.timing_stats, &records, args.slowest),
+            records,
+        );
+        if cancel::requested() {
+            report.set_cancelled(remaining);
+        }
+
+        // Write detailed statistics to info file
+        let stats_name = args
+            .info
+            .clone()
+            .unwrap_or_else(|| format!("conversion_stats.{}", args.report_format.extension()));
+        let stats_path = args.dest_folder().join(stats_name);
+        report.write(&stats_path, args.report_format)?;
+        Ok((report, stats_path))
+    }
+}
+
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Err(e) = config::apply(&mut args, &matches) {
+        Args::command().error(clap::error::ErrorKind::InvalidValue, e).exit();
+    }
+    if args.print_config {
+        print!("{}", config::Settings::effective(&args).to_toml());
+        return Exit::Success.into();
+    }
+    if args.command.is_none() && args.freedesktop.is_none() {
+        args.check_batch_args();
+    }
+    if args.nice {
+        if let Err(e) = priority::lower() {
+            eprintln!("Warning: could not lower the process priority: {}", e);
+        }
+    }
+
+    match run(&args) {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Exit::IoError.into()
+        }
+    }
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
+fn run(args: &Args) -> io::Result<Exit> {
+    match &args.command {
+        Some(Command::Serve(serve_args)) => return serve::serve(serve_args),
+        Some(Command::Inspect(inspect_args)) => return inspect::inspect(inspect_args),
+        Some(Command::Diff(diff_args)) => return diff::diff(diff_args),
+        Some(Command::Thumbnailer { action }) => return run_thumbnailer_command(action),
+        None => {}
+    }
+    if let Some(file) = &args.freedesktop {
+        return run_freedesktop(args, file);
+    }
+
     let start_time = Instant::now();
 
-    if !args.source_folder.is_dir() {
+    if !args.source_folder().is_dir() {
         eprintln!("Error: Source path is not a valid directory.");
-        return Ok(());
+        return Ok(Exit::NoInput);
     }
 
-    fs::create_dir_all(&args.dest_folder)?;
+    fs::create_dir_all(args.dest_folder())?;
 
-    let color_config = ColorConfig::new(args.linear_tone_mapping, args.gamma);
+    // Find all EXR files
+    let exr_files: Vec<PathBuf> = fs::read_dir(args.source_folder())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()).filter(|path| is_exr_file(path)))
+        .collect();
 
-    // Parsowanie filtru skalowania
-    let filter_type = match args.filter.as_str() {
-        "lanczos3" => image::imageops::FilterType::Lanczos3,
-        "gaussian" => image::imageops::FilterType::Gaussian,
-        "cubic" => image::imageops::FilterType::CatmullRom,
-        "triangle" => image::imageops::FilterType::Triangle,
-        _ => {
-            eprintln!("Warning: Unknown filter '{}', using Lanczos3", args.filter);
-            image::imageops::FilterType::Lanczos3
+    let parallelism = Parallelism::choose(args.jobs, args.decode_threads, &exr_files);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(parallelism.jobs)
+        .build()
+        .map_err(io::Error::other)?;
+    let cancel = match cancel::install(Duration::from_secs(args.grace_period)) {
+        Ok(cancel) => Some(cancel),
+        Err(e) => {
+            eprintln!("Warning: could not install the Ctrl-C handler: {}", e);
+            None
         }
     };
+    let batch = Batch::new(args, parallelism, cancel);
 
-    // Find all EXR files
-    let exr_files: Vec<PathBuf> = fs::read_dir(&args.source_folder)?
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                let path = e.path();
-                if path.is_file() && path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("exr")) {
-                    Some(path)
-                } else {
-                    None
+    let total_files = exr_files.len();
+    let verbosity = Verbosity::from_flags(args.quiet, args.verbose);
+    let progress = Progress::new(total_files, verbosity);
+
+    if verbosity > Verbosity::Quiet {
+        println!(
+            "Found {} EXR files. Starting conversion to {}px height thumbnails...",
+            total_files,
+            args.height()
+        );
+    }
+    if verbosity == Verbosity::Verbose {
+        println!(
+            "Converting {} files at a time with {} decode threads each",
+            parallelism.jobs, parallelism.decode_threads
+        );
+    }
+
+    // Process files in parallel while a separate thread draws the progress
+    let mut records: Vec<FileRecord> = std::thread::scope(|scope| {
+        scope.spawn(|| progress.run());
+        let records =
+            pool.install(|| exr_files.par_iter().filter_map(|exr_path| batch.convert(exr_path, &progress)).collect());
+        progress.finish();
+        records
+    });
+    let remaining = total_files - records.len();
+
+    let border = args.render.qc_border.then_some(&batch.request);
+    let jumps = sequence::flag_brightness_jumps(&mut records, args.jump_threshold as f64, border);
+    if verbosity > Verbosity::Quiet {
+        for index in jumps {
+            eprintln!("Warning: {} failed QC: brightness jump", records[index].source);
+        }
+    }
+
+    if args.watch && !cancel::requested() {
+        let progress = Progress::new(0, verbosity);
+        return pool
+            .install(|| watch::watch(&batch, &progress, records, start_time))
+            .map(|report| Exit::for_watch_report(&report));
+    }
+
+    let total_duration = start_time.elapsed();
+    batch.save_manifest();
+    let (report, stats_path) = batch.write_report(records, remaining, total_duration)?;
+    let timing_stats = &batch.timing_stats;
+
+    if verbosity > Verbosity::Quiet {
+        println!("\n=== Conversion Statistics ===");
+        println!("Total execution time: {:.2}ms", total_duration.as_millis());
+        println!("Processing time breakdown (parallel processing, sum of all files):");
+        for stage in Stage::ALL {
+            println!("  - {}: {:.2}ms", stage.name(), timing_stats.get(stage).as_millis());
+        }
+        println!("  - Total processing: {:.2}ms", timing_stats.get_total_time().as_millis());
+        println!(
+            "Files: Success: {}, Skipped: {}, Failure: {}",
+            report.summary.succeeded, report.summary.skipped, report.summary.failed
+        );
+        if report.summary.cancelled {
+            println!("Interrupted: {} files not converted", report.summary.remaining);
+        }
+        println!("\nNote: Times are summed across all files due to parallel processing.");
+        println!("Total execution time is much shorter than sum of individual file times.");
+        println!("Detailed statistics saved to {}", stats_path.display());
+    }
+
+    Ok(Exit::for_report(&report))
+}
+
+/// Generate the freedesktop.org thumbnail of a single file.
+fn run_freedesktop(args: &Args, file: &Path) -> io::Result<Exit> {
+    let source = freedesktop::path_from_arg(file);
+    if !source.is_file() {
+        eprintln!("Error: {} is not a file.", source.display());
+        return Ok(Exit::NoInput);
+    }
+
+    let result = (|| -> Result<PathBuf, ThumbError> {
+        let uri = freedesktop::file_uri(&source)?;
+        let (flavor, max_edge) = freedesktop::flavor(args.size);
+        let out_path = match &args.output {
+            Some(output) => output.clone(),
+            None => {
+                let out_path = freedesktop::cache_path(&uri, flavor)?;
+                if let Some(parent) = out_path.parent() {
+                    freedesktop::create_private_dir(parent)?;
                 }
-            })
-        })
-        .collect();
+                out_path
+            }
+        };
 
-    let total_files = exr_files.len();
-    let success_count = AtomicUsize::new(0);
-    let failure_count = AtomicUsize::new(0);
-    let timing_stats = TimingStats::new();
-
-    println!(
-        "Found {} EXR files. Starting conversion to {}px height thumbnails...",
-        total_files, args.height
-    );
-
-    // Process files in parallel
-    exr_files.par_iter().for_each(|exr_path| {
-        match process_exr_file(exr_path, &args.dest_folder, args.height, &timing_stats, &color_config, filter_type) {
-            Ok(thumb_path) => {
-                println!("Successfully created thumbnail: {}", thumb_path.display());
-                success_count.fetch_add(1, Ordering::SeqCst);
+        let thumbnail = thumbnail_request(args, ThumbnailSize::Fit(max_edge))
+            .decode_threads(args.decode_threads.unwrap_or(0))
+            .source(&source)
+            .thumbnail()?;
+        let source_size = (thumbnail.source_width, thumbnail.source_height);
+        freedesktop::write_thumbnail(&thumbnail.image, &out_path, &source, &uri, source_size)?;
+        Ok(out_path)
+    })();
+
+    match result {
+        Ok(out_path) => {
+            if !args.quiet {
+                println!("{}", out_path.display());
             }
-            Err(e) => {
-                eprintln!("Failed to process {}: {}", exr_path.display(), e);
-                failure_count.fetch_add(1, Ordering::SeqCst);
+            Ok(Exit::Success)
+        }
+        Err(e) => {
+            eprintln!("Failed to process {}: {}", source.display(), e);
+            Ok(Exit::TotalFailure)
+        }
+    }
+}
+
+fn run_thumbnailer_command(action: &ThumbnailerAction) -> io::Result<Exit> {
+    match action {
+        ThumbnailerAction::Install { dir, print } => {
+            if *print {
+                print!("{}", freedesktop::thumbnailer_entry(&std::env::current_exe()?));
+            } else {
+                let path = freedesktop::install_thumbnailer(dir.as_deref())?;
+                println!("Installed thumbnailer entry {}", path.display());
             }
         }
-    });
+    }
+    Ok(Exit::Success)
+}
 
-    let total_duration = start_time.elapsed();
-    let successes = success_count.load(Ordering::SeqCst);
-    let failures = failure_count.load(Ordering::SeqCst);
-    
-    // Get timing statistics
-    let load_time = timing_stats.get_load_time();
-    let save_time = timing_stats.get_save_time();
-    let processing_time = timing_stats.get_total_time();
-
-    println!("\n=== Conversion Statistics ===");
-    println!("Total execution time: {:.2}ms", total_duration.as_millis());
-    println!("Processing time breakdown (parallel processing):");
-    println!("  - Loading/Creation: {:.2}ms (sum of all files)", load_time.as_millis());
-    println!("  - Saving: {:.2}ms (sum of all files)", save_time.as_millis());
-    println!("  - Total processing: {:.2}ms (sum of all files)", processing_time.as_millis());
-    println!("Files: Success: {}, Failure: {}", successes, failures);
-    println!("\nNote: Times are summed across all files due to parallel processing.");
-    println!("Total execution time is much shorter than sum of individual file times.");
-
-    // Write detailed statistics to info file
-    let stats_path = args.dest_folder.join(&args.info);
-    let mut stats_file = File::create(&stats_path)?;
-    writeln!(stats_file, "=== EXR to Thumbnail Conversion Statistics ===")?;
-    writeln!(stats_file, "Source Folder: {}", args.source_folder.display())?;
-    writeln!(stats_file, "Destination Folder: {}", args.dest_folder.display())?;
-    writeln!(stats_file, "Target Thumbnail Height: {}px", args.height)?;
-    writeln!(stats_file, "============================================")?;
-    writeln!(stats_file, "Total files found: {}", total_files)?;
-    writeln!(stats_file, "Successfully converted: {}", successes)?;
-    writeln!(stats_file, "Failed to convert: {}", failures)?;
-    writeln!(stats_file, "============================================")?;
-    writeln!(stats_file, "Timing Breakdown (Parallel Processing):")?;
-    writeln!(stats_file, "  Total execution time: {:.2}ms", total_duration.as_millis())?;
-    writeln!(stats_file, "  Loading/Creation time: {:.2}ms (sum of all files)", load_time.as_millis())?;
-    writeln!(stats_file, "  Saving time: {:.2}ms (sum of all files)", save_time.as_millis())?;
-    writeln!(stats_file, "  Total processing time: {:.2}ms (sum of all files)", processing_time.as_millis())?;
-    writeln!(stats_file, "")?;
-    writeln!(stats_file, "Note: Due to parallel processing, total execution time is much shorter")?;
-    writeln!(stats_file, "than the sum of individual file processing times.")?;
-    if total_files > 0 {
-        writeln!(stats_file, "  Average load time per file: {:.2}ms", (load_time.as_millis() as f64 / total_files as f64))?;
-        writeln!(stats_file, "  Average save time per file: {:.2}ms", (save_time.as_millis() as f64 / total_files as f64))?;
-        writeln!(stats_file, "  Average total time per file: {:.2}ms", (processing_time.as_millis() as f64 / total_files as f64))?;
-    }
-    writeln!(stats_file, "============================================")?;
-
-    println!("Detailed statistics saved to {}", stats_path.display());
-
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr_thumbnailer::ErrorKind;
+
+    #[test]
+    fn a_panicking_file_fails_without_stopping_the_batch() {
+        let files = ["a.exr", "b.exr", "c.exr"].map(PathBuf::from);
+        let records: Vec<FileRecord> = files
+            .par_iter()
+            .map(|path| {
+                let result = catch_panic(|| match path.to_str() {
+                    Some("b.exr") => panic!("malformed tile in {}", path.display()),
+                    _ => Ok(FileRecord::new(path)),
+                });
+                result.unwrap_or_else(|e| FileRecord::failed(path, &e))
+            })
+            .collect();
+
+        let statuses: Vec<FileStatus> = records.iter().map(|record| record.status).collect();
+        assert_eq!(statuses, [FileStatus::Ok, FileStatus::Failed, FileStatus::Ok]);
+        assert_eq!(records[1].error_kind, Some(ErrorKind::Panic));
+        assert_eq!(records[1].error.as_deref(), Some("panic: malformed tile in b.exr"));
+    }
+
+    #[test]
+    fn files_failing_on_invalid_values_are_not_skipped_by_the_next_incremental_run() {
+        use exr::prelude::*;
+
+        let dir = std::env::temp_dir().join(format!("exr_thumbnailer_main_incremental_{}", std::process::id()));
+        let (source_folder, dest_folder) = (dir.join("exr"), dir.join("thumbs"));
+        fs::create_dir_all(&source_folder).unwrap();
+        let source = source_folder.join("shot.exr");
+        let channels = SpecificChannels::rgba(|position: Vec2<usize>| {
+            let red = if position.x() == 0 { f32::NAN } else { 0.5 };
+            (red, 0.5, 0.5, 1.0)
+        });
+        Image::from_channels((8, 8), channels).write().to_file(&source).unwrap();
+        let args = Args::try_parse_from([
+            "exr_thumbnailer".as_ref(),
+            "--source-folder".as_ref(),
+            source_folder.as_os_str(),
+            "--dest-folder".as_ref(),
+            dest_folder.as_os_str(),
+            "--height=8".as_ref(),
+            "--incremental".as_ref(),
+            "--fail-on-invalid".as_ref(),
+        ])
+        .unwrap();
+        fs::create_dir_all(&dest_folder).unwrap();
+
+        for _ in 0..2 {
+            let parallelism = Parallelism { jobs: 1, decode_threads: 1 };
+            let batch = Batch::new(&args, parallelism, None);
+            let record = batch.convert(&source, &Progress::new(1, Verbosity::Quiet)).unwrap();
+            batch.save_manifest();
+            assert_eq!(record.status, FileStatus::Failed);
+            assert_eq!(record.error_kind, Some(ErrorKind::InvalidPixels));
+            assert_eq!(record.invalid_values.nan[0], 8);
+            assert_eq!(record.output, None);
+            assert!(!dest_folder.join("shot.png").exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_files_decide_the_status_of_a_watch_run() {
+        let report = |records: Vec<FileRecord>| {
+            let timing = TimingSummary::new(Duration::ZERO, &TimingStats::new(), &records, 0);
+            let mut report = RunReport::new(Path::new("exr"), Path::new("thumbs"), 8, timing, records);
+            report.set_cancelled(1);
+            report
+        };
+        let converted = FileRecord::new(Path::new("a.exr"));
+        let failed = FileRecord::failed(Path::new("b.exr"), &ThumbError::Timeout("took longer than 1s".to_string()));
+
+        assert_eq!(Exit::for_watch_report(&report(vec![converted.clone()])), Exit::Cancelled);
+        let mixed = report(vec![converted, failed]);
+        assert_eq!(Exit::for_report(&mixed), Exit::Cancelled);
+        assert_eq!(Exit::for_watch_report(&mixed), Exit::PartialFailure);
+    }
+
+    #[test]
+    fn panics_under_the_timeout_are_caught() {
+        let result = catch_panic(|| exr_thumbnailer::with_timeout(Duration::from_secs(60), || panic!("stuck encoder")));
+        assert_eq!(result.unwrap_err().to_string(), "panic: stuck encoder");
+    }
+}
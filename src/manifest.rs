@@ -0,0 +1,281 @@
+//! Sidecar manifest used by `--incremental` to skip up-to-date thumbnails.
+//!
+//! The manifest is a JSON file in the destination folder that records, for
+//! every thumbnail, the size, modification time and content hash of the EXR
+//! it was generated from together with the settings used.
+
+use exr_thumbnailer::file_content_hash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// File name of the manifest inside the destination folder
+pub const MANIFEST_FILE_NAME: &str = ".exr_thumbnailer_manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+/// What a thumbnail was generated from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub source_size: u64,
+    pub source_mtime_ns: u64,
+    pub source_hash: String,
+    pub settings: String,
+}
+
+/// Whether an existing thumbnail can be kept
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// The thumbnail must be (re)generated
+    Stale,
+    /// The thumbnail matches the source and settings
+    UpToDate,
+    /// The thumbnail matches, but the manifest entry needs the new mtime
+    UpToDateTouched(ManifestEntry),
+}
+
+/// What the manifest records about a source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Recorded {
+    Entry(ManifestEntry),
+    /// The manifest has no entry for the source, e.g. because it failed
+    Missing,
+    /// There is no manifest, the thumbnails predate `--incremental`
+    NoManifest,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    /// Entries keyed by the file name of the source EXR
+    entries: BTreeMap<String, ManifestEntry>,
+    /// Whether the manifest was read from the destination folder
+    #[serde(skip)]
+    loaded: bool,
+}
+
+impl Manifest {
+    pub fn path(dest_folder: &Path) -> PathBuf {
+        dest_folder.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Load the manifest of `dest_folder`, or an empty one if there is none or
+    /// it cannot be parsed.
+    pub fn load(dest_folder: &Path) -> Self {
+        fs::read(Self::path(dest_folder))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Manifest>(&bytes).ok())
+            .filter(|manifest| manifest.version == MANIFEST_VERSION)
+            .map(|manifest| Manifest { loaded: true, ..manifest })
+            .unwrap_or_default()
+    }
+
+    /// Write the manifest atomically into `dest_folder`.
+    pub fn save(&mut self, dest_folder: &Path) -> io::Result<()> {
+        self.version = MANIFEST_VERSION;
+        let path = Self::path(dest_folder);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temp_path, &path)
+    }
+
+    pub fn insert(&mut self, source: &Path, entry: ManifestEntry) {
+        self.entries.insert(key(source), entry);
+    }
+
+    pub fn get(&self, source: &Path) -> Recorded {
+        match self.entries.get(&key(source)) {
+            Some(entry) => Recorded::Entry(entry.clone()),
+            None if self.loaded => Recorded::Missing,
+            None => Recorded::NoManifest,
+        }
+    }
+}
+
+/// Decide whether the thumbnail `output` of `source` is up to date with the
+/// given `settings`.
+///
+/// With a manifest entry the source size, mtime and settings must match; if
+/// only the mtime changed the content hash decides. Sources missing from the
+/// manifest are always converted, while without a manifest the thumbnail
+/// must merely be newer than the source.
+pub fn freshness(recorded: &Recorded, source: &Path, output: &Path, settings: &str) -> io::Result<Freshness> {
+    let output_meta = match fs::metadata(output) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Freshness::Stale),
+        Err(e) => return Err(e),
+    };
+    let source_meta = fs::metadata(source)?;
+
+    let entry = match recorded {
+        Recorded::Entry(entry) => entry,
+        Recorded::Missing => return Ok(Freshness::Stale),
+        Recorded::NoManifest => {
+            let up_to_date = output_meta.modified()? >= source_meta.modified()?;
+            return Ok(if up_to_date { Freshness::UpToDate } else { Freshness::Stale });
+        }
+    };
+
+    if entry.settings != settings || entry.source_size != source_meta.len() {
+        return Ok(Freshness::Stale);
+    }
+    let mtime_ns = mtime_ns(&source_meta)?;
+    if entry.source_mtime_ns == mtime_ns {
+        return Ok(Freshness::UpToDate);
+    }
+    if file_content_hash(source)? == entry.source_hash {
+        Ok(Freshness::UpToDateTouched(ManifestEntry {
+            source_mtime_ns: mtime_ns,
+            ..entry.clone()
+        }))
+    } else {
+        Ok(Freshness::Stale)
+    }
+}
+
+/// Build the manifest entry of a freshly converted `source`.
+pub fn entry_for(source: &Path, source_hash: String, settings: &str) -> io::Result<ManifestEntry> {
+    let meta = fs::metadata(source)?;
+    Ok(ManifestEntry {
+        source_size: meta.len(),
+        source_mtime_ns: mtime_ns(&meta)?,
+        source_hash,
+        settings: settings.to_string(),
+    })
+}
+
+fn key(source: &Path) -> String {
+    source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn mtime_ns(meta: &fs::Metadata) -> io::Result<u64> {
+    let since_epoch = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(since_epoch.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    const SETTINGS: &str = "height=32;tone_map=none;gamma=2.2;filter=lanczos3;layer=";
+
+    /// Source and thumbnail in a temporary folder, removed when dropped
+    struct Files {
+        dir: PathBuf,
+        source: PathBuf,
+        output: PathBuf,
+    }
+
+    impl Files {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("exr_thumbnailer_manifest_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let files = Self {
+                source: dir.join("shot.exr"),
+                output: dir.join("shot.png"),
+                dir,
+            };
+            files.write_source(b"exr contents");
+            fs::write(&files.output, b"png").unwrap();
+            files
+        }
+
+        /// Replace the source contents, moving its mtime forward
+        fn write_source(&self, contents: &[u8]) {
+            fs::write(&self.source, contents).unwrap();
+            self.set_source_mtime(SystemTime::now() + Duration::from_secs(1));
+        }
+
+        fn set_source_mtime(&self, mtime: SystemTime) {
+            fs::File::options().write(true).open(&self.source).unwrap().set_modified(mtime).unwrap();
+        }
+
+        fn entry(&self) -> ManifestEntry {
+            entry_for(&self.source, file_content_hash(&self.source).unwrap(), SETTINGS).unwrap()
+        }
+
+        fn freshness(&self, entry: &ManifestEntry, settings: &str) -> Freshness {
+            freshness(&Recorded::Entry(entry.clone()), &self.source, &self.output, settings).unwrap()
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn unchanged_file_and_settings_are_up_to_date() {
+        let files = Files::new("unchanged");
+        assert_eq!(files.freshness(&files.entry(), SETTINGS), Freshness::UpToDate);
+    }
+
+    #[test]
+    fn changed_settings_are_stale() {
+        let files = Files::new("settings");
+        let settings = SETTINGS.replace("tone_map=none", "tone_map=reinhard");
+        assert_eq!(files.freshness(&files.entry(), &settings), Freshness::Stale);
+    }
+
+    #[test]
+    fn a_changed_file_is_stale() {
+        let files = Files::new("resized");
+        let entry = files.entry();
+        files.write_source(b"longer exr contents");
+        assert_eq!(files.freshness(&entry, SETTINGS), Freshness::Stale);
+    }
+
+    #[test]
+    fn a_changed_file_of_the_same_size_is_stale() {
+        let files = Files::new("rewritten");
+        let entry = files.entry();
+        files.write_source(b"EXR CONTENTS");
+        assert_eq!(files.freshness(&entry, SETTINGS), Freshness::Stale);
+    }
+
+    #[test]
+    fn a_touched_file_only_needs_the_new_mtime() {
+        let files = Files::new("touched");
+        let entry = files.entry();
+        files.write_source(b"exr contents");
+        let touched = files.entry();
+        assert_ne!(touched.source_mtime_ns, entry.source_mtime_ns);
+        assert_eq!(files.freshness(&entry, SETTINGS), Freshness::UpToDateTouched(touched));
+    }
+
+    #[test]
+    fn a_missing_thumbnail_is_stale() {
+        let files = Files::new("missing");
+        let entry = files.entry();
+        fs::remove_file(&files.output).unwrap();
+        assert_eq!(files.freshness(&entry, SETTINGS), Freshness::Stale);
+    }
+
+    #[test]
+    fn without_a_manifest_the_thumbnail_must_be_newer() {
+        let files = Files::new("no_manifest");
+        let fresh = |files: &Files| freshness(&Recorded::NoManifest, &files.source, &files.output, SETTINGS).unwrap();
+        assert_eq!(fresh(&files), Freshness::Stale);
+        files.set_source_mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        assert_eq!(fresh(&files), Freshness::UpToDate);
+    }
+
+    #[test]
+    fn sources_missing_from_the_manifest_are_stale() {
+        let files = Files::new("no_entry");
+        files.set_source_mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        let mut manifest = Manifest::default();
+        manifest.save(&files.dir).unwrap();
+        let manifest = Manifest::load(&files.dir);
+        assert_eq!(manifest.get(&files.source), Recorded::Missing);
+        assert_eq!(freshness(&Recorded::Missing, &files.source, &files.output, SETTINGS).unwrap(), Freshness::Stale);
+        assert_eq!(Manifest::default().get(&files.source), Recorded::NoManifest);
+    }
+}
@@ -0,0 +1,186 @@
+//! Output files: atomic writes and the `--overwrite` policy.
+//!
+//! Files are written to a hidden temporary file in the destination folder
+//! and renamed over the destination, so readers and later runs never see a
+//! partially written thumbnail, even if the process is killed. The
+//! temporary files being written are remembered so an aborted run can remove
+//! them.
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Whether existing output files are replaced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overwrite {
+    /// Always write the thumbnail
+    #[default]
+    Always,
+    /// Skip files whose thumbnail exists
+    Never,
+    /// Skip files whose thumbnail is newer than the source
+    IfNewer,
+}
+
+impl Overwrite {
+    /// Whether the thumbnail of `source` at `output` is kept. Files whose
+    /// modification times cannot be read are converted.
+    pub fn keeps(self, source: &Path, output: &Path) -> bool {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+        match self {
+            Overwrite::Always => false,
+            Overwrite::Never => output.exists(),
+            Overwrite::IfNewer => match (modified(source), modified(output)) {
+                (Ok(source), Ok(output)) => output >= source,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Distinguishes the temporary files of concurrent writes
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Temporary files currently being written
+static TEMP_FILES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Suffix of the temporary files
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Hidden temporary file next to `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}-{}{}", name, std::process::id(), unique, TEMP_SUFFIX))
+}
+
+fn temp_files() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    // A panic while holding the lock leaves the set intact
+    TEMP_FILES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Write `bytes` to `path` through a temporary file and a rename. The
+/// temporary file is synced to disk before the rename, so a crash cannot
+/// leave an empty or partial file at `path`, and removed if writing fails.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    temp_files().insert(temp_path.clone());
+    let write = || {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    temp_files().remove(&temp_path);
+    result
+}
+
+/// Remove the temporary files of the writes in progress and exit the
+/// process without waiting for them. Writes that have not started yet wait
+/// for the exit, so they leave nothing behind either.
+pub fn exit_removing_temp_files(code: i32) -> ! {
+    let temp_files = temp_files();
+    for temp_path in temp_files.iter() {
+        let _ = fs::remove_file(temp_path);
+    }
+    std::process::exit(code)
+}
+
+/// Encode `image` in the format of its file extension and write it with
+/// [`write_atomic`]. JPEG drops the alpha channel.
+pub fn save_atomic(image: RgbaImage, path: &Path) -> image::ImageResult<()> {
+    let format = ImageFormat::from_path(path)?;
+    let mut encoded = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image).to_rgb8().write_to(&mut encoded, format)?,
+        _ => image.write_to(&mut encoded, format)?,
+    }
+    Ok(write_atomic(path, encoded.get_ref())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    /// Source and thumbnail in a temporary folder, removed when dropped
+    struct Files {
+        dir: PathBuf,
+        source: PathBuf,
+        output: PathBuf,
+    }
+
+    impl Files {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("exr_thumbnailer_output_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let files = Self {
+                source: dir.join("shot.exr"),
+                output: dir.join("shot.png"),
+                dir,
+            };
+            fs::write(&files.source, b"exr").unwrap();
+            files
+        }
+
+        fn set_mtime(path: &Path, seconds: u64) {
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn if_newer_keeps_only_thumbnails_newer_than_the_source() {
+        let files = Files::new("if_newer");
+        assert!(!Overwrite::IfNewer.keeps(&files.source, &files.output));
+
+        fs::write(&files.output, b"png").unwrap();
+        Files::set_mtime(&files.source, 1_000_000);
+        Files::set_mtime(&files.output, 2_000_000);
+        assert!(Overwrite::IfNewer.keeps(&files.source, &files.output));
+
+        Files::set_mtime(&files.output, 2_000_000);
+        Files::set_mtime(&files.source, 3_000_000);
+        assert!(!Overwrite::IfNewer.keeps(&files.source, &files.output));
+    }
+
+    #[test]
+    fn never_keeps_existing_thumbnails_and_always_replaces_them() {
+        let files = Files::new("never");
+        assert!(!Overwrite::Never.keeps(&files.source, &files.output));
+        fs::write(&files.output, b"png").unwrap();
+        assert!(Overwrite::Never.keeps(&files.source, &files.output));
+        assert!(!Overwrite::Always.keeps(&files.source, &files.output));
+    }
+
+    #[test]
+    fn atomic_writes_leave_no_temporary_files() {
+        let files = Files::new("atomic");
+        fs::write(&files.output, b"old").unwrap();
+        write_atomic(&files.output, b"new").unwrap();
+        assert_eq!(fs::read(&files.output).unwrap(), b"new");
+        let names: BTreeSet<String> = fs::read_dir(&files.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, BTreeSet::from(["shot.exr".to_string(), "shot.png".to_string()]));
+        assert!(write_atomic(&files.dir.join("missing/shot.png"), b"new").is_err());
+        assert!(temp_files().iter().all(|path| !path.starts_with(&files.dir)));
+    }
+}
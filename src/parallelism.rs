@@ -0,0 +1,117 @@
+//! How many files are converted at once and how many threads decode each.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Files from this size on decode noticeably faster with several threads
+const LARGE_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parallelism {
+    /// Files converted at the same time
+    pub jobs: usize,
+    /// Threads decompressing the blocks of one file
+    pub decode_threads: usize,
+}
+
+impl Parallelism {
+    /// Share the CPUs between files and decode threads so they are not
+    /// oversubscribed. A value given explicitly is kept and the other one
+    /// gets the remaining CPUs.
+    ///
+    /// Without either, a few large files divide the CPUs between them and
+    /// decode in parallel, while many or small files are converted one per CPU
+    /// with single-threaded decoding.
+    pub fn choose(jobs: Option<usize>, decode_threads: Option<usize>, files: &[PathBuf]) -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        let has_large_files = || {
+            files
+                .iter()
+                .any(|path| fs::metadata(path).is_ok_and(|meta| meta.len() >= LARGE_FILE_BYTES))
+        };
+        Self::for_cpus(cpus, jobs, decode_threads, files.len(), has_large_files)
+    }
+
+    /// [`Parallelism::choose`] for `cpus` CPUs and `files` files, looking at
+    /// their sizes only if neither value is given.
+    fn for_cpus(
+        cpus: usize,
+        jobs: Option<usize>,
+        decode_threads: Option<usize>,
+        files: usize,
+        has_large_files: impl FnOnce() -> bool,
+    ) -> Self {
+        let share = |used: usize| (cpus / used).max(1);
+        match (jobs, decode_threads) {
+            (Some(jobs), Some(decode_threads)) => Self { jobs, decode_threads },
+            (Some(jobs), None) => Self {
+                jobs,
+                decode_threads: share(jobs),
+            },
+            (None, Some(decode_threads)) => Self {
+                jobs: share(decode_threads),
+                decode_threads,
+            },
+            (None, None) => {
+                if files < cpus && has_large_files() {
+                    Self {
+                        jobs: files,
+                        decode_threads: share(files),
+                    }
+                } else {
+                    Self {
+                        jobs: cpus,
+                        decode_threads: 1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parallelism(jobs: Option<usize>, decode_threads: Option<usize>, files: usize, large: bool) -> (usize, usize) {
+        let chosen = Parallelism::for_cpus(8, jobs, decode_threads, files, || large);
+        (chosen.jobs, chosen.decode_threads)
+    }
+
+    #[test]
+    fn explicit_values_are_kept() {
+        assert_eq!(parallelism(Some(3), Some(5), 100, false), (3, 5));
+        assert_eq!(parallelism(Some(16), Some(16), 2, true), (16, 16));
+    }
+
+    #[test]
+    fn one_explicit_value_gets_the_remaining_cpus() {
+        assert_eq!(parallelism(Some(2), None, 100, false), (2, 4));
+        assert_eq!(parallelism(Some(3), None, 100, false), (3, 2));
+        assert_eq!(parallelism(None, Some(4), 100, false), (2, 4));
+        // Never fewer than one
+        assert_eq!(parallelism(Some(16), None, 100, false), (16, 1));
+        assert_eq!(parallelism(None, Some(16), 100, false), (1, 16));
+    }
+
+    #[test]
+    fn a_few_large_files_decode_in_parallel() {
+        assert_eq!(parallelism(None, None, 1, true), (1, 8));
+        assert_eq!(parallelism(None, None, 3, true), (3, 2));
+    }
+
+    #[test]
+    fn many_or_small_files_decode_in_one_thread_each() {
+        assert_eq!(parallelism(None, None, 3, false), (8, 1));
+        assert_eq!(parallelism(None, None, 8, true), (8, 1));
+        assert_eq!(parallelism(None, None, 500, true), (8, 1));
+    }
+
+    #[test]
+    fn file_sizes_are_only_read_when_needed() {
+        let chosen = Parallelism::for_cpus(8, Some(2), None, 1, || panic!("file sizes read"));
+        assert_eq!(chosen, Parallelism { jobs: 2, decode_threads: 4 });
+        let chosen = Parallelism::for_cpus(8, None, None, 100, || panic!("file sizes read"));
+        assert_eq!(chosen, Parallelism { jobs: 8, decode_threads: 1 });
+    }
+}
@@ -0,0 +1,41 @@
+//! Lower scheduling priority for `--nice`.
+
+use std::io;
+
+/// Nice value of the process on Unix, low enough to yield to interactive work
+#[cfg(unix)]
+const NICE: libc::c_int = 10;
+
+/// Lower the priority of the process. Threads started afterwards inherit it,
+/// so this is called before any thread pool is created.
+#[cfg(unix)]
+pub fn lower() -> io::Result<()> {
+    // SAFETY: both calls only query or change the scheduling priority of
+    // this process. Never raise an existing nice value, that needs privileges.
+    let result = unsafe {
+        let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+        libc::setpriority(libc::PRIO_PROCESS, 0, current.max(NICE))
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+pub fn lower() -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS};
+
+    // SAFETY: the pseudo handle of the current process is always valid
+    if unsafe { SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS) } != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn lower() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
@@ -0,0 +1,272 @@
+//! Live progress display for batch conversion.
+//!
+//! On a terminal a single status line is redrawn on stderr; when stderr is
+//! piped a plain status line is printed periodically instead.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::report::{FileRecord, FileStatus};
+
+/// How often the status line is redrawn on a terminal
+const TTY_REFRESH: Duration = Duration::from_millis(200);
+/// How often a plain status line is printed when not on a terminal
+const PLAIN_REFRESH: Duration = Duration::from_secs(5);
+const BAR_WIDTH: usize = 30;
+
+/// Amount of per-file output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// No progress display and no per-file messages
+    Quiet,
+    /// Progress display and failed files only
+    Normal,
+    /// Progress display and a message for every file
+    Verbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        if quiet {
+            Verbosity::Quiet
+        } else if verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+}
+
+/// Progress of a batch of files, shared between the worker threads
+pub struct Progress {
+    total: usize,
+    verbosity: Verbosity,
+    tty: bool,
+    start: Instant,
+    done: AtomicUsize,
+    failed: AtomicUsize,
+    bytes_decoded: AtomicU64,
+    finished: AtomicBool,
+    /// Serialises terminal output and remembers whether a status line is shown
+    line_visible: Mutex<bool>,
+}
+
+impl Progress {
+    pub fn new(total: usize, verbosity: Verbosity) -> Self {
+        Self {
+            total,
+            verbosity,
+            tty: io::stderr().is_terminal(),
+            start: Instant::now(),
+            done: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            bytes_decoded: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+            line_visible: Mutex::new(false),
+        }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Record a finished file and print its message according to the verbosity.
+    pub fn file_done(&self, record: &FileRecord) {
+        self.done.fetch_add(1, Ordering::SeqCst);
+        self.bytes_decoded.fetch_add(record.bytes_in, Ordering::SeqCst);
+        match record.status {
+            FileStatus::Ok => {
+                if self.verbosity >= Verbosity::Verbose {
+                    self.message(&format!(
+                        "Successfully created thumbnail: {} ({:.2}ms)",
+                        record.output.as_deref().unwrap_or(""),
+                        record.total_ms
+                    ));
+                }
+                if !record.invalid_values.is_empty() && self.verbosity >= Verbosity::Normal {
+                    self.error(&format!("Warning: {} contains {}", record.source, record.invalid_values));
+                }
+                if !record.qc.is_empty() && self.verbosity >= Verbosity::Normal {
+                    let issues: Vec<&str> = record.qc.iter().map(|issue| issue.as_str()).collect();
+                    self.error(&format!("Warning: {} failed QC: {}", record.source, issues.join(", ")));
+                }
+            }
+            FileStatus::Skipped => {
+                if self.verbosity >= Verbosity::Verbose {
+                    self.message(&format!(
+                        "Skipped up-to-date thumbnail: {}",
+                        record.output.as_deref().unwrap_or("")
+                    ));
+                }
+            }
+            FileStatus::Failed => {
+                self.failed.fetch_add(1, Ordering::SeqCst);
+                if self.verbosity >= Verbosity::Normal {
+                    self.error(&format!(
+                        "Failed to process {}: {}",
+                        record.source,
+                        record.error.as_deref().unwrap_or("")
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Print a message on stdout without garbling the status line.
+    pub fn message(&self, text: &str) {
+        let mut line_visible = self.line_visible.lock().unwrap();
+        clear_line(&mut line_visible);
+        println!("{}", text);
+    }
+
+    /// Print an error message on stderr without garbling the status line.
+    pub fn error(&self, text: &str) {
+        let mut line_visible = self.line_visible.lock().unwrap();
+        clear_line(&mut line_visible);
+        eprintln!("{}", text);
+    }
+
+    /// Redraw the status line until [`Progress::finish`] is called.
+    /// Meant to run on its own thread for the duration of the batch.
+    pub fn run(&self) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        let refresh = if self.tty { TTY_REFRESH } else { PLAIN_REFRESH };
+        let mut last_render = Instant::now();
+        while !self.finished.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(50));
+            if last_render.elapsed() >= refresh {
+                self.render();
+                last_render = Instant::now();
+            }
+        }
+    }
+
+    /// Stop the status line and clear it from the terminal.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+        clear_line(&mut self.line_visible.lock().unwrap());
+    }
+
+    fn render(&self) {
+        let status = self.status_line();
+        let mut line_visible = self.line_visible.lock().unwrap();
+        if self.finished.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        if self.tty {
+            let _ = write!(stderr, "\r\x1b[2K{}", status);
+            *line_visible = true;
+        } else {
+            let _ = writeln!(stderr, "{}", status);
+        }
+        let _ = stderr.flush();
+    }
+
+    fn status_line(&self) -> String {
+        let done = self.done.load(Ordering::SeqCst);
+        let failed = self.failed.load(Ordering::SeqCst);
+        let elapsed = self.start.elapsed();
+        let fraction = if self.total > 0 { done as f64 / self.total as f64 } else { 1.0 };
+        let megabytes = self.bytes_decoded.load(Ordering::SeqCst) as f64 / (1024.0 * 1024.0);
+        let throughput = megabytes / elapsed.as_secs_f64().max(1e-3);
+        let eta = if done > 0 {
+            format_duration(elapsed.mul_f64((self.total - done) as f64 / done as f64))
+        } else {
+            "--:--:--".to_string()
+        };
+
+        let counts = format!(
+            "{}/{} ({:.0}%)  {:.1} MB/s  ETA {}  failed: {}",
+            done,
+            self.total,
+            fraction * 100.0,
+            throughput,
+            eta,
+            failed
+        );
+        if self.tty {
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            let bar: String = (0..BAR_WIDTH)
+                .map(|i| if i < filled { '=' } else if i == filled { '>' } else { ' ' })
+                .collect();
+            format!("[{}] {}", bar, counts)
+        } else {
+            format!("Progress: {}", counts)
+        }
+    }
+}
+
+/// Erase the status line if one is shown.
+fn clear_line(line_visible: &mut bool) {
+    if *line_visible {
+        let _ = write!(io::stderr(), "\r\x1b[2K");
+        *line_visible = false;
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr_thumbnailer::ThumbError;
+    use std::path::Path;
+
+    /// Progress of 4 files with one converted, one skipped and one failed
+    fn progress(tty: bool) -> Progress {
+        let progress = Progress {
+            tty,
+            ..Progress::new(4, Verbosity::Quiet)
+        };
+        let mut converted = FileRecord::new(Path::new("a.exr"));
+        converted.bytes_in = 3 * 1024 * 1024;
+        progress.file_done(&converted);
+        progress.file_done(&FileRecord::skipped(Path::new("b.exr"), Path::new("b.png")));
+        progress.file_done(&FileRecord::failed(Path::new("c.exr"), &ThumbError::Cancelled));
+        progress
+    }
+
+    #[test]
+    fn verbosity_follows_the_flags() {
+        assert_eq!(Verbosity::from_flags(false, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(false, true), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(true, false), Verbosity::Quiet);
+        assert!(Verbosity::Quiet < Verbosity::Normal && Verbosity::Normal < Verbosity::Verbose);
+    }
+
+    #[test]
+    fn the_status_line_counts_done_and_failed_files() {
+        let status = progress(false).status_line();
+        assert!(status.starts_with("Progress: 3/4 (75%)  "), "{}", status);
+        assert!(status.contains(" MB/s  ETA "), "{}", status);
+        assert!(status.ends_with("  failed: 1"), "{}", status);
+    }
+
+    #[test]
+    fn terminals_get_a_progress_bar() {
+        let status = progress(true).status_line();
+        let bar = format!("[{}>{}] 3/4 (75%)", "=".repeat(22), " ".repeat(7));
+        assert!(status.starts_with(&bar), "{}", status);
+    }
+
+    #[test]
+    fn nothing_done_has_no_eta() {
+        let status = Progress::new(0, Verbosity::Quiet).status_line();
+        assert!(status.contains("0/0 (100%)") && status.contains("ETA --:--:--"), "{}", status);
+    }
+
+    #[test]
+    fn durations_are_hours_minutes_and_seconds() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "00:00:00");
+        assert_eq!(format_duration(Duration::from_millis(61_999)), "00:01:01");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 25 * 60 + 7)), "03:25:07");
+    }
+}
@@ -0,0 +1,193 @@
+//! Provenance metadata embedded into encoded thumbnails, so a thumbnail can
+//! be traced back to the EXR file and settings it was generated from.
+//!
+//! PNG thumbnails carry one `tEXt` chunk per entry, or `iTXt` for values that
+//! are not ASCII. JPEG and WebP thumbnails carry the entries as XMP, plus an
+//! EXIF block with the source path as the image description.
+
+use image::codecs::jpeg::JpegEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageEncoder, ImageError, ImageFormat, RgbImage, RgbaImage};
+use std::path::PathBuf;
+
+use crate::color::ColorConfig;
+use crate::error::ThumbError;
+
+/// Prefix of the PNG text keywords and XMP properties
+pub const KEY_PREFIX: &str = "exrthumb:";
+
+/// Namespace of the XMP properties
+pub const XMP_NAMESPACE: &str = "urn:exr-thumbnailer:provenance:1/";
+
+/// Name and version of this library, written as the PNG `Software` and XMP
+/// `CreatorTool`
+const SOFTWARE: &str = concat!("exr_thumbnailer ", env!("CARGO_PKG_VERSION"));
+
+/// Identifies an XMP segment in a JPEG file
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// EXIF tags written
+const EXIF_IMAGE_DESCRIPTION: u16 = 0x010e;
+const EXIF_SOFTWARE: u16 = 0x0131;
+
+/// Where a thumbnail comes from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    /// Absolute path of the source file, unknown for in-memory sources
+    pub source: Option<PathBuf>,
+    /// Modification time of the source file in seconds since the Unix epoch
+    pub source_mtime: Option<u64>,
+    /// See [`crate::content_hash`]
+    pub source_hash: Option<String>,
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Name of the layer the pixels were taken from, if it has one
+    pub layer: Option<String>,
+    pub color: ColorConfig,
+}
+
+impl Provenance {
+    /// Keys, without [`KEY_PREFIX`], and values of the known entries.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = Vec::new();
+        if let Some(source) = &self.source {
+            entries.push(("source", source.display().to_string()));
+        }
+        if let Some(mtime) = self.source_mtime {
+            entries.push(("source_mtime", mtime.to_string()));
+        }
+        if let Some(hash) = &self.source_hash {
+            entries.push(("source_hash", hash.clone()));
+        }
+        entries.push(("source_width", self.source_width.to_string()));
+        entries.push(("source_height", self.source_height.to_string()));
+        if let Some(layer) = &self.layer {
+            entries.push(("layer", layer.clone()));
+        }
+        entries.push(("tone_map", self.color.tone_map.as_str().to_string()));
+        entries.push(("gamma", self.color.gamma.to_string()));
+        entries
+    }
+
+    /// The entries as an XMP packet.
+    fn xmp(&self) -> String {
+        let prefix = KEY_PREFIX.trim_end_matches(':');
+        let mut xmp = format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+             <rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" xmlns:{}=\"{}\">\n\
+             <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+            prefix, XMP_NAMESPACE, SOFTWARE
+        );
+        for (key, value) in self.entries() {
+            xmp += &format!("<{}{}>{}</{}{}>\n", KEY_PREFIX, key, xml_escape(&value), KEY_PREFIX, key);
+        }
+        xmp += "</rdf:Description>\n</rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>";
+        xmp
+    }
+
+    /// Little-endian TIFF structure with the source path as the image
+    /// description, without the `Exif\0\0` header of JPEG files.
+    fn exif(&self) -> Vec<u8> {
+        let mut tags = vec![(EXIF_SOFTWARE, SOFTWARE.to_string())];
+        if let Some(source) = &self.source {
+            tags.insert(0, (EXIF_IMAGE_DESCRIPTION, source.display().to_string()));
+        }
+        // Header, entry count, entries and the offset of the next IFD,
+        // followed by the strings that do not fit into an entry
+        let mut values_offset = 8 + 2 + tags.len() * 12 + 4;
+        let mut exif = b"II*\0\x08\0\0\0".to_vec();
+        exif.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        let mut values = Vec::new();
+        for (tag, text) in &tags {
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.push(0);
+            exif.extend_from_slice(&tag.to_le_bytes());
+            // ASCII
+            exif.extend_from_slice(&2u16.to_le_bytes());
+            exif.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            if bytes.len() <= 4 {
+                bytes.resize(4, 0);
+                exif.extend_from_slice(&bytes);
+            } else {
+                exif.extend_from_slice(&(values_offset as u32).to_le_bytes());
+                values_offset += bytes.len();
+                values.extend_from_slice(&bytes);
+            }
+        }
+        exif.extend_from_slice(&0u32.to_le_bytes());
+        exif.extend_from_slice(&values);
+        exif
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Encode `image` as PNG with the provenance as text chunks.
+pub(crate) fn encode_png(image: &RgbaImage, provenance: &Provenance) -> Result<Vec<u8>, ThumbError> {
+    let mut encoded = Vec::new();
+    let mut encoder = png::Encoder::new(&mut encoded, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut chunks = vec![("Software".to_string(), SOFTWARE.to_string())];
+    chunks.extend(provenance.entries().into_iter().map(|(key, value)| (format!("{}{}", KEY_PREFIX, key), value)));
+    chunks
+        .into_iter()
+        .try_for_each(|(keyword, text)| match text.is_ascii() {
+            true => encoder.add_text_chunk(keyword, text),
+            false => encoder.add_itxt_chunk(keyword, text),
+        })
+        .and_then(|()| encoder.write_header())
+        .and_then(|mut writer| {
+            writer.write_image_data(image.as_raw())?;
+            writer.finish()
+        })
+        .map_err(|e| encoding_error(ImageFormat::Png, e))?;
+    Ok(encoded)
+}
+
+/// Encode `image` as JPEG with the provenance as EXIF and XMP.
+pub(crate) fn encode_jpeg(image: &RgbImage, provenance: &Provenance) -> Result<Vec<u8>, ThumbError> {
+    let mut encoded = Vec::new();
+    let mut encoder = JpegEncoder::new(&mut encoded);
+    encoder
+        .set_exif_metadata(provenance.exif())
+        .map_err(|e| ThumbError::Encode(ImageError::Unsupported(e)))?;
+    encoder.write_image(image.as_raw(), image.width(), image.height(), image::ExtendedColorType::Rgb8)?;
+
+    let mut xmp = JPEG_XMP_HEADER.to_vec();
+    xmp.extend_from_slice(provenance.xmp().as_bytes());
+    // A segment holds at most 64 KiB, far more than the packet needs unless
+    // the source path is absurdly long
+    if xmp.len() + 2 <= u16::MAX as usize {
+        // After the start of image marker and the APP0 and APP1 segments
+        let mut position = 2;
+        while matches!(encoded.get(position..position + 2), Some([0xff, 0xe0 | 0xe1])) {
+            position += 2 + u16::from_be_bytes([encoded[position + 2], encoded[position + 3]]) as usize;
+        }
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend_from_slice(&((xmp.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(&xmp);
+        encoded.splice(position..position, segment);
+    }
+    Ok(encoded)
+}
+
+/// Encode `image` as lossless WebP with the provenance as EXIF and XMP.
+pub(crate) fn encode_webp(image: &RgbaImage, provenance: &Provenance) -> Result<Vec<u8>, ThumbError> {
+    let mut encoded = Vec::new();
+    let mut encoder = image_webp::WebPEncoder::new(&mut encoded);
+    encoder.set_exif_metadata(provenance.exif());
+    encoder.set_xmp_metadata(provenance.xmp().into_bytes());
+    encoder
+        .encode(image.as_raw(), image.width(), image.height(), image_webp::ColorType::Rgba8)
+        .map_err(|e| encoding_error(ImageFormat::WebP, e))?;
+    Ok(encoded)
+}
+
+fn encoding_error(format: ImageFormat, error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ThumbError {
+    ThumbError::Encode(ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), error)))
+}
@@ -0,0 +1,226 @@
+//! Render QC: black and constant frames, fireflies and brightness jumps.
+//!
+//! The statistics are gathered from the full resolution pixels while they
+//! are decoded. Fireflies are judged on the reduced image, so an isolated
+//! pixel is one that stands out from the reduced pixels around it.
+
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+/// Default luminance above which an isolated pixel counts as a firefly
+pub const DEFAULT_FIREFLY_THRESHOLD: f32 = 100.0;
+
+/// Colour of the border [`crate::ThumbnailRequest::qc_border`] draws
+pub const QC_BORDER_COLOUR: [u8; 4] = [255, 0, 0, 255];
+
+/// Largest channel range of a constant frame, and largest value of a black one
+const CONSTANT_TOLERANCE: f32 = 1e-4;
+
+/// Most pixels above the threshold in the 3x3 reduced pixels around a
+/// firefly; more are a small light source
+const FIREFLY_MAX_PIXELS: u32 = 2;
+
+/// How much brighter than the median of the reduced pixels around it a
+/// firefly is at least
+const FIREFLY_CONTRAST: f32 = 4.0;
+
+/// Added to mean luminances before comparing them in stops, so black frames
+/// do not compare as infinitely far apart
+const JUMP_EPSILON: f64 = 1e-4;
+
+/// A problem found by the QC checks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QcIssue {
+    /// All RGB values are 0
+    Black,
+    /// All pixels have the same colour
+    Constant,
+    /// Isolated pixels above the firefly threshold
+    Fireflies,
+    /// The mean luminance differs from the previous frame of the sequence
+    BrightnessJump,
+}
+
+impl QcIssue {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QcIssue::Black => "black",
+            QcIssue::Constant => "constant",
+            QcIssue::Fireflies => "fireflies",
+            QcIssue::BrightnessJump => "brightness_jump",
+        }
+    }
+}
+
+/// Statistics of the finite RGB values of a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct FrameStats {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// Mean Rec. 709 luminance
+    pub mean_luminance: f64,
+    /// Isolated pixels above the firefly threshold
+    pub fireflies: u64,
+}
+
+impl FrameStats {
+    pub fn is_constant(&self) -> bool {
+        (0..3).all(|c| self.max[c] - self.min[c] <= CONSTANT_TOLERANCE)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.is_constant() && self.max.iter().chain(&self.min).all(|value| value.abs() <= CONSTANT_TOLERANCE)
+    }
+
+    /// Issues found in this frame alone. A black frame is not also reported
+    /// as constant.
+    pub fn issues(&self) -> Vec<QcIssue> {
+        let mut issues = Vec::new();
+        if self.is_black() {
+            issues.push(QcIssue::Black);
+        } else if self.is_constant() {
+            issues.push(QcIssue::Constant);
+        }
+        if self.fireflies > 0 {
+            issues.push(QcIssue::Fireflies);
+        }
+        issues
+    }
+}
+
+/// Linear Rec. 709 luminance
+pub(crate) fn luminance(pixel: [f32; 4]) -> f32 {
+    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+}
+
+/// Gathers [`FrameStats`] while the pixels are box averaged into the reduced
+/// image.
+pub(crate) struct QcAccumulator {
+    firefly_threshold: f32,
+    min: [f32; 3],
+    max: [f32; 3],
+    luminance_sum: f64,
+    count: u64,
+    /// Brightest luminance and number of pixels above the threshold per
+    /// reduced pixel, empty until the first such pixel
+    bright: Vec<(f32, u32)>,
+}
+
+impl QcAccumulator {
+    pub fn new(firefly_threshold: f32) -> Self {
+        Self {
+            firefly_threshold,
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            luminance_sum: 0.0,
+            count: 0,
+            bright: Vec::new(),
+        }
+    }
+
+    /// Add a finite `pixel` that is averaged into reduced pixel `index` of `len`.
+    pub fn add(&mut self, index: usize, len: usize, pixel: [f32; 4]) {
+        for (c, value) in pixel[..3].iter().enumerate() {
+            self.min[c] = self.min[c].min(*value);
+            self.max[c] = self.max[c].max(*value);
+        }
+        let luminance = luminance(pixel);
+        self.luminance_sum += luminance as f64;
+        self.count += 1;
+        if luminance > self.firefly_threshold {
+            if self.bright.is_empty() {
+                self.bright = vec![(0.0, 0); len];
+            }
+            let (peak, count) = &mut self.bright[index];
+            *peak = peak.max(luminance);
+            *count += 1;
+        }
+    }
+
+    /// The statistics, with fireflies judged against the averaged `pixels`
+    /// of the reduced image.
+    pub fn finish(self, pixels: &[[f32; 4]], width: u32, height: u32) -> FrameStats {
+        let finite = self.count > 0;
+        FrameStats {
+            min: if finite { self.min } else { [0.0; 3] },
+            max: if finite { self.max } else { [0.0; 3] },
+            mean_luminance: if finite { self.luminance_sum / self.count as f64 } else { 0.0 },
+            fireflies: count_fireflies(&self.bright, pixels, width as usize, height as usize),
+        }
+    }
+}
+
+fn count_fireflies(bright: &[(f32, u32)], pixels: &[[f32; 4]], width: usize, height: usize) -> u64 {
+    let mut fireflies = 0;
+    for (index, &(peak, count)) in bright.iter().enumerate().filter(|(_, (_, count))| *count > 0) {
+        let (x, y) = (index % width, index / width);
+        let mut neighbourhood_count = 0;
+        let mut neighbours = Vec::with_capacity(8);
+        for ny in y.saturating_sub(1)..(y + 2).min(height) {
+            for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                let neighbour = ny * width + nx;
+                neighbourhood_count += bright[neighbour].1;
+                if neighbour != index {
+                    neighbours.push(luminance(pixels[neighbour]));
+                }
+            }
+        }
+        neighbours.sort_by(f32::total_cmp);
+        let median = neighbours.get(neighbours.len() / 2).copied().unwrap_or(0.0);
+        if neighbourhood_count <= FIREFLY_MAX_PIXELS && median * FIREFLY_CONTRAST < peak {
+            fireflies += count as u64;
+        }
+    }
+    fireflies
+}
+
+/// Frames of a sequence, given their mean luminances in frame order, whose
+/// brightness differs from the previous frame by more than `threshold_stops`.
+///
+/// Only the first frame after a jump is flagged. A single frame that stands
+/// out is not followed by a second flag when the sequence returns to the
+/// brightness before it.
+pub fn brightness_jumps(mean_luminances: &[f64], threshold_stops: f64) -> Vec<bool> {
+    let stops = |a: f64, b: f64| ((b.max(0.0) + JUMP_EPSILON) / (a.max(0.0) + JUMP_EPSILON)).log2().abs();
+    let mut jumps = vec![false; mean_luminances.len()];
+    for i in 1..mean_luminances.len() {
+        let returns = i >= 2 && jumps[i - 1] && stops(mean_luminances[i - 2], mean_luminances[i]) <= threshold_stops;
+        jumps[i] = !returns && stops(mean_luminances[i - 1], mean_luminances[i]) > threshold_stops;
+    }
+    jumps
+}
+
+/// Draw a [`QC_BORDER_COLOUR`] border around `image`.
+pub fn draw_border(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    let border = (width.min(height) / 32).max(2);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if x < border || y < border || x + border >= width || y + border >= height {
+            *pixel = Rgba(QC_BORDER_COLOUR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brightness_jumps_flag_the_first_frame_after_a_jump() {
+        assert_eq!(brightness_jumps(&[1.0, 1.0, 4.0, 4.0], 1.0), [false, false, true, false]);
+        // A single bright frame is flagged once, not again when the sequence returns
+        assert_eq!(brightness_jumps(&[1.0, 1.0, 4.0, 1.0, 1.0], 1.0), [false, false, true, false, false]);
+        assert_eq!(brightness_jumps(&[0.0, 0.0, 1.5, 1.0], 1.0), [false, false, true, false]);
+        assert!(brightness_jumps(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn the_border_scales_with_the_image() {
+        let mut image = RgbaImage::new(256, 128);
+        draw_border(&mut image);
+        assert_eq!(image.get_pixel(3, 64).0, QC_BORDER_COLOUR);
+        assert_eq!(image.get_pixel(4, 64).0, [0; 4]);
+        assert_eq!(image.get_pixel(255, 127).0, QC_BORDER_COLOUR);
+    }
+}
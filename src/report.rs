@@ -0,0 +1,747 @@
+//! Machine-readable conversion report.
+//!
+//! The report is written once per run into the destination folder in one of
+//! three formats selected with `--report-format`:
+//!
+//! * `text` - the human readable summary (default).
+//! * `json` - a single object with the fields of [`RunReport`].
+//! * `csv`  - one row per file with the columns of [`FileRecord`], plus a
+//!   companion `<name>_summary.csv` holding the aggregate values as
+//!   `key,value` rows.
+//!
+//! # Schema (version 1)
+//!
+//! Per-file fields, in CSV column order:
+//!
+//! | field           | type           | description                                 |
+//! |-----------------|----------------|---------------------------------------------|
+//! | `source`        | string         | path of the EXR file                        |
+//! | `output`        | string or null | path of the written thumbnail               |
+//! | `source_width`  | integer        | width of the decoded EXR layer              |
+//! | `source_height` | integer        | height of the decoded EXR layer             |
+//! | `thumb_width`   | integer        | width of the thumbnail                      |
+//! | `thumb_height`  | integer        | height of the thumbnail                     |
+//! | `layer`         | string or null | name of the layer used, null if unnamed     |
+//! | `read_ms`       | float          | reading the EXR file from disk              |
+//! | `decode_ms`     | float          | decompressing and decoding the pixel data   |
+//! | `colour_ms`     | float          | tone mapping, gamma and 8 bit quantisation  |
+//! | `resize_ms`     | float          | scaling to the thumbnail size               |
+//! | `encode_ms`     | float          | encoding the thumbnail image                |
+//! | `write_ms`      | float          | writing the thumbnail to disk               |
+//! | `total_ms`      | float          | sum of all stages                           |
+//! | `bytes_in`      | integer        | size of the EXR file                        |
+//! | `bytes_out`     | integer        | size of the thumbnail file                  |
+//! | `status`        | string         | `ok`, `skipped` or `failed`                 |
+//! | `error`         | string or null | error message when `status` is `failed`     |
+//! | `error_kind`    | string or null | failure category, see below                 |
+//! | `source_hash`   | string or null | xxh3 hash of the EXR contents (hex)         |
+//! | `nan_values`    | integer        | NaN values in the decoded channels          |
+//! | `inf_values`    | integer        | positive and negative infinite values       |
+//! | `negative_values` | integer      | finite values below zero                    |
+//! | `mean_luminance`| float          | mean linear Rec. 709 luminance              |
+//! | `fireflies`     | integer        | isolated pixels above `--firefly-threshold` |
+//! | `qc`            | list of strings| QC issues, `;` separated in CSV             |
+//! | `histogram_image` | string or null | path of the `--histogram image` file      |
+//! | `pixel_stats`   | object or null | `--stats` only, see below                   |
+//!
+//! In JSON `nan_values`, `inf_values` and `negative_values` are replaced by
+//! an `invalid_values` object with `nan`, `pos_inf`, `neg_inf` and
+//! `negative` counts per R, G, B, A channel.
+//!
+//! `qc` holds `black`, `constant` (all pixels the same colour), `fireflies`
+//! and `brightness_jump` (the mean luminance differs from the previous frame
+//! of the sequence by more than `--jump-threshold` stops). The run summary
+//! counts files per issue in `qc_issues`.
+//!
+//! `pixel_stats` holds `channels`, the `min`, `max`, `mean` and `stddev` of
+//! the finite values of R, G, B and A, and `histogram`, the pixel counts of
+//! 80 quarter-stop bins of log2 luminance from -12 to +8 stops. In CSV it is
+//! written as `r_min` ... `a_stddev` columns and a `histogram` column of `;`
+//! separated counts, all empty without `--stats`.
+//!
+//! `skipped` files were up to date in `--incremental` mode, or their existing
+//! thumbnail was kept by `--overwrite never` or `if-newer`. They are counted
+//! separately as `skipped` in the run summary.
+//!
+//! `error_kind` is one of `io`, `unsupported_compression`,
+//! `unsupported_feature`, `corrupt_header`, `corrupt_data`, `missing_layer`,
+//! `encode`, `invalid_pixels` (NaN or infinite values with
+//! `--fail-on-invalid`; no thumbnail is written), `timeout` (decoding
+//! and encoding took longer than `--timeout-per-file`) or `panic` (the
+//! decoder or image processing crashed on the file). The run summary counts
+//! failures per category in `failures_by_kind`.
+//!
+//! A run interrupted with Ctrl-C is marked with `cancelled` in the run
+//! summary. `remaining` counts the files that were not converted, because
+//! they were not started yet or were abandoned after the grace period; they
+//! have no per-file entry but are included in `total_files`.
+//!
+//! The aggregate `timing` object holds, per stage, the total, average, min,
+//! median, p95 and max of the per-file durations of converted files together
+//! with a histogram of those durations, followed by the slowest files.
+//! `processing_ms` is the sum of the durations of converted files and
+//! `average_processing_ms` its average over the converted files.
+//!
+//! Fields are only ever added to the schema; a removal or change of meaning
+//! bumps [`SCHEMA_VERSION`]. Missing numeric values are reported as `0`, and
+//! in CSV null values are written as empty cells.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use exr_thumbnailer::error::{ErrorKind, ThumbError};
+use exr_thumbnailer::timing::{Stage, StageSummary, StageTimings, TimingStats};
+use exr_thumbnailer::qc::QcIssue;
+use exr_thumbnailer::stats::PixelStats;
+use exr_thumbnailer::InvalidValues;
+
+/// Version of the report schema, bumped on incompatible changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Output format of the conversion report
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    /// File extension used for the default report file name.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Text => "txt",
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Where the luminance histogram of `--stats` is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistogramOutput {
+    /// Only in the report
+    #[default]
+    None,
+    /// A `<name>_histogram.png` next to the thumbnail
+    Image,
+    /// In the bottom right corner of the thumbnail
+    Overlay,
+}
+
+/// Outcome of converting a single file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Ok,
+    Skipped,
+    Failed,
+}
+
+impl FileStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            FileStatus::Ok => "ok",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Per-file entry of the report
+#[derive(Clone, Debug, Serialize)]
+pub struct FileRecord {
+    pub source: String,
+    pub output: Option<String>,
+    pub source_width: u32,
+    pub source_height: u32,
+    pub thumb_width: u32,
+    pub thumb_height: u32,
+    pub layer: Option<String>,
+    pub read_ms: f64,
+    pub decode_ms: f64,
+    pub colour_ms: f64,
+    pub resize_ms: f64,
+    pub encode_ms: f64,
+    pub write_ms: f64,
+    pub total_ms: f64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub status: FileStatus,
+    pub error: Option<String>,
+    pub error_kind: Option<ErrorKind>,
+    pub source_hash: Option<String>,
+    pub invalid_values: InvalidValues,
+    pub mean_luminance: f64,
+    pub fireflies: u64,
+    pub qc: Vec<QcIssue>,
+    pub histogram_image: Option<String>,
+    pub pixel_stats: Option<PixelStats>,
+}
+
+impl FileRecord {
+    /// Create an empty successful record for `source`.
+    pub fn new(source: &Path) -> Self {
+        Self {
+            source: source.display().to_string(),
+            output: None,
+            source_width: 0,
+            source_height: 0,
+            thumb_width: 0,
+            thumb_height: 0,
+            layer: None,
+            read_ms: 0.0,
+            decode_ms: 0.0,
+            colour_ms: 0.0,
+            resize_ms: 0.0,
+            encode_ms: 0.0,
+            write_ms: 0.0,
+            total_ms: 0.0,
+            bytes_in: 0,
+            bytes_out: 0,
+            status: FileStatus::Ok,
+            error: None,
+            error_kind: None,
+            source_hash: None,
+            invalid_values: InvalidValues::default(),
+            mean_luminance: 0.0,
+            fireflies: 0,
+            qc: Vec::new(),
+            histogram_image: None,
+            pixel_stats: None,
+        }
+    }
+
+    /// Create a record for a file that could not be converted.
+    pub fn failed(source: &Path, error: &ThumbError) -> Self {
+        Self {
+            status: FileStatus::Failed,
+            error: Some(error.to_string()),
+            error_kind: Some(error.kind()),
+            ..Self::new(source)
+        }
+    }
+
+    /// Mark a converted file as failed, keeping what is known about it.
+    pub fn set_failed(&mut self, error: &ThumbError) {
+        self.status = FileStatus::Failed;
+        self.error = Some(error.to_string());
+        self.error_kind = Some(error.kind());
+    }
+
+    /// Create a record for a file whose thumbnail was already up to date.
+    pub fn skipped(source: &Path, output: &Path) -> Self {
+        Self {
+            status: FileStatus::Skipped,
+            output: Some(output.display().to_string()),
+            ..Self::new(source)
+        }
+    }
+
+    /// Store the per-stage durations of this file.
+    pub fn set_timings(&mut self, timings: &StageTimings) {
+        self.read_ms = millis(timings.get(Stage::Read));
+        self.decode_ms = millis(timings.get(Stage::Decode));
+        self.colour_ms = millis(timings.get(Stage::Colour));
+        self.resize_ms = millis(timings.get(Stage::Resize));
+        self.encode_ms = millis(timings.get(Stage::Encode));
+        self.write_ms = millis(timings.get(Stage::Write));
+        self.total_ms = millis(timings.total());
+    }
+
+    fn stage_ms(&self, stage: Stage) -> f64 {
+        match stage {
+            Stage::Read => self.read_ms,
+            Stage::Decode => self.decode_ms,
+            Stage::Colour => self.colour_ms,
+            Stage::Resize => self.resize_ms,
+            Stage::Encode => self.encode_ms,
+            Stage::Write => self.write_ms,
+        }
+    }
+}
+
+/// Total processing time of one of the slowest files
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowFile {
+    pub source: String,
+    pub total_ms: f64,
+}
+
+/// Aggregate timings of the whole run, in milliseconds
+#[derive(Clone, Debug, Serialize)]
+pub struct TimingSummary {
+    pub total_execution_ms: f64,
+    pub processing_ms: f64,
+    pub average_processing_ms: f64,
+    pub stages: Vec<StageSummary>,
+    pub slowest: Vec<SlowFile>,
+}
+
+impl TimingSummary {
+    /// Summarise the run, listing the `slowest` files with the longest total time.
+    pub fn new(total_execution: Duration, stats: &TimingStats, files: &[FileRecord], slowest: usize) -> Self {
+        let converted: Vec<&FileRecord> = files.iter().filter(|f| f.status == FileStatus::Ok).collect();
+        let processing = millis(stats.get_total_time());
+        let stages = Stage::ALL
+            .iter()
+            .map(|&stage| StageSummary::new(stage.name(), converted.iter().map(|f| f.stage_ms(stage)).collect()))
+            .collect();
+
+        let mut by_time = converted.clone();
+        by_time.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+        let slowest = by_time
+            .iter()
+            .take(slowest)
+            .map(|f| SlowFile {
+                source: f.source.clone(),
+                total_ms: f.total_ms,
+            })
+            .collect();
+
+        Self {
+            total_execution_ms: millis(total_execution),
+            processing_ms: processing,
+            average_processing_ms: if converted.is_empty() { 0.0 } else { processing / converted.len() as f64 },
+            stages,
+            slowest,
+        }
+    }
+}
+
+/// File counts of the whole run
+#[derive(Clone, Debug, Serialize)]
+pub struct RunSummary {
+    pub total_files: usize,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub failures_by_kind: BTreeMap<ErrorKind, usize>,
+    pub qc_issues: BTreeMap<QcIssue, usize>,
+    /// Whether the run was interrupted
+    pub cancelled: bool,
+    /// Files not converted because the run was interrupted
+    pub remaining: usize,
+}
+
+/// Complete report of a conversion run
+#[derive(Clone, Debug, Serialize)]
+pub struct RunReport {
+    pub schema_version: u32,
+    pub source_folder: String,
+    pub dest_folder: String,
+    pub height: u32,
+    pub summary: RunSummary,
+    pub timing: TimingSummary,
+    pub files: Vec<FileRecord>,
+}
+
+impl RunReport {
+    pub fn new(
+        source_folder: &Path,
+        dest_folder: &Path,
+        height: u32,
+        timing: TimingSummary,
+        files: Vec<FileRecord>,
+    ) -> Self {
+        let count = |status| files.iter().filter(|f| f.status == status).count();
+        let mut failures_by_kind = BTreeMap::new();
+        for kind in files.iter().filter_map(|f| f.error_kind) {
+            *failures_by_kind.entry(kind).or_insert(0) += 1;
+        }
+        let mut qc_issues = BTreeMap::new();
+        for issue in files.iter().flat_map(|f| &f.qc) {
+            *qc_issues.entry(*issue).or_insert(0) += 1;
+        }
+        let summary = RunSummary {
+            total_files: files.len(),
+            succeeded: count(FileStatus::Ok),
+            skipped: count(FileStatus::Skipped),
+            failed: count(FileStatus::Failed),
+            failures_by_kind,
+            qc_issues,
+            cancelled: false,
+            remaining: 0,
+        };
+        Self {
+            schema_version: SCHEMA_VERSION,
+            source_folder: source_folder.display().to_string(),
+            dest_folder: dest_folder.display().to_string(),
+            height,
+            summary,
+            timing,
+            files,
+        }
+    }
+
+    /// Mark the run as interrupted with `remaining` files not converted.
+    pub fn set_cancelled(&mut self, remaining: usize) {
+        self.summary.cancelled = true;
+        self.summary.remaining = remaining;
+        self.summary.total_files += remaining;
+    }
+
+    /// Write the report to `path` in the given format.
+    pub fn write(&self, path: &Path, format: ReportFormat) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ReportFormat::Text => self.write_text(&mut out)?,
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)?;
+            }
+            ReportFormat::Csv => {
+                self.write_csv(&mut out)?;
+                let mut summary = BufWriter::new(File::create(summary_csv_path(path))?);
+                self.write_summary_csv(&mut summary)?;
+                summary.flush()?;
+            }
+        }
+        out.flush()
+    }
+
+    fn write_text(&self, out: &mut impl Write) -> io::Result<()> {
+        let t = &self.timing;
+        writeln!(out, "=== EXR to Thumbnail Conversion Statistics ===")?;
+        writeln!(out, "Source Folder: {}", self.source_folder)?;
+        writeln!(out, "Destination Folder: {}", self.dest_folder)?;
+        writeln!(out, "Target Thumbnail Height: {}px", self.height)?;
+        writeln!(out, "============================================")?;
+        writeln!(out, "Total files found: {}", self.summary.total_files)?;
+        writeln!(out, "Successfully converted: {}", self.summary.succeeded)?;
+        writeln!(out, "Skipped (up to date or kept): {}", self.summary.skipped)?;
+        writeln!(out, "Failed to convert: {}", self.summary.failed)?;
+        for (kind, count) in &self.summary.failures_by_kind {
+            writeln!(out, "  {}: {}", kind.as_str(), count)?;
+        }
+        if self.summary.cancelled {
+            writeln!(out, "Cancelled, not converted: {}", self.summary.remaining)?;
+        }
+        if !self.summary.qc_issues.is_empty() {
+            writeln!(out, "QC issues:")?;
+            for (issue, count) in &self.summary.qc_issues {
+                writeln!(out, "  {}: {}", issue.as_str(), count)?;
+            }
+        }
+        writeln!(out, "============================================")?;
+        writeln!(out, "Timing Breakdown (Parallel Processing):")?;
+        writeln!(out, "  Total execution time: {:.2}ms", t.total_execution_ms)?;
+        writeln!(out, "  Total processing time: {:.2}ms (sum of all files)", t.processing_ms)?;
+        if self.summary.succeeded > 0 {
+            writeln!(out, "  Average total time per converted file: {:.2}ms", t.average_processing_ms)?;
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "  {:<8} {:>12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "stage", "total", "average", "min", "median", "p95", "max"
+        )?;
+        for stage in &t.stages {
+            writeln!(
+                out,
+                "  {:<8} {:>10.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms",
+                stage.stage, stage.total_ms, stage.average_ms, stage.min_ms, stage.median_ms, stage.p95_ms, stage.max_ms
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(out, "Note: Due to parallel processing, total execution time is much shorter")?;
+        writeln!(out, "than the sum of individual file processing times.")?;
+        if !t.slowest.is_empty() {
+            writeln!(out, "============================================")?;
+            writeln!(out, "Slowest files:")?;
+            for file in &t.slowest {
+                writeln!(out, "  {:>10.2}ms  {}", file.total_ms, file.source)?;
+            }
+        }
+        writeln!(out, "============================================")?;
+        writeln!(out, "Files:")?;
+        for file in &self.files {
+            match file.status {
+                FileStatus::Ok => writeln!(
+                    out,
+                    "  [ok] {} -> {} ({}x{} -> {}x{}, {:.2}ms)",
+                    file.source,
+                    file.output.as_deref().unwrap_or(""),
+                    file.source_width,
+                    file.source_height,
+                    file.thumb_width,
+                    file.thumb_height,
+                    file.total_ms,
+                )?,
+                FileStatus::Skipped => writeln!(
+                    out,
+                    "  [skipped] {} -> {}",
+                    file.source,
+                    file.output.as_deref().unwrap_or("")
+                )?,
+                FileStatus::Failed => writeln!(
+                    out,
+                    "  [failed] {}: {}",
+                    file.source,
+                    file.error.as_deref().unwrap_or("")
+                )?,
+            }
+            if !file.invalid_values.is_empty() {
+                writeln!(out, "      {}", file.invalid_values)?;
+            }
+            if !file.qc.is_empty() {
+                writeln!(out, "      QC: {}", qc_list(&file.qc, ", "))?;
+            }
+            if let Some(stats) = &file.pixel_stats {
+                for (name, channel) in ["R", "G", "B", "A"].iter().zip(&stats.channels) {
+                    writeln!(
+                        out,
+                        "      {}: min {:.4}, max {:.4}, mean {:.4}, stddev {:.4}",
+                        name, channel.min, channel.max, channel.mean, channel.stddev
+                    )?;
+                }
+            }
+        }
+        writeln!(out, "============================================")?;
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "source,output,source_width,source_height,thumb_width,thumb_height,layer,\
+             read_ms,decode_ms,colour_ms,resize_ms,encode_ms,write_ms,total_ms,\
+             bytes_in,bytes_out,status,error,error_kind,source_hash,\
+             nan_values,inf_values,negative_values,mean_luminance,fireflies,qc,histogram_image,{},histogram",
+            ["r", "g", "b", "a"]
+                .iter()
+                .flat_map(|c| ["min", "max", "mean", "stddev"].map(|stat| format!("{}_{}", c, stat)))
+                .collect::<Vec<_>>()
+                .join(",")
+        )?;
+        for f in &self.files {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{},{},{},{},{},{:.6},{},{},{},{}",
+                csv_field(&f.source),
+                csv_field(f.output.as_deref().unwrap_or("")),
+                f.source_width,
+                f.source_height,
+                f.thumb_width,
+                f.thumb_height,
+                csv_field(f.layer.as_deref().unwrap_or("")),
+                f.read_ms,
+                f.decode_ms,
+                f.colour_ms,
+                f.resize_ms,
+                f.encode_ms,
+                f.write_ms,
+                f.total_ms,
+                f.bytes_in,
+                f.bytes_out,
+                f.status.as_str(),
+                csv_field(f.error.as_deref().unwrap_or("")),
+                f.error_kind.map_or("", ErrorKind::as_str),
+                f.source_hash.as_deref().unwrap_or(""),
+                f.invalid_values.nan_count(),
+                f.invalid_values.inf_count(),
+                f.invalid_values.negative_count(),
+                f.mean_luminance,
+                f.fireflies,
+                qc_list(&f.qc, ";"),
+                csv_field(f.histogram_image.as_deref().unwrap_or("")),
+                pixel_stats_csv(f.pixel_stats.as_ref()),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_summary_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let t = &self.timing;
+        writeln!(out, "key,value")?;
+        writeln!(out, "schema_version,{}", self.schema_version)?;
+        writeln!(out, "source_folder,{}", csv_field(&self.source_folder))?;
+        writeln!(out, "dest_folder,{}", csv_field(&self.dest_folder))?;
+        writeln!(out, "height,{}", self.height)?;
+        writeln!(out, "total_files,{}", self.summary.total_files)?;
+        writeln!(out, "succeeded,{}", self.summary.succeeded)?;
+        writeln!(out, "skipped,{}", self.summary.skipped)?;
+        writeln!(out, "failed,{}", self.summary.failed)?;
+        for (kind, count) in &self.summary.failures_by_kind {
+            writeln!(out, "failed.{},{}", kind.as_str(), count)?;
+        }
+        for (issue, count) in &self.summary.qc_issues {
+            writeln!(out, "qc.{},{}", issue.as_str(), count)?;
+        }
+        writeln!(out, "cancelled,{}", self.summary.cancelled)?;
+        writeln!(out, "remaining,{}", self.summary.remaining)?;
+        writeln!(out, "total_execution_ms,{:.3}", t.total_execution_ms)?;
+        writeln!(out, "processing_ms,{:.3}", t.processing_ms)?;
+        writeln!(out, "average_processing_ms,{:.3}", t.average_processing_ms)?;
+        for stage in &t.stages {
+            writeln!(out, "{}.total_ms,{:.3}", stage.stage, stage.total_ms)?;
+            writeln!(out, "{}.average_ms,{:.3}", stage.stage, stage.average_ms)?;
+            writeln!(out, "{}.min_ms,{:.3}", stage.stage, stage.min_ms)?;
+            writeln!(out, "{}.median_ms,{:.3}", stage.stage, stage.median_ms)?;
+            writeln!(out, "{}.p95_ms,{:.3}", stage.stage, stage.p95_ms)?;
+            writeln!(out, "{}.max_ms,{:.3}", stage.stage, stage.max_ms)?;
+            for bucket in &stage.histogram {
+                match bucket.le_ms {
+                    Some(bound) => writeln!(out, "{}.histogram.le_{}ms,{}", stage.stage, bound, bucket.count)?,
+                    None => writeln!(out, "{}.histogram.inf,{}", stage.stage, bucket.count)?,
+                }
+            }
+        }
+        for (rank, file) in t.slowest.iter().enumerate() {
+            writeln!(out, "slowest.{}.source,{}", rank + 1, csv_field(&file.source))?;
+            writeln!(out, "slowest.{}.total_ms,{:.3}", rank + 1, file.total_ms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the aggregate CSV written next to the per-file CSV.
+pub fn summary_csv_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_summary.csv", stem))
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn qc_list(issues: &[QcIssue], separator: &str) -> String {
+    issues.iter().map(|issue| issue.as_str()).collect::<Vec<_>>().join(separator)
+}
+
+/// The channel statistics and histogram columns of a CSV row.
+fn pixel_stats_csv(stats: Option<&PixelStats>) -> String {
+    let Some(stats) = stats else {
+        return ",".repeat(16);
+    };
+    let mut fields: Vec<String> = stats
+        .channels
+        .iter()
+        .flat_map(|c| [c.min as f64, c.max as f64, c.mean, c.stddev].map(|value| format!("{:.6}", value)))
+        .collect();
+    fields.push(stats.histogram.iter().map(|count| count.to_string()).collect::<Vec<_>>().join(";"));
+    fields.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr_thumbnailer::stats::ChannelStats;
+
+    /// Split a CSV line into its fields, honouring quoted fields.
+    fn csv_fields(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn report() -> RunReport {
+        let mut converted = FileRecord::new(Path::new("/renders/shot, final_1001.exr"));
+        converted.output = Some("/thumbs/shot, final_1001.png".to_string());
+        converted.qc = vec![QcIssue::Black, QcIssue::Constant];
+        converted.pixel_stats = Some(PixelStats {
+            channels: [ChannelStats {
+                min: 0.0,
+                max: 1.0,
+                mean: 0.5,
+                stddev: 0.25,
+            }; 4],
+            histogram: vec![3, 0, 5],
+        });
+        let plain = FileRecord::new(Path::new("/renders/shot_1002.exr"));
+        let failed = FileRecord::failed(
+            Path::new("/renders/shot_1003.exr"),
+            &ThumbError::CorruptData("bad \"block\", line 2".to_string()),
+        );
+        let skipped = FileRecord::skipped(Path::new("/renders/shot_1004.exr"), Path::new("/thumbs/shot_1004.png"));
+        let files = vec![converted, plain, failed, skipped];
+        let timing = TimingSummary::new(Duration::from_millis(10), &TimingStats::new(), &files, 3);
+        RunReport::new(Path::new("/renders"), Path::new("/thumbs"), 256, timing, files)
+    }
+
+    #[test]
+    fn csv_rows_have_as_many_fields_as_the_header() {
+        let mut csv = Vec::new();
+        report().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+
+        let header = csv_fields(lines[0]);
+        assert_eq!(header.len(), 44);
+        for line in &lines[1..] {
+            let row = csv_fields(line);
+            assert_eq!(row.len(), header.len(), "{}", line);
+        }
+
+        let column = |name: &str| header.iter().position(|field| field == name).unwrap();
+        let converted = csv_fields(lines[1]);
+        assert_eq!(converted[column("source")], "/renders/shot, final_1001.exr");
+        assert_eq!(converted[column("qc")], "black;constant");
+        assert_eq!(converted[column("r_min")], "0.000000");
+        assert_eq!(converted[column("a_stddev")], "0.250000");
+        assert_eq!(converted[column("histogram")], "3;0;5");
+        // Without --stats the statistics columns are empty
+        let plain = csv_fields(lines[2]);
+        assert!(plain[column("r_min")..].iter().all(String::is_empty));
+        let failed = csv_fields(lines[3]);
+        assert_eq!(failed[column("error")], "corrupt pixel data: bad \"block\", line 2");
+        assert_eq!(failed[column("error_kind")], "corrupt_data");
+        assert_eq!(csv_fields(lines[4])[column("status")], "skipped");
+    }
+
+    #[test]
+    fn json_reports_carry_the_schema_version() {
+        let json = serde_json::to_value(report()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        let keys: Vec<&str> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            ["dest_folder", "files", "height", "schema_version", "source_folder", "summary", "timing"]
+        );
+
+        let summary = &json["summary"];
+        assert_eq!(summary["total_files"], 4);
+        assert_eq!(summary["succeeded"], 2);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["failures_by_kind"]["corrupt_data"], 1);
+        assert_eq!(summary["qc_issues"]["black"], 1);
+
+        let file = &json["files"][0];
+        assert_eq!(file["status"], "ok");
+        assert_eq!(file["qc"], serde_json::json!(["black", "constant"]));
+        assert_eq!(file["pixel_stats"]["channels"][0]["mean"], 0.5);
+        assert_eq!(file["pixel_stats"]["histogram"], serde_json::json!([3, 0, 5]));
+        assert!(file["invalid_values"].is_object());
+        assert!(json["files"][1]["pixel_stats"].is_null());
+    }
+}
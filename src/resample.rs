@@ -0,0 +1,236 @@
+//! Downsampling of linear HDR pixels for large reduction ratios.
+//!
+//! Filtering the decoded f32 data instead of the 8 bit display image averages
+//! light rather than gamma encoded values and keeps highlights above 1.0 for
+//! the tone mapping. The image crate clamps float pixels to 0..1 while
+//! resizing, so both steps are implemented here.
+
+use crate::invalid::{InvalidValues, NON_FINITE};
+use crate::qc::{FrameStats, QcAccumulator};
+use crate::stats::{PixelStats, StatsAccumulator};
+use crate::thumbnail::ThumbnailSize;
+
+/// Lobes of the final windowed-sinc pass
+const LANCZOS_LOBES: f32 = 3.0;
+
+/// Box averages pixels by an integer factor as they are decoded, so the full
+/// resolution image is never held in memory.
+///
+/// The factors are chosen so the reduced image stays at least twice the
+/// thumbnail size, leaving the final scaling to the selected filter.
+pub(crate) struct BoxReducer {
+    source_width: u32,
+    source_height: u32,
+    factor_x: u32,
+    factor_y: u32,
+    width: u32,
+    sums: Vec<[f32; 4]>,
+    invalid: InvalidValues,
+    /// `NON_FINITE` and `NEGATIVE` flags per reduced pixel, empty while
+    /// all values are valid
+    flags: Vec<u8>,
+    qc: QcAccumulator,
+    stats: Option<StatsAccumulator>,
+}
+
+impl BoxReducer {
+    pub fn for_thumbnail(source_width: u32, source_height: u32, size: ThumbnailSize, firefly_threshold: f32) -> Self {
+        let (factor_x, factor_y) = reduction_factors(source_width, source_height, size);
+        let width = source_width.div_ceil(factor_x);
+        let height = source_height.div_ceil(factor_y);
+        Self {
+            source_width,
+            source_height,
+            factor_x,
+            factor_y,
+            width,
+            sums: vec![[0.0; 4]; width as usize * height as usize],
+            invalid: InvalidValues::default(),
+            flags: Vec::new(),
+            qc: QcAccumulator::new(firefly_threshold),
+            stats: None,
+        }
+    }
+
+    /// Also gather [`PixelStats`] of the source pixels
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(StatsAccumulator::new());
+        self
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, mut pixel: [f32; 4]) {
+        let index = y / self.factor_y as usize * self.width as usize + x / self.factor_x as usize;
+        let flags = self.invalid.check(pixel);
+        if flags != 0 {
+            if self.flags.is_empty() {
+                self.flags = vec![0; self.sums.len()];
+            }
+            self.flags[index] |= flags;
+            pixel = pixel.map(|value| if value.is_finite() { value } else { 0.0 });
+        }
+        if flags & NON_FINITE == 0 {
+            self.qc.add(index, self.sums.len(), pixel);
+            if let Some(stats) = &mut self.stats {
+                stats.add(pixel);
+            }
+        }
+        let sum = &mut self.sums[index];
+        for c in 0..4 {
+            sum[c] += pixel[c];
+        }
+    }
+
+    pub fn source_size(&self) -> (u32, u32) {
+        (self.source_width, self.source_height)
+    }
+
+    pub fn invalid(&self) -> InvalidValues {
+        self.invalid
+    }
+
+    /// Invalid value flags of the reduced pixels, empty if there are none
+    pub fn take_flags(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.flags)
+    }
+
+    pub fn take_pixel_stats(&mut self) -> Option<PixelStats> {
+        self.stats.take().map(StatsAccumulator::finish)
+    }
+
+    /// Size of the reduced image
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.source_height.div_ceil(self.factor_y))
+    }
+
+    /// The averaged pixels and the QC statistics of the frame. Blocks cut
+    /// off by the right and bottom edges average the pixels they contain.
+    pub fn finish(mut self) -> (Vec<[f32; 4]>, FrameStats) {
+        let (width, height) = self.size();
+        if (self.factor_x, self.factor_y) != (1, 1) {
+            for (index, sum) in self.sums.iter_mut().enumerate() {
+                let (x, y) = ((index % width as usize) as u32, (index / width as usize) as u32);
+                let columns = (self.source_width - x * self.factor_x).min(self.factor_x);
+                let rows = (self.source_height - y * self.factor_y).min(self.factor_y);
+                let scale = 1.0 / (rows * columns) as f32;
+                *sum = sum.map(|value| value * scale);
+            }
+        }
+        let stats = self.qc.finish(&self.sums, width, height);
+        (self.sums, stats)
+    }
+}
+
+/// Integer factors that reduce a source image to between two and four times
+/// the thumbnail size, or 1 if it is not that large.
+pub(crate) fn reduction_factors(width: u32, height: u32, size: ThumbnailSize) -> (u32, u32) {
+    let (target_width, target_height) = size.dimensions(width, height);
+    (
+        (width / target_width.max(1).saturating_mul(2)).max(1),
+        (height / target_height.max(1).saturating_mul(2)).max(1),
+    )
+}
+
+/// Scale linear RGBA pixels to `target_width` x `target_height` with a
+/// Lanczos3 filter.
+pub(crate) fn lanczos_resize(
+    pixels: Vec<[f32; 4]>,
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<[f32; 4]> {
+    if target_width == 0 || target_height == 0 {
+        return Vec::new();
+    }
+    if (width, height) == (target_width, target_height) {
+        return pixels;
+    }
+
+    let pixels = lanczos_horizontal(&pixels, width, height, target_width);
+    lanczos_vertical(&pixels, target_width, height, target_height)
+}
+
+/// Contributions of the source samples to each of `target` output samples.
+struct Weights {
+    /// First source index per output sample
+    starts: Vec<usize>,
+    /// Normalised weights per output sample, starting at `starts`
+    weights: Vec<Vec<f32>>,
+}
+
+impl Weights {
+    fn lanczos(source: u32, target: u32) -> Self {
+        let ratio = source as f32 / target as f32;
+        // Widen the kernel when downscaling so every source sample contributes
+        let scale = ratio.max(1.0);
+        let support = LANCZOS_LOBES * scale;
+
+        let mut starts = Vec::with_capacity(target as usize);
+        let mut weights = Vec::with_capacity(target as usize);
+        for i in 0..target {
+            let center = (i as f32 + 0.5) * ratio;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(source as usize);
+            let mut row: Vec<f32> = (start..end)
+                .map(|j| lanczos((j as f32 + 0.5 - center) / scale))
+                .collect();
+            let total: f32 = row.iter().sum();
+            if total != 0.0 {
+                row.iter_mut().for_each(|weight| *weight /= total);
+            }
+            starts.push(start);
+            weights.push(row);
+        }
+        Self { starts, weights }
+    }
+}
+
+fn lanczos(x: f32) -> f32 {
+    if x.abs() >= LANCZOS_LOBES {
+        return 0.0;
+    }
+    sinc(x) * sinc(x / LANCZOS_LOBES)
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+fn lanczos_horizontal(pixels: &[[f32; 4]], width: u32, height: u32, target_width: u32) -> Vec<[f32; 4]> {
+    let weights = Weights::lanczos(width, target_width);
+    let mut out = Vec::with_capacity(target_width as usize * height as usize);
+    for row in pixels.chunks_exact(width as usize) {
+        for (start, row_weights) in weights.starts.iter().zip(&weights.weights) {
+            let mut sum = [0.0f32; 4];
+            for (pixel, weight) in row[*start..].iter().zip(row_weights) {
+                for c in 0..4 {
+                    sum[c] += pixel[c] * weight;
+                }
+            }
+            out.push(sum);
+        }
+    }
+    out
+}
+
+fn lanczos_vertical(pixels: &[[f32; 4]], width: u32, height: u32, target_height: u32) -> Vec<[f32; 4]> {
+    let weights = Weights::lanczos(height, target_height);
+    let width = width as usize;
+    let mut out = vec![[0.0f32; 4]; width * target_height as usize];
+    for (out_row, (start, row_weights)) in out.chunks_exact_mut(width).zip(weights.starts.iter().zip(&weights.weights)) {
+        for (offset, weight) in row_weights.iter().enumerate() {
+            let row = &pixels[(start + offset) * width..(start + offset + 1) * width];
+            for (sum, pixel) in out_row.iter_mut().zip(row) {
+                for c in 0..4 {
+                    sum[c] += pixel[c] * weight;
+                }
+            }
+        }
+    }
+    out
+}
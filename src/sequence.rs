@@ -0,0 +1,137 @@
+//! Brightness jump detection across the frames of image sequences.
+//!
+//! Files named `<prefix><frame number>.exr`, e.g. `shot_1001.exr`, with the
+//! same prefix in the same folder form a sequence. Each converted frame is
+//! compared with the previous converted frame of its sequence.
+
+use exr_thumbnailer::qc::{self, QcIssue};
+use exr_thumbnailer::{ThumbError, ThumbnailRequest};
+use std::borrow::BorrowMut;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::output;
+use crate::report::{FileRecord, FileStatus};
+
+/// Add [`QcIssue::BrightnessJump`] to the records of frames whose mean
+/// luminance jumps by more than `threshold_stops`. If `border` is given,
+/// their thumbnails are generated again with it and a border drawn on them.
+/// Returns the indices of the flagged records.
+///
+/// Frames flagged before are left alone, so watch mode can check the whole
+/// folder again whenever new frames arrive.
+pub fn flag_brightness_jumps(
+    records: &mut [impl BorrowMut<FileRecord>],
+    threshold_stops: f64,
+    border: Option<&ThumbnailRequest>,
+) -> Vec<usize> {
+    let mut sequences: BTreeMap<(PathBuf, String), Vec<(u64, usize)>> = BTreeMap::new();
+    let converted = records
+        .iter()
+        .map(|record| record.borrow())
+        .enumerate()
+        .filter(|(_, record)| record.status == FileStatus::Ok);
+    for (index, record) in converted {
+        if let Some((key, frame)) = sequence_frame(Path::new(&record.source)) {
+            sequences.entry(key).or_default().push((frame, index));
+        }
+    }
+
+    let mut flagged = Vec::new();
+    for frames in sequences.values_mut() {
+        frames.sort_unstable();
+        let means: Vec<f64> = frames.iter().map(|&(_, index)| records[index].borrow().mean_luminance).collect();
+        let jumps = qc::brightness_jumps(&means, threshold_stops);
+        for (&(_, index), _) in frames.iter().zip(jumps).filter(|(_, jump)| *jump) {
+            let record = records[index].borrow_mut();
+            if record.qc.contains(&QcIssue::BrightnessJump) {
+                continue;
+            }
+            // Frames with other issues already have a border
+            if record.qc.is_empty() {
+                if let (Some(request), Some(output)) = (border, &record.output) {
+                    if let Err(e) = add_border(request, Path::new(&record.source), Path::new(output)) {
+                        eprintln!("Warning: could not mark {}: {}", output, e);
+                    }
+                }
+            }
+            record.qc.push(QcIssue::BrightnessJump);
+            flagged.push(index);
+        }
+    }
+    flagged.sort_unstable();
+    flagged
+}
+
+/// The sequence `path` belongs to, as its folder and name prefix, and its
+/// frame number.
+fn sequence_frame(path: &Path) -> Option<((PathBuf, String), u64)> {
+    let stem = path.file_stem()?.to_str()?;
+    let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let frame = stem[prefix.len()..].parse().ok()?;
+    let folder = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    Some(((folder, prefix.to_string()), frame))
+}
+
+/// Replace the thumbnail of a flagged frame by one with the QC border.
+/// Decoding the source again keeps the provenance of the thumbnail and
+/// spares JPEGs a second lossy compression.
+fn add_border(request: &ThumbnailRequest, source: &Path, output: &Path) -> Result<(), ThumbError> {
+    let mut thumbnail = request.clone().source(source).thumbnail()?;
+    qc::draw_border(&mut thumbnail.image);
+    output::write_atomic(output, &thumbnail.encode()?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::*;
+    use exr_thumbnailer::qc::QC_BORDER_COLOUR;
+    use exr_thumbnailer::ThumbnailSize;
+    use std::fs;
+
+    #[test]
+    fn flagged_frames_keep_their_provenance() {
+        let dir = std::env::temp_dir().join(format!("exr_thumbnailer_sequence_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let request = ThumbnailRequest::default().size(ThumbnailSize::Height(16)).qc_border(true);
+        let mut records: Vec<FileRecord> = [("shot_1001", 0.01_f32), ("shot_1002", 1.0)]
+            .into_iter()
+            .map(|(name, value)| {
+                let source = dir.join(format!("{}.exr", name));
+                let channels = SpecificChannels::rgba(|_| (value, value, value, 1.0_f32));
+                Image::from_channels((32, 16), channels).write().to_file(&source).unwrap();
+                let thumbnail = request.clone().source(&source).thumbnail().unwrap();
+                let output = dir.join(format!("{}.png", name));
+                fs::write(&output, thumbnail.encode().unwrap()).unwrap();
+                let mut record = FileRecord::new(&source);
+                record.mean_luminance = thumbnail.stats.mean_luminance;
+                record.output = Some(output.display().to_string());
+                record
+            })
+            .collect();
+
+        assert_eq!(flag_brightness_jumps(&mut records, 1.0, Some(&request)), [1]);
+        assert_eq!(records[1].qc, [QcIssue::BrightnessJump]);
+        // Checking again flags nothing new
+        assert!(flag_brightness_jumps(&mut records, 1.0, Some(&request)).is_empty());
+        assert_eq!(records[1].qc, [QcIssue::BrightnessJump]);
+
+        let file = fs::File::open(dir.join("shot_1002.png")).unwrap();
+        let mut png = png::Decoder::new(std::io::BufReader::new(file)).read_info().unwrap();
+        let text: Vec<(String, String)> = png
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+            .collect();
+        let source = fs::canonicalize(dir.join("shot_1002.exr")).unwrap();
+        assert!(text.contains(&("exrthumb:source".to_string(), source.display().to_string())));
+        assert!(text.iter().any(|(keyword, _)| keyword == "exrthumb:source_hash"));
+        let mut pixels = vec![0; png.output_buffer_size().unwrap()];
+        png.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels[..4], QC_BORDER_COLOUR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
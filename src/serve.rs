@@ -0,0 +1,691 @@
+//! HTTP server mode: thumbnails on demand for web review tools.
+//!
+//! `GET /thumb?path=<relative path>&h=<height>&layer=<name>&format=<png|jpeg|webp>`
+//! returns the encoded thumbnail of an EXR file below the served root; `HEAD`
+//! returns its headers without generating it. Generated thumbnails are kept in
+//! an on-disk cache keyed by the path and content hash of the source and the
+//! request parameters, evicting the least recently used ones when the cache
+//! grows over its size limit. Thumbnails are generated on the rayon pool;
+//! requests beyond the concurrency limit are answered with 503.
+
+use clap::ValueEnum;
+use exr_thumbnailer::{
+    content_hash, file_content_hash, freedesktop, OutputFormat, ThumbError, ThumbnailRequest, ThumbnailSize,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{is_exr_file, output, render_request, Exit, ServeArgs, MAX_HEIGHT};
+
+/// Height used when a request has no `h` parameter
+const DEFAULT_HEIGHT: u32 = 256;
+/// Source hashes remembered at most; the least recently used half is
+/// forgotten when there are more
+const MAX_SOURCE_HASHES: usize = 10_000;
+
+/// Serve thumbnails until the process is stopped.
+pub fn serve(args: &ServeArgs) -> io::Result<Exit> {
+    let root = match fs::canonicalize(&args.root) {
+        Ok(root) if root.is_dir() => root,
+        _ => {
+            eprintln!("Error: {} is not a valid directory.", args.root.display());
+            return Ok(Exit::NoInput);
+        }
+    };
+    let cache_dir = match &args.cache_dir {
+        Some(dir) => dir.clone(),
+        None => freedesktop::cache_home()?.join("exr_thumbnailer"),
+    };
+    let cache = DiskCache::open(cache_dir, args.cache_size_mb * 1024 * 1024)?;
+
+    let server = Server::http(&args.bind).map_err(io::Error::other)?;
+    println!(
+        "Serving thumbnails of {} on http://{} (cache {})",
+        root.display(),
+        server.server_addr(),
+        cache.dir.display()
+    );
+
+    let render = &args.render;
+    let max_concurrent = args.max_concurrent.unwrap_or_else(rayon::current_num_threads).max(1);
+    // Concurrent requests each decode in one thread instead of a pool per CPU
+    let decode_threads = if max_concurrent > 1 { 1 } else { 0 };
+    let state = Arc::new(State {
+        root,
+        request: render_request(render).decode_threads(decode_threads),
+        render_settings: format!(
+            "tone_map={};gamma={};filter={}",
+            render.tone_map().as_str(),
+            render.gamma,
+            render.filter.as_str()
+        ) + if render.flag_invalid { ";flag_invalid" } else { "" }
+            + &if render.qc_border {
+                format!(";qc_border;firefly_threshold={}", render.firefly_threshold)
+            } else {
+                String::new()
+            },
+        cache,
+        hashes: Mutex::new(HashMap::new()),
+        in_flight: AtomicUsize::new(0),
+        max_concurrent,
+        verbose: args.verbose,
+    });
+
+    for request in server.incoming_requests() {
+        if state.in_flight.fetch_add(1, Ordering::SeqCst) >= state.max_concurrent {
+            state.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let reply = Reply::error(503, "too many concurrent requests");
+            state.log(&request, &reply, Instant::now());
+            let _ = request.respond(reply.into_response().with_header(header("Retry-After", "1")));
+            continue;
+        }
+        let state = Arc::clone(&state);
+        rayon::spawn(move || {
+            state.handle(request);
+            state.in_flight.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(Exit::Success)
+}
+
+/// Content hash of a source file, valid while its size and mtime are unchanged
+struct SourceHash {
+    size: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+    last_used: Instant,
+}
+
+struct State {
+    root: PathBuf,
+    /// Colour and filter settings of every thumbnail
+    request: ThumbnailRequest,
+    /// The same settings as part of the cache key
+    render_settings: String,
+    cache: DiskCache,
+    /// Remembered source hashes, so cache hits do not read the source
+    hashes: Mutex<HashMap<PathBuf, SourceHash>>,
+    in_flight: AtomicUsize,
+    max_concurrent: usize,
+    verbose: bool,
+}
+
+impl State {
+    fn handle(&self, request: Request) {
+        let start = Instant::now();
+        let reply = self.reply(&request).unwrap_or_else(|reply| reply);
+        self.log(&request, &reply, start);
+        let mut response = reply.into_response();
+        if *request.method() == Method::Head {
+            // Only the headers; the length of the body is not known
+            response = response.with_data(io::Cursor::new(Vec::new()), None);
+        }
+        let _ = request.respond(response);
+    }
+
+    fn log(&self, request: &Request, reply: &Reply, start: Instant) {
+        if self.verbose || reply.status >= 500 {
+            eprintln!(
+                "{} {} {} {:.2}ms",
+                request.method(),
+                request.url(),
+                reply.status,
+                start.elapsed().as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    fn reply(&self, request: &Request) -> Result<Reply, Reply> {
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return Err(Reply::error(405, "only GET is supported"));
+        }
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        if path != "/thumb" {
+            return Err(Reply::error(404, "not found"));
+        }
+        let params = parse_query(query);
+
+        let requested = params.get("path").ok_or_else(|| Reply::error(400, "missing path parameter"))?;
+        let height = match params.get("h") {
+            None => DEFAULT_HEIGHT,
+            Some(h) => h
+                .parse()
+                .ok()
+                .filter(|h| (1..=MAX_HEIGHT).contains(h))
+                .ok_or_else(|| Reply::error(400, format!("h must be between 1 and {}", MAX_HEIGHT)))?,
+        };
+        let format = match params.get("format") {
+            None => OutputFormat::Png,
+            Some(format) => OutputFormat::from_str(format, true)
+                .map_err(|_| Reply::error(400, "format must be png, jpeg or webp"))?,
+        };
+        let layer = params.get("layer").filter(|layer| !layer.is_empty()).cloned();
+
+        let source = resolve(&self.root, requested)?;
+        let source_hash = self.source_hash(&source).map_err(|e| Reply::thumb_error(&e.into()))?;
+        // The path is part of the key as it is embedded into the thumbnail
+        let key = content_hash(
+            format!(
+                "{};{};h={};layer={};format={};{}",
+                source_hash,
+                source.display(),
+                height,
+                layer.as_deref().unwrap_or(""),
+                format.extension(),
+                self.render_settings
+            )
+            .as_bytes(),
+        );
+
+        let not_modified = request
+            .headers()
+            .iter()
+            .any(|h| h.field.equiv("If-None-Match") && h.value.as_str().trim_matches('"') == key);
+        if not_modified {
+            return Ok(Reply {
+                status: 304,
+                content_type: format.mime_type(),
+                body: Vec::new(),
+                etag: Some(key),
+            });
+        }
+
+        // HEAD only needs the headers, no thumbnail is generated for it
+        if *request.method() == Method::Head {
+            return Ok(Reply::image(format, Vec::new(), key));
+        }
+        let cache_name = format!("{}.{}", key, format.extension());
+        if let Some(body) = self.cache.get(&cache_name) {
+            return Ok(Reply::image(format, body, key));
+        }
+
+        let thumbnail = self
+            .request
+            .clone()
+            .source(&source)
+            .size(ThumbnailSize::Height(height))
+            .maybe_layer(layer)
+            .format(format)
+            .thumbnail()
+            .map_err(|e| Reply::thumb_error(&e))?;
+        let body = thumbnail.encode().map_err(|e| Reply::thumb_error(&e))?;
+        // A source rewritten since it was hashed must not fill the cache
+        // entry of its old contents
+        if thumbnail.source_hash.as_deref() == Some(source_hash.as_str()) {
+            if let Err(e) = self.cache.insert(&cache_name, &body) {
+                eprintln!("Warning: could not write to the thumbnail cache: {}", e);
+            }
+        }
+        Ok(Reply::image(format, body, key))
+    }
+
+    /// Content hash of `source`, read in chunks unless it is remembered.
+    fn source_hash(&self, source: &Path) -> io::Result<String> {
+        let meta = fs::metadata(source)?;
+        let modified = meta.modified().ok();
+        if let Some(known) = self.hashes.lock().unwrap().get_mut(source) {
+            if known.size == meta.len() && known.modified == modified {
+                known.last_used = Instant::now();
+                return Ok(known.hash.clone());
+            }
+        }
+        let hash = file_content_hash(source)?;
+        let mut hashes = self.hashes.lock().unwrap();
+        if hashes.len() >= MAX_SOURCE_HASHES {
+            forget_least_recent(&mut hashes, MAX_SOURCE_HASHES / 2);
+        }
+        hashes.insert(
+            source.to_path_buf(),
+            SourceHash {
+                size: meta.len(),
+                modified,
+                hash: hash.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(hash)
+    }
+}
+
+/// Forget all but the `keep` most recently used source hashes.
+fn forget_least_recent(hashes: &mut HashMap<PathBuf, SourceHash>, keep: usize) {
+    if hashes.len() <= keep {
+        return;
+    }
+    let mut by_use: Vec<(Instant, PathBuf)> =
+        hashes.iter().map(|(path, known)| (known.last_used, path.clone())).collect();
+    by_use.sort();
+    for (_, path) in &by_use[..by_use.len() - keep] {
+        hashes.remove(path);
+    }
+}
+
+/// Response to a request
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    etag: Option<String>,
+}
+
+impl Reply {
+    fn image(format: OutputFormat, body: Vec<u8>, etag: String) -> Self {
+        Self {
+            status: 200,
+            content_type: format.mime_type(),
+            body,
+            etag: Some(etag),
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        let mut body = message.into().into_bytes();
+        body.push(b'\n');
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+            etag: None,
+        }
+    }
+
+    fn thumb_error(error: &ThumbError) -> Self {
+        let status = match error {
+            ThumbError::Io(e) if e.kind() == io::ErrorKind::NotFound => 404,
+            ThumbError::Io(_) | ThumbError::Encode(_) => 500,
+            ThumbError::MissingLayer(_) => 404,
+            _ => 422,
+        };
+        Self::error(status, error.to_string())
+    }
+
+    fn into_response(self) -> Response<io::Cursor<Vec<u8>>> {
+        let mut response = Response::from_data(self.body)
+            .with_status_code(self.status)
+            .with_header(header("Content-Type", self.content_type));
+        if let Some(etag) = self.etag {
+            response = response.with_header(header("ETag", &format!("\"{}\"", etag)));
+        }
+        response
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+/// Resolve a requested path below `root`, which must be canonical. Paths
+/// with `..` are refused outright; symbolic links that lead out of the root
+/// are refused after resolving them.
+fn resolve(root: &Path, requested: &str) -> Result<PathBuf, Reply> {
+    let relative = Path::new(requested.trim_start_matches(['/', '\\']));
+    if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(Reply::error(403, "path outside of the served root"));
+    }
+    let source = fs::canonicalize(root.join(relative)).map_err(|_| Reply::error(404, "no such file"))?;
+    if !source.starts_with(root) {
+        return Err(Reply::error(403, "path outside of the served root"));
+    }
+    if !is_exr_file(&source) {
+        return Err(Reply::error(404, "not an EXR file"));
+    }
+    Ok(source)
+}
+
+/// Decode `a=1&b=x%20y` into a map; later duplicates win.
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encoded thumbnails on disk with a size limit and least recently used
+/// eviction. Only the index is locked, files are read and written outside the
+/// lock so requests do not wait for each other's disk access.
+struct DiskCache {
+    dir: PathBuf,
+    limit: u64,
+    index: Mutex<CacheIndex>,
+}
+
+/// Cached files with their sizes and last use
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total: u64,
+}
+
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl DiskCache {
+    /// Open the cache folder, creating it if needed. The modification time of
+    /// cached files records when they were last used.
+    fn open(dir: PathBuf, limit: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut entries = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(meta) = entry.metadata() else { continue };
+            if !meta.is_file() || name.ends_with(".tmp") {
+                continue;
+            }
+            let last_used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.insert(name, CacheEntry { size: meta.len(), last_used });
+        }
+        let total = entries.values().map(|entry| entry.size).sum();
+        let mut index = CacheIndex { entries, total };
+        let evicted = index.evict(limit);
+        let cache = Self { dir, limit, index: Mutex::new(index) };
+        cache.remove_files(evicted);
+        Ok(cache)
+    }
+
+    fn get(&self, name: &str) -> Option<Vec<u8>> {
+        let last_used = SystemTime::now();
+        self.index.lock().unwrap().entries.get_mut(name)?.last_used = last_used;
+        let path = self.dir.join(name);
+        match fs::read(&path) {
+            Ok(body) => {
+                if let Ok(file) = fs::File::options().write(true).open(&path) {
+                    let _ = file.set_modified(last_used);
+                }
+                Some(body)
+            }
+            Err(_) => {
+                self.index.lock().unwrap().remove(name);
+                None
+            }
+        }
+    }
+
+    /// Store a thumbnail, written to a temporary name first so readers never
+    /// see a partial file, and evict old entries over the size limit.
+    fn insert(&self, name: &str, body: &[u8]) -> io::Result<()> {
+        output::write_atomic(&self.dir.join(name), body)?;
+        let entry = CacheEntry {
+            size: body.len() as u64,
+            last_used: SystemTime::now(),
+        };
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.remove(name);
+            index.total += entry.size;
+            index.entries.insert(name.to_string(), entry);
+            index.evict(self.limit)
+        };
+        self.remove_files(evicted);
+        Ok(())
+    }
+
+    fn remove_files(&self, names: Vec<String>) {
+        for name in names {
+            let _ = fs::remove_file(self.dir.join(name));
+        }
+    }
+}
+
+impl CacheIndex {
+    fn remove(&mut self, name: &str) {
+        if let Some(entry) = self.entries.remove(name) {
+            self.total -= entry.size;
+        }
+    }
+
+    /// Drop the least recently used entries until the total fits in `limit`
+    /// and return their names, for deleting their files outside the lock.
+    fn evict(&mut self, limit: u64) -> Vec<String> {
+        if self.total <= limit {
+            return Vec::new();
+        }
+        let mut by_age: Vec<(SystemTime, String)> =
+            self.entries.iter().map(|(name, entry)| (entry.last_used, name.clone())).collect();
+        by_age.sort();
+        let mut evicted = Vec::new();
+        for (_, name) in by_age {
+            if self.total <= limit {
+                break;
+            }
+            self.remove(&name);
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Served root with `shot/a.exr`, next to a folder outside of it with
+    /// `secret.exr`; removed when dropped.
+    struct Folders {
+        base: PathBuf,
+        root: PathBuf,
+        outside: PathBuf,
+    }
+
+    impl Folders {
+        fn new(name: &str) -> Self {
+            let base = std::env::temp_dir().join(format!("exr_thumbnailer_serve_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&base);
+            fs::create_dir_all(base.join("root/shot")).unwrap();
+            fs::create_dir_all(base.join("outside")).unwrap();
+            fs::write(base.join("root/shot/a.exr"), b"").unwrap();
+            fs::write(base.join("outside/secret.exr"), b"").unwrap();
+            let base = fs::canonicalize(base).unwrap();
+            Self {
+                root: base.join("root"),
+                outside: base.join("outside"),
+                base,
+            }
+        }
+    }
+
+    impl Drop for Folders {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    /// Server state for the root of `folders`, caching in `cache`
+    fn state(folders: &Folders, cache: DiskCache) -> State {
+        State {
+            root: folders.root.clone(),
+            request: ThumbnailRequest::default().decode_threads(1),
+            render_settings: String::new(),
+            cache,
+            hashes: Mutex::new(HashMap::new()),
+            in_flight: AtomicUsize::new(0),
+            max_concurrent: 1,
+            verbose: false,
+        }
+    }
+
+    fn status(result: Result<PathBuf, Reply>) -> u16 {
+        match result {
+            Ok(path) => panic!("resolved to {}", path.display()),
+            Err(reply) => reply.status,
+        }
+    }
+
+    #[test]
+    fn paths_below_the_root_resolve() {
+        let folders = Folders::new("below");
+        let expected = folders.root.join("shot/a.exr");
+        assert_eq!(resolve(&folders.root, "shot/a.exr").ok(), Some(expected.clone()));
+        assert_eq!(resolve(&folders.root, "./shot/./a.exr").ok(), Some(expected.clone()));
+        assert_eq!(resolve(&folders.root, "/shot/a.exr").ok(), Some(expected));
+        assert_eq!(status(resolve(&folders.root, "shot/missing.exr")), 404);
+    }
+
+    #[test]
+    fn parent_components_are_refused() {
+        let folders = Folders::new("parent");
+        assert_eq!(status(resolve(&folders.root, "../outside/secret.exr")), 403);
+        assert_eq!(status(resolve(&folders.root, "shot/../../outside/secret.exr")), 403);
+        assert_eq!(status(resolve(&folders.root, "shot/../shot/a.exr")), 403);
+        // A folder separator on Windows, part of the file name elsewhere
+        assert!(resolve(&folders.root, "..\\outside\\secret.exr").is_err());
+    }
+
+    #[test]
+    fn percent_encoded_parents_are_refused() {
+        let folders = Folders::new("encoded");
+        let params = parse_query("path=%2e%2e%2Foutside%2fsecret.exr&h=64");
+        assert_eq!(params["path"], "../outside/secret.exr");
+        assert_eq!(status(resolve(&folders.root, &params["path"])), 403);
+        assert_eq!(percent_decode("shot%2F..%2F..%2fx.exr"), "shot/../../x.exr");
+        assert_eq!(percent_decode("a+b%20c%zz%"), "a b c%zz%");
+    }
+
+    #[test]
+    fn absolute_paths_stay_below_the_root() {
+        let folders = Folders::new("absolute");
+        let secret = folders.outside.join("secret.exr");
+        assert_eq!(status(resolve(&folders.root, secret.to_str().unwrap())), 404);
+        assert_eq!(status(resolve(&folders.root, "//etc/passwd")), 404);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_out_of_the_root_are_refused() {
+        let folders = Folders::new("symlink");
+        std::os::unix::fs::symlink(folders.outside.join("secret.exr"), folders.root.join("link.exr")).unwrap();
+        std::os::unix::fs::symlink(&folders.outside, folders.root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(folders.root.join("shot/a.exr"), folders.root.join("inside.exr")).unwrap();
+        assert_eq!(status(resolve(&folders.root, "link.exr")), 403);
+        assert_eq!(status(resolve(&folders.root, "linked/secret.exr")), 403);
+        assert_eq!(resolve(&folders.root, "inside.exr").ok(), Some(folders.root.join("shot/a.exr")));
+    }
+
+    #[test]
+    fn head_requests_get_no_thumbnail() {
+        use exr::prelude::traits::*;
+
+        let folders = Folders::new("head");
+        let channels = exr::prelude::SpecificChannels::rgba(|_| (0.5_f32, 0.5_f32, 0.5_f32, 1.0_f32));
+        exr::prelude::Image::from_channels((16, 8), channels)
+            .write()
+            .to_file(folders.root.join("shot/a.exr"))
+            .unwrap();
+        let cache_dir = folders.base.join("cache");
+        let state = state(&folders, DiskCache::open(cache_dir.clone(), 1 << 20).unwrap());
+        let reply = |method: Method, url: &str| {
+            let request = tiny_http::TestRequest::new().with_method(method).with_path(url);
+            state.reply(&request.into()).unwrap_or_else(|reply| reply)
+        };
+
+        let head = reply(Method::Head, "/thumb?path=shot/a.exr&h=4");
+        assert_eq!(head.status, 200);
+        assert_eq!(head.content_type, "image/png");
+        assert!(head.body.is_empty());
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 0);
+
+        let get = reply(Method::Get, "/thumb?path=shot/a.exr&h=4");
+        assert_eq!(get.status, 200);
+        assert_eq!(image::load_from_memory(&get.body).unwrap().height(), 4);
+        assert_eq!(get.etag, head.etag);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        // The height limit of the command line
+        assert_eq!(reply(Method::Head, &format!("/thumb?path=shot/a.exr&h={}", MAX_HEIGHT)).status, 200);
+        assert_eq!(reply(Method::Head, &format!("/thumb?path=shot/a.exr&h={}", MAX_HEIGHT + 1)).status, 400);
+    }
+
+    #[test]
+    fn the_cache_evicts_the_least_recently_used_thumbnails() {
+        let folders = Folders::new("cache");
+        let dir = folders.base.join("cache");
+        let cache = DiskCache::open(dir.clone(), 10).unwrap();
+        cache.insert("a.png", b"aaaa").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        cache.insert("b.png", b"bbbb").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(cache.get("a.png").as_deref(), Some(&b"aaaa"[..]));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        cache.insert("c.png", b"cccc").unwrap();
+
+        assert_eq!(cache.get("b.png"), None);
+        assert!(!dir.join("b.png").exists());
+        assert!(cache.get("a.png").is_some() && cache.get("c.png").is_some());
+        assert_eq!(cache.index.lock().unwrap().total, 8);
+    }
+
+    #[test]
+    fn opening_the_cache_evicts_the_oldest_files_over_the_limit() {
+        let folders = Folders::new("reopen");
+        let dir = folders.base.join("cache");
+        fs::create_dir_all(&dir).unwrap();
+        for (name, seconds) in [("old.png", 1_000_000), ("new.png", 2_000_000), ("newest.png", 3_000_000)] {
+            fs::write(dir.join(name), b"1234").unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            fs::File::options().write(true).open(dir.join(name)).unwrap().set_modified(mtime).unwrap();
+        }
+        fs::write(dir.join("partial.png.tmp"), b"1234").unwrap();
+
+        let cache = DiskCache::open(dir.clone(), 8).unwrap();
+        assert!(!dir.join("old.png").exists());
+        assert!(dir.join("new.png").exists() && dir.join("newest.png").exists());
+        assert_eq!(cache.index.lock().unwrap().total, 8);
+    }
+
+    #[test]
+    fn only_the_most_recently_used_source_hashes_are_kept() {
+        let start = Instant::now();
+        let mut hashes: HashMap<PathBuf, SourceHash> = (0..4)
+            .map(|i| {
+                let known = SourceHash {
+                    size: 0,
+                    modified: None,
+                    hash: i.to_string(),
+                    last_used: start + std::time::Duration::from_secs(i),
+                };
+                (PathBuf::from(format!("{}.exr", i)), known)
+            })
+            .collect();
+        forget_least_recent(&mut hashes, 4);
+        assert_eq!(hashes.len(), 4);
+        forget_least_recent(&mut hashes, 2);
+        let mut kept: Vec<&str> = hashes.values().map(|known| known.hash.as_str()).collect();
+        kept.sort();
+        assert_eq!(kept, ["2", "3"]);
+    }
+}
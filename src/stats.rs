@@ -0,0 +1,206 @@
+//! Per-channel statistics and a log-luminance histogram of the decoded
+//! pixels, to compare exposure across shots.
+
+use image::{imageops, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::qc::luminance;
+
+/// Lower end of the histogram in stops relative to a luminance of 1.0
+pub const HISTOGRAM_MIN_STOPS: f32 = -12.0;
+/// Upper end of the histogram in stops relative to a luminance of 1.0
+pub const HISTOGRAM_MAX_STOPS: f32 = 8.0;
+/// Histogram bins, a quarter stop each
+pub const HISTOGRAM_BINS: usize = 80;
+
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 160]);
+const BAR: Rgba<u8> = Rgba([230, 230, 230, 255]);
+/// Marks a luminance of 1.0
+const MARKER: Rgba<u8> = Rgba([255, 200, 0, 255]);
+
+/// Statistics of the finite values of one channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ChannelStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    /// Population standard deviation
+    pub stddev: f64,
+}
+
+/// Statistics of the decoded pixels
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PixelStats {
+    /// R, G, B and A
+    pub channels: [ChannelStats; 4],
+    /// Pixels per bin of log2 luminance from [`HISTOGRAM_MIN_STOPS`] to
+    /// [`HISTOGRAM_MAX_STOPS`]. Darker and brighter pixels, including
+    /// black ones, are counted in the first and last bin.
+    pub histogram: Vec<u64>,
+}
+
+impl PixelStats {
+    /// Draw the histogram into a `width` x `height` image on a translucent
+    /// background, with a marker at a luminance of 1.0.
+    pub fn histogram_image(&self, width: u32, height: u32) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+        let tallest = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        let marker = (-HISTOGRAM_MIN_STOPS / (HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS) * width as f32) as u32;
+        for x in 0..width {
+            let bin = x as usize * self.histogram.len() / width as usize;
+            let count = self.histogram.get(bin).copied().unwrap_or(0);
+            let bar = (count as f64 / tallest as f64 * height as f64).round() as u32;
+            for y in height - bar..height {
+                image.put_pixel(x, y, BAR);
+            }
+            if x == marker {
+                for y in 0..height {
+                    image.put_pixel(x, y, MARKER);
+                }
+            }
+        }
+        image
+    }
+
+    /// Overlay the histogram in the bottom right corner of `image`, at a
+    /// third of its width and a quarter of its height.
+    pub fn overlay_histogram(&self, image: &mut RgbaImage) {
+        let width = (image.width() / 3).max(32).min(image.width());
+        let height = (image.height() / 4).max(16).min(image.height());
+        let histogram = self.histogram_image(width, height);
+        imageops::overlay(image, &histogram, (image.width() - width) as i64, (image.height() - height) as i64);
+    }
+}
+
+/// Gathers [`PixelStats`] while the pixels are decoded.
+pub(crate) struct StatsAccumulator {
+    min: [f32; 4],
+    max: [f32; 4],
+    sums: [f64; 4],
+    squares: [f64; 4],
+    count: u64,
+    histogram: Vec<u64>,
+}
+
+impl StatsAccumulator {
+    pub fn new() -> Self {
+        Self {
+            min: [f32::INFINITY; 4],
+            max: [f32::NEG_INFINITY; 4],
+            sums: [0.0; 4],
+            squares: [0.0; 4],
+            count: 0,
+            histogram: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    /// Add a pixel with finite values.
+    pub fn add(&mut self, pixel: [f32; 4]) {
+        for (c, &value) in pixel.iter().enumerate() {
+            self.min[c] = self.min[c].min(value);
+            self.max[c] = self.max[c].max(value);
+            self.sums[c] += value as f64;
+            self.squares[c] += value as f64 * value as f64;
+        }
+        self.count += 1;
+
+        let stops = luminance(pixel).log2();
+        let position = (stops - HISTOGRAM_MIN_STOPS) / (HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS);
+        // Black and negative luminances give -inf and NaN, which saturate
+        // to the first bin
+        let bin = (position * HISTOGRAM_BINS as f32) as usize;
+        self.histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    pub fn finish(self) -> PixelStats {
+        let count = self.count.max(1) as f64;
+        let channels = std::array::from_fn(|c| {
+            if self.count == 0 {
+                return ChannelStats::default();
+            }
+            let mean = self.sums[c] / count;
+            ChannelStats {
+                min: self.min[c],
+                max: self.max[c],
+                mean,
+                stddev: (self.squares[c] / count - mean * mean).max(0.0).sqrt(),
+            }
+        });
+        PixelStats {
+            channels,
+            histogram: self.histogram,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(pixels: &[[f32; 4]]) -> PixelStats {
+        let mut accumulator = StatsAccumulator::new();
+        for &pixel in pixels {
+            accumulator.add(pixel);
+        }
+        accumulator.finish()
+    }
+
+    /// Height of the bar drawn in column `x`
+    fn bar_height(image: &RgbaImage, x: u32) -> u32 {
+        (0..image.height()).filter(|&y| *image.get_pixel(x, y) == BAR).count() as u32
+    }
+
+    #[test]
+    fn channels_and_histogram_of_a_known_image() {
+        let stats = stats(&[
+            [0.0, 0.0, 0.0, 1.0],
+            [1.1, 1.1, 1.1, 1.0],
+            [1.1, 1.1, 1.1, 1.0],
+            [2.2, 2.2, 2.2, 1.0],
+        ]);
+        for channel in &stats.channels[..3] {
+            assert_eq!((channel.min, channel.max), (0.0, 2.2));
+            assert!((channel.mean - 1.1).abs() < 1e-6, "{:?}", channel);
+            assert!((channel.stddev - 0.605_f64.sqrt()).abs() < 1e-6, "{:?}", channel);
+        }
+        assert_eq!(stats.channels[3], ChannelStats { min: 1.0, max: 1.0, mean: 1.0, stddev: 0.0 });
+
+        // Black in the first bin, 1.1 and 2.2 a little above 0 and 1 stops
+        let filled: Vec<(usize, u64)> =
+            stats.histogram.iter().copied().enumerate().filter(|&(_, count)| count > 0).collect();
+        assert_eq!(filled, [(0, 1), (48, 2), (52, 1)]);
+
+        // Two columns per bin, scaled to the tallest bin
+        let image = stats.histogram_image(160, 10);
+        assert_eq!(bar_height(&image, 0), 5);
+        assert_eq!(bar_height(&image, 1), 5);
+        assert_eq!(bar_height(&image, 2), 0);
+        assert_eq!(bar_height(&image, 97), 10);
+        assert_eq!(bar_height(&image, 104), 5);
+        // The marker at a luminance of 1.0 covers the whole column
+        assert!((0..10).all(|y| *image.get_pixel(96, y) == MARKER));
+    }
+
+    #[test]
+    fn equal_and_missing_pixels_give_flat_statistics() {
+        let equal = stats(&[[0.3, 0.3, 0.3, 1.0]; 5]);
+        for channel in &equal.channels {
+            assert_eq!(channel.min, channel.max);
+            assert!((channel.mean - channel.min as f64).abs() < 1e-6);
+            assert!(channel.stddev < 1e-6, "{:?}", channel);
+        }
+        assert_eq!(equal.histogram.iter().filter(|&&count| count > 0).count(), 1);
+        let image = equal.histogram_image(80, 10);
+        assert_eq!((0..80).filter(|&x| bar_height(&image, x) == 10).count(), 1);
+
+        let empty = stats(&[]);
+        assert_eq!(empty.channels, [ChannelStats::default(); 4]);
+        assert!(empty.histogram.iter().all(|&count| count == 0));
+        let image = empty.histogram_image(80, 10);
+        assert!((0..80).all(|x| bar_height(&image, x) == 0));
+
+        let no_bins = PixelStats { channels: empty.channels, histogram: Vec::new() };
+        let image = no_bins.histogram_image(80, 10);
+        assert!((0..80).all(|x| bar_height(&image, x) == 0));
+    }
+}
@@ -0,0 +1,626 @@
+//! Thumbnail requests and the in-memory thumbnails they produce.
+
+use image::buffer::ConvertBuffer;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use image::{ImageFormat, Rgba, RgbImage, RgbaImage};
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::burnin::{draw_burnins, header_attributes, Burnin, BurninContext};
+use crate::color::ColorConfig;
+use crate::decode::{decode_exr, Deadline, DecodePool, DecodedExr};
+use crate::hashing::HashingReader;
+use crate::error::ThumbError;
+use crate::invalid::{InvalidValues, NEGATIVE, NEGATIVE_COLOUR, NON_FINITE, NON_FINITE_COLOUR};
+use crate::provenance::{encode_jpeg, encode_png, encode_webp, Provenance};
+use crate::qc::{draw_border, FrameStats, DEFAULT_FIREFLY_THRESHOLD};
+use crate::stats::PixelStats;
+use crate::resample::{lanczos_resize, reduction_factors};
+use crate::timing::{Stage, StageTimings};
+
+/// Image format the thumbnail is encoded to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    /// File extension of thumbnails in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    /// MIME type of thumbnails in this format
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Resampling filter used to scale the image to the thumbnail size.
+///
+/// Sources at least four times the thumbnail size are first box averaged in
+/// linear light while decoding, to between two and four times the thumbnail
+/// size. The filter only scales the rest of the way, so for large sources
+/// every filter works on averaged pixels and they differ mostly in sharpness.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// Nearest neighbour, fastest and blockiest. Picks source pixels only
+    /// for sources less than four times the thumbnail size, otherwise box
+    /// averaged ones
+    Nearest,
+    /// Bilinear
+    Triangle,
+    /// Catmull-Rom bicubic
+    Cubic,
+    Gaussian,
+    #[default]
+    Lanczos3,
+    /// Average of the pixels covered by each thumbnail pixel; with the box
+    /// averaging while decoding close to a plain box filter of the source
+    Box,
+    /// Lanczos3 on the linear HDR data before the colour processing. Highest
+    /// quality for large downscale ratios.
+    Area,
+}
+
+impl Filter {
+    /// Name used on the command line and in configuration files
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Triangle => "triangle",
+            Filter::Cubic => "cubic",
+            Filter::Gaussian => "gaussian",
+            Filter::Lanczos3 => "lanczos3",
+            Filter::Box => "box",
+            Filter::Area => "area",
+        }
+    }
+
+    fn resize(self, image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+        let filter = match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::Cubic => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+            Filter::Box => return image::imageops::thumbnail(image, width, height),
+            Filter::Area => unreachable!("the area filter resizes the linear pixels"),
+        };
+        image::imageops::resize(image, width, height, filter)
+    }
+}
+
+/// How the thumbnail is sized relative to the source image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    /// Fixed height in pixels, the width keeps the aspect ratio
+    Height(u32),
+    /// Fit into a square of this edge length, never upscaling
+    Fit(u32),
+}
+
+impl ThumbnailSize {
+    /// Thumbnail dimensions for a source image of the given size.
+    pub fn dimensions(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            ThumbnailSize::Height(thumb_height) => {
+                (((width as f32 / height as f32 * thumb_height as f32) as u32).max(1), thumb_height)
+            }
+            ThumbnailSize::Fit(max_edge) => {
+                let longest = width.max(height);
+                if longest <= max_edge {
+                    return (width, height);
+                }
+                let scale = max_edge as f64 / longest as f64;
+                (
+                    ((width as f64 * scale).round() as u32).max(1),
+                    ((height as f64 * scale).round() as u32).max(1),
+                )
+            }
+        }
+    }
+}
+
+/// What to generate a thumbnail from and how.
+///
+/// Defaults to a 256 pixel high PNG of the first RGBA layer, Lanczos3
+/// filtering and a plain 2.2 gamma. Sources at least four times the thumbnail
+/// size are box averaged while decoding, the filter scales the rest of the way.
+#[derive(Clone, Debug)]
+pub struct ThumbnailRequest {
+    source: PathBuf,
+    pub(crate) size: ThumbnailSize,
+    pub(crate) layer: Option<String>,
+    color: ColorConfig,
+    filter: Filter,
+    format: OutputFormat,
+    pub(crate) decode_pool: DecodePool,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) cancel: Option<Arc<AtomicBool>>,
+    flag_invalid: bool,
+    firefly_threshold: f32,
+    qc_border: bool,
+    pixel_stats: bool,
+    histogram_overlay: bool,
+    burnin: Vec<Burnin>,
+    provenance: bool,
+}
+
+impl ThumbnailRequest {
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        Self {
+            source: source.into(),
+            size: ThumbnailSize::Height(256),
+            layer: None,
+            color: ColorConfig::default(),
+            filter: Filter::Lanczos3,
+            format: OutputFormat::Png,
+            decode_pool: DecodePool::default(),
+            timeout: None,
+            cancel: None,
+            flag_invalid: false,
+            firefly_threshold: DEFAULT_FIREFLY_THRESHOLD,
+            qc_border: false,
+            pixel_stats: false,
+            histogram_overlay: false,
+            burnin: Vec::new(),
+            provenance: true,
+        }
+    }
+
+    /// EXR file to read
+    pub fn source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.source = source.into();
+        self
+    }
+
+    pub fn size(mut self, size: ThumbnailSize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Layer to read instead of the first RGBA layer: the name of a part in a
+    /// multi-part file or a channel prefix such as `diffuse` for `diffuse.R`
+    pub fn layer(mut self, layer: impl Into<String>) -> Self {
+        self.layer = Some(layer.into());
+        self
+    }
+
+    /// Like [`ThumbnailRequest::layer`], but `None` selects the first RGBA layer
+    pub fn maybe_layer(mut self, layer: Option<String>) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn color(mut self, color: ColorConfig) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Threads decompressing the blocks of the file, 0 (the default) uses one
+    /// per CPU and 1 decompresses them in the calling thread. The threads are
+    /// started by the first thumbnail and shared by the clones of the request.
+    pub fn decode_threads(mut self, threads: usize) -> Self {
+        self.decode_pool = DecodePool::new(threads, self.decode_pool.files());
+        self
+    }
+
+    /// Files converted at the same time with clones of the request, 1 by
+    /// default. Their [`ThumbnailRequest::decode_threads`] are started once
+    /// for all of them.
+    pub fn concurrent_files(mut self, files: usize) -> Self {
+        self.decode_pool = DecodePool::new(self.decode_pool.threads(), files);
+        self
+    }
+
+    /// Paint pixels covering NaN or infinite values in [`NON_FINITE_COLOUR`]
+    /// and those covering negative values in [`NEGATIVE_COLOUR`]
+    pub fn flag_invalid(mut self, flag_invalid: bool) -> Self {
+        self.flag_invalid = flag_invalid;
+        self
+    }
+
+    /// Luminance above which isolated pixels count as fireflies, see
+    /// [`FrameStats::fireflies`]
+    pub fn firefly_threshold(mut self, threshold: f32) -> Self {
+        self.firefly_threshold = threshold;
+        self
+    }
+
+    /// Draw a red border around thumbnails of frames with
+    /// [`FrameStats::issues`]
+    pub fn qc_border(mut self, qc_border: bool) -> Self {
+        self.qc_border = qc_border;
+        self
+    }
+
+    /// Gather [`Thumbnail::pixel_stats`] while decoding
+    pub fn pixel_stats(mut self, pixel_stats: bool) -> Self {
+        self.pixel_stats = pixel_stats;
+        self
+    }
+
+    /// Overlay the luminance histogram in the bottom right corner of the
+    /// thumbnail. Implies [`ThumbnailRequest::pixel_stats`].
+    pub fn histogram_overlay(mut self, histogram_overlay: bool) -> Self {
+        self.histogram_overlay = histogram_overlay;
+        self
+    }
+
+    /// Give up on a thumbnail that takes longer than `timeout` with
+    /// [`ThumbError::Timeout`]. Decoding stops at the next block once the time
+    /// is up, and the thumbnail is generated on a thread of its own that is
+    /// abandoned if it is stuck, see [`crate::with_timeout`]. `None`, the
+    /// default, never stops.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stop decoding with [`ThumbError::Cancelled`] once `cancel` is set,
+    /// checked between blocks like the timeout. Setting the flag from
+    /// another thread abandons the thumbnails in progress without waiting
+    /// for them to finish.
+    pub fn cancel(mut self, cancel: Option<Arc<AtomicBool>>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Lines of text to draw into the corners of the thumbnail. Only
+    /// [`ThumbnailRequest::thumbnail`] knows the file name and frame number.
+    pub fn burnin(mut self, burnin: Vec<Burnin>) -> Self {
+        self.burnin = burnin;
+        self
+    }
+
+    /// Embed the source and settings into the encoded thumbnail, see
+    /// [`Provenance`]. On by default.
+    pub fn provenance(mut self, provenance: bool) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Read the source file and generate its thumbnail, recording the read,
+    /// decode, colour and resize stages. The file is streamed and hashed as
+    /// it is decoded, the read stage is the time spent reading it.
+    pub fn thumbnail(&self) -> Result<Thumbnail, ThumbError> {
+        match self.timeout {
+            Some(timeout) => {
+                let request = self.clone();
+                crate::with_timeout(timeout, move || request.thumbnail_of_file())
+            }
+            None => self.thumbnail_of_file(),
+        }
+    }
+
+    fn thumbnail_of_file(&self) -> Result<Thumbnail, ThumbError> {
+        let file = File::open(&self.source)?;
+        let meta = file.metadata()?;
+        let mtime = meta.modified().ok().and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok());
+        let reader = HashingReader::new(BufReader::new(file));
+        let mut thumbnail = self.generate(reader, Some(&self.source), meta.len(), StageTimings::default())?;
+        if let Some(provenance) = &mut thumbnail.provenance {
+            provenance.source = Some(fs::canonicalize(&self.source).unwrap_or_else(|_| self.source.clone()));
+            provenance.source_mtime = mtime.map(|mtime| mtime.as_secs());
+        }
+        Ok(thumbnail)
+    }
+
+    /// Rough upper bound of the memory in bytes [`ThumbnailRequest::thumbnail`]
+    /// needs for the source file, from the sizes declared in its headers.
+    pub fn memory_estimate(&self) -> Result<u64, ThumbError> {
+        let meta = ::exr::meta::MetaData::read_from_file(&self.source, false).map_err(ThumbError::from_exr_header)?;
+        // Blocks decompressed at the same time, see decode::read_layers
+        let blocks_in_flight = self.decode_pool.blocks_in_flight() as u64;
+        let estimate = meta
+            .headers
+            .iter()
+            .map(|header| {
+                let (width, height) = (header.layer_size.width() as u32, header.layer_size.height() as u32);
+                let (factor_x, factor_y) = reduction_factors(width, height, self.size);
+                let reduced_pixels = width.div_ceil(factor_x) as u64 * height.div_ceil(factor_y) as u64;
+                // Compressed and decompressed copy of every block in flight,
+                // the f32 reduced image and its 8 bit copy
+                2 * blocks_in_flight * header.max_block_byte_size() as u64 + reduced_pixels * (16 + 4)
+            })
+            .max()
+            .unwrap_or(0);
+        Ok(estimate)
+    }
+
+    /// Generate the thumbnail of an EXR file held in memory. The source path
+    /// of the request is ignored. With a timeout the bytes are copied for the
+    /// thread that generates the thumbnail.
+    pub fn thumbnail_from_bytes(&self, exr_bytes: &[u8]) -> Result<Thumbnail, ThumbError> {
+        let generate = |request: &Self, exr_bytes: &[u8]| {
+            let reader = HashingReader::new(Cursor::new(exr_bytes));
+            request.generate(reader, None, exr_bytes.len() as u64, StageTimings::default())
+        };
+        match self.timeout {
+            Some(timeout) => {
+                let (request, exr_bytes) = (self.clone(), exr_bytes.to_vec());
+                crate::with_timeout(timeout, move || generate(&request, &exr_bytes))
+            }
+            None => generate(self, exr_bytes),
+        }
+    }
+
+    /// Generate the thumbnail of an EXR file read from `reader`, which must
+    /// start at the beginning of the file and should be buffered. The source
+    /// path of the request is ignored and no content hash is computed. The
+    /// timeout is only checked between blocks, see [`crate::with_timeout`].
+    pub fn thumbnail_from_reader(&self, mut reader: impl Read + Seek) -> Result<Thumbnail, ThumbError> {
+        let source_bytes = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        self.generate(HashingReader::without_hash(reader), None, source_bytes, StageTimings::default())
+    }
+
+    fn generate(
+        &self,
+        mut reader: HashingReader<impl Read + Seek>,
+        source: Option<&Path>,
+        source_bytes: u64,
+        mut timings: StageTimings,
+    ) -> Result<Thumbnail, ThumbError> {
+        let stage_start = Instant::now();
+        let attributes = if self.burnin.iter().any(Burnin::uses_attributes) {
+            let attributes = header_attributes(&mut reader, self.layer.as_deref())?;
+            reader.seek(SeekFrom::Start(0))?;
+            attributes
+        } else {
+            Default::default()
+        };
+        let mut decoded = decode_exr(
+            &mut reader,
+            self.layer.as_deref(),
+            self.size,
+            self.firefly_threshold,
+            self.pixel_stats || self.histogram_overlay,
+            &self.decode_pool,
+            Deadline::new(self.timeout, self.cancel.clone()),
+        )?;
+        let (source_hash, read_time) = reader.finish()?;
+        // The file is read while decoding, reading is its own stage
+        timings.set(Stage::Read, read_time);
+        timings.set(Stage::Decode, stage_start.elapsed().saturating_sub(read_time));
+
+        let (source_width, source_height) = (decoded.source_width, decoded.source_height);
+        let (reduced_width, reduced_height) = (decoded.width, decoded.height);
+        let layer = decoded.layer.clone();
+        let invalid = decoded.invalid;
+        let invalid_flags = std::mem::take(&mut decoded.invalid_flags);
+        let stats = decoded.stats;
+        let pixel_stats = decoded.pixel_stats.take();
+        let mut image = self.render(decoded, &mut timings)?;
+        if self.flag_invalid && !invalid_flags.is_empty() {
+            paint_invalid(&mut image, &invalid_flags, reduced_width, reduced_height);
+        }
+        if let Some(pixel_stats) = pixel_stats.as_ref().filter(|_| self.histogram_overlay) {
+            pixel_stats.overlay_histogram(&mut image);
+        }
+        if !self.burnin.is_empty() {
+            let context = BurninContext {
+                source,
+                width: source_width,
+                height: source_height,
+                layer: layer.as_deref(),
+                attributes,
+            };
+            draw_burnins(&mut image, &self.burnin, &context);
+        }
+        if self.qc_border && !stats.issues().is_empty() {
+            draw_border(&mut image);
+        }
+        let provenance = self.provenance.then(|| Provenance {
+            source: None,
+            source_mtime: None,
+            source_hash: source_hash.clone(),
+            source_width,
+            source_height,
+            layer: layer.clone(),
+            color: self.color,
+        });
+
+        Ok(Thumbnail {
+            image,
+            source_width,
+            source_height,
+            layer,
+            format: self.format,
+            source_bytes,
+            source_hash,
+            invalid,
+            stats,
+            pixel_stats,
+            provenance,
+            timings,
+        })
+    }
+
+    /// Apply the colour processing to decoded pixels and scale them to the
+    /// thumbnail size, recording the colour and resize stages.
+    pub(crate) fn render(&self, mut decoded: DecodedExr, timings: &mut StageTimings) -> Result<RgbaImage, ThumbError> {
+        let (thumb_width, thumb_height) = self.size.dimensions(decoded.source_width, decoded.source_height);
+
+        if self.filter == Filter::Area {
+            // Resize the linear pixels first, the colour processing then only
+            // touches the thumbnail
+            let stage_start = Instant::now();
+            let pixels = std::mem::take(&mut decoded.pixels);
+            decoded.pixels = lanczos_resize(pixels, decoded.width, decoded.height, thumb_width, thumb_height);
+            timings.set(Stage::Resize, stage_start.elapsed());
+
+            let stage_start = Instant::now();
+            let img = RgbaImage::from_raw(thumb_width, thumb_height, self.color.to_rgba8(&decoded.pixels))
+                .ok_or_else(|| ThumbError::CorruptData("Could not create image buffer".to_string()))?;
+            timings.set(Stage::Colour, stage_start.elapsed());
+            return Ok(img);
+        }
+
+        // Apply the colour processing and create an 8 bit image
+        let stage_start = Instant::now();
+        let img = RgbaImage::from_raw(decoded.width, decoded.height, self.color.to_rgba8(&decoded.pixels))
+            .ok_or_else(|| ThumbError::CorruptData("Could not create image buffer".to_string()))?;
+        drop(decoded);
+        timings.set(Stage::Colour, stage_start.elapsed());
+
+        // Resize the image using the specified filter
+        let stage_start = Instant::now();
+        let thumbnail = self.filter.resize(&img, thumb_width, thumb_height);
+        timings.set(Stage::Resize, stage_start.elapsed());
+        Ok(thumbnail)
+    }
+}
+
+/// Paint the thumbnail pixels covered by flagged pixels of the reduced image.
+/// Non-finite values are painted last so they win where both kinds meet.
+fn paint_invalid(image: &mut RgbaImage, flags: &[u8], width: u32, height: u32) {
+    let (thumb_width, thumb_height) = image.dimensions();
+    // Range of thumbnail pixels covered by reduced pixel `i` of `n`
+    let span = |i: u32, n: u32, target: u32| {
+        let start = (i as u64 * target as u64 / n as u64) as u32;
+        let end = ((i as u64 + 1) * target as u64 / n as u64) as u32;
+        start.min(target - 1)..end.clamp(start + 1, target)
+    };
+    for (flag, colour) in [(NEGATIVE, NEGATIVE_COLOUR), (NON_FINITE, NON_FINITE_COLOUR)] {
+        for (index, _) in flags.iter().enumerate().filter(|(_, &flags)| flags & flag != 0) {
+            let (x, y) = ((index % width as usize) as u32, (index / width as usize) as u32);
+            for ty in span(y, height, thumb_height) {
+                for tx in span(x, width, thumb_width) {
+                    image.put_pixel(tx, ty, Rgba(colour));
+                }
+            }
+        }
+    }
+}
+
+impl Default for ThumbnailRequest {
+    /// Default settings without a source, for the `thumbnail_from_*` methods
+    fn default() -> Self {
+        Self::new(PathBuf::new())
+    }
+}
+
+/// Generate a thumbnail of `path` with the default settings of
+/// [`ThumbnailRequest`].
+pub fn thumbnail(path: &Path) -> Result<Thumbnail, ThumbError> {
+    ThumbnailRequest::new(path).thumbnail()
+}
+
+/// Generate a thumbnail of an in-memory EXR file with the default settings of
+/// [`ThumbnailRequest`] and return it encoded as PNG.
+pub fn thumbnail_bytes(exr_bytes: &[u8]) -> Result<Vec<u8>, ThumbError> {
+    ThumbnailRequest::default().thumbnail_from_bytes(exr_bytes)?.encode()
+}
+
+/// A generated thumbnail with metadata about its source
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    /// 8 bit display-referred pixels
+    pub image: RgbaImage,
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Name of the layer the pixels were taken from, if it has one
+    pub layer: Option<String>,
+    /// Format used by [`Thumbnail::encode`]
+    pub format: OutputFormat,
+    /// Size of the source file in bytes
+    pub source_bytes: u64,
+    /// Content hash of the source file, see [`crate::content_hash`]. Not
+    /// computed for thumbnails read from a reader.
+    pub source_hash: Option<String>,
+    /// NaN, infinite and negative values found while decoding
+    pub invalid: InvalidValues,
+    /// QC statistics of the decoded frame
+    pub stats: FrameStats,
+    /// Channel statistics and luminance histogram, if requested with
+    /// [`ThumbnailRequest::pixel_stats`]
+    pub pixel_stats: Option<PixelStats>,
+    /// Metadata [`Thumbnail::encode`] embeds, unless turned off with
+    /// [`ThumbnailRequest::provenance`]. The source path and modification
+    /// time are only known for thumbnails of files.
+    pub provenance: Option<Provenance>,
+    /// Durations of the stages run so far
+    pub timings: StageTimings,
+}
+
+impl Thumbnail {
+    /// Encode the thumbnail in its output format, with the provenance if
+    /// there is one. JPEG drops the alpha channel.
+    pub fn encode(&self) -> Result<Vec<u8>, ThumbError> {
+        let mut encoded = Cursor::new(Vec::new());
+        match (self.format, &self.provenance) {
+            (OutputFormat::Png, Some(provenance)) => return encode_png(&self.image, provenance),
+            (OutputFormat::Jpeg, Some(provenance)) => return encode_jpeg(&self.image.convert(), provenance),
+            (OutputFormat::Webp, Some(provenance)) => return encode_webp(&self.image, provenance),
+            (OutputFormat::Jpeg, None) => {
+                let rgb: RgbImage = self.image.convert();
+                rgb.write_to(&mut encoded, ImageFormat::Jpeg)?;
+            }
+            (format, None) => self.image.write_to(&mut encoded, format.image_format())?,
+        }
+        Ok(encoded.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_keeps_the_aspect_ratio() {
+        assert_eq!(ThumbnailSize::Height(100).dimensions(4096, 2048), (200, 100));
+        assert_eq!(ThumbnailSize::Height(256).dimensions(1920, 1080), (455, 256));
+    }
+
+    #[test]
+    fn very_tall_sources_are_at_least_one_pixel_wide() {
+        assert_eq!(ThumbnailSize::Height(32).dimensions(1, 4096), (1, 32));
+        assert_eq!(ThumbnailSize::Fit(32).dimensions(1, 4096), (1, 32));
+    }
+
+    #[test]
+    fn fit_only_shrinks() {
+        assert_eq!(ThumbnailSize::Fit(256).dimensions(4096, 2048), (256, 128));
+        assert_eq!(ThumbnailSize::Fit(256).dimensions(100, 50), (100, 50));
+    }
+}
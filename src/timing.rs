@@ -0,0 +1,212 @@
+//! Per-stage timing of the conversion pipeline.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Stage of the per-file conversion pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the EXR file from disk
+    Read,
+    /// Decompressing and decoding the EXR pixel data
+    Decode,
+    /// Tone mapping, gamma correction and quantisation to 8 bit
+    Colour,
+    /// Scaling to the thumbnail size
+    Resize,
+    /// Encoding the thumbnail image
+    Encode,
+    /// Writing the thumbnail to disk
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Read,
+        Stage::Decode,
+        Stage::Colour,
+        Stage::Resize,
+        Stage::Encode,
+        Stage::Write,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Decode => "decode",
+            Stage::Colour => "colour",
+            Stage::Resize => "resize",
+            Stage::Encode => "encode",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// Durations of every stage for a single file
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTimings([Duration; 6]);
+
+impl StageTimings {
+    pub fn set(&mut self, stage: Stage, duration: Duration) {
+        self.0[stage as usize] = duration;
+    }
+
+    pub fn get(&self, stage: Stage) -> Duration {
+        self.0[stage as usize]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.0.iter().sum()
+    }
+}
+
+/// Statistics for timing operations, summed over all files
+#[derive(Default)]
+pub struct TimingStats {
+    totals: [AtomicU64; 6], // Total time per stage (in nanoseconds)
+}
+
+impl TimingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, timings: &StageTimings) {
+        for stage in Stage::ALL {
+            self.totals[stage as usize].fetch_add(timings.get(stage).as_nanos() as u64, Ordering::SeqCst);
+        }
+    }
+
+    pub fn get(&self, stage: Stage) -> Duration {
+        Duration::from_nanos(self.totals[stage as usize].load(Ordering::SeqCst))
+    }
+
+    pub fn get_total_time(&self) -> Duration {
+        Stage::ALL.iter().map(|&stage| self.get(stage)).sum()
+    }
+}
+
+/// Upper bounds (in milliseconds) of the per-file duration histogram buckets.
+/// The last bucket is open-ended.
+const HISTOGRAM_BOUNDS_MS: [f64; 10] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+
+/// One bucket of a per-file duration histogram
+#[derive(Clone, Debug, Serialize)]
+pub struct HistogramBucket {
+    /// Upper bound of the bucket in milliseconds, `None` for the open-ended last bucket
+    pub le_ms: Option<f64>,
+    pub count: usize,
+}
+
+/// Distribution of per-file durations for one stage, in milliseconds
+#[derive(Clone, Debug, Serialize)]
+pub struct StageSummary {
+    pub stage: &'static str,
+    pub total_ms: f64,
+    pub average_ms: f64,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl StageSummary {
+    /// Summarise the per-file durations `samples_ms` of `stage`.
+    pub fn new(stage: &'static str, mut samples_ms: Vec<f64>) -> Self {
+        samples_ms.sort_by(f64::total_cmp);
+        let total_ms: f64 = samples_ms.iter().sum();
+        let mut histogram: Vec<HistogramBucket> = HISTOGRAM_BOUNDS_MS
+            .iter()
+            .map(|&bound| HistogramBucket { le_ms: Some(bound), count: 0 })
+            .chain(std::iter::once(HistogramBucket { le_ms: None, count: 0 }))
+            .collect();
+        for &sample in &samples_ms {
+            let bucket = HISTOGRAM_BOUNDS_MS
+                .iter()
+                .position(|&bound| sample <= bound)
+                .unwrap_or(HISTOGRAM_BOUNDS_MS.len());
+            histogram[bucket].count += 1;
+        }
+        Self {
+            stage,
+            total_ms,
+            average_ms: if samples_ms.is_empty() { 0.0 } else { total_ms / samples_ms.len() as f64 },
+            min_ms: samples_ms.first().copied().unwrap_or(0.0),
+            median_ms: percentile(&samples_ms, 50.0),
+            p95_ms: percentile(&samples_ms, 95.0),
+            max_ms: samples_ms.last().copied().unwrap_or(0.0),
+            histogram,
+        }
+    }
+}
+
+/// Nearest-rank percentile of already sorted samples.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert_eq!(percentile(&sorted, 30.0), 20.0);
+        assert_eq!(percentile(&sorted, 40.0), 20.0);
+        assert_eq!(percentile(&sorted, 50.0), 35.0);
+        assert_eq!(percentile(&sorted, 100.0), 50.0);
+        assert_eq!(percentile(&sorted, 0.0), 15.0);
+        assert_eq!(percentile(&[7.0], 95.0), 7.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn summaries_sort_the_samples() {
+        let samples: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+        let summary = StageSummary::new("decode", samples);
+        assert_eq!(summary.total_ms, 210.0);
+        assert_eq!(summary.average_ms, 10.5);
+        assert_eq!((summary.min_ms, summary.max_ms), (1.0, 20.0));
+        assert_eq!((summary.median_ms, summary.p95_ms), (10.0, 19.0));
+    }
+
+    #[test]
+    fn histogram_buckets_include_their_upper_bound() {
+        let summary = StageSummary::new("read", vec![1500.0, 0.5, 1.0, 1.5, 5.0, 1000.0]);
+        let counts: Vec<usize> = summary.histogram.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, [2, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+        assert_eq!(summary.histogram[0].le_ms, Some(1.0));
+        assert_eq!(summary.histogram[10].le_ms, None);
+    }
+
+    #[test]
+    fn an_empty_stage_is_all_zero() {
+        let summary = StageSummary::new("write", Vec::new());
+        assert_eq!(
+            (summary.total_ms, summary.average_ms, summary.min_ms, summary.median_ms, summary.p95_ms, summary.max_ms),
+            (0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+        );
+        assert!(summary.histogram.iter().all(|bucket| bucket.count == 0));
+    }
+
+    #[test]
+    fn stats_sum_the_stages_of_all_files() {
+        let stats = TimingStats::new();
+        let mut timings = StageTimings::default();
+        timings.set(Stage::Decode, Duration::from_millis(30));
+        timings.set(Stage::Write, Duration::from_millis(5));
+        stats.add(&timings);
+        stats.add(&timings);
+        assert_eq!(timings.total(), Duration::from_millis(35));
+        assert_eq!(stats.get(Stage::Decode), Duration::from_millis(60));
+        assert_eq!(stats.get(Stage::Read), Duration::ZERO);
+        assert_eq!(stats.get_total_time(), Duration::from_millis(70));
+    }
+}
@@ -0,0 +1,320 @@
+//! Watch mode: convert EXR files as renders finish writing them.
+//!
+//! File system events (inotify on Linux) mark files as pending. A pending
+//! file is converted once it has not changed for the debounce period and its
+//! header can be read, so frames still being written are left alone.
+//!
+//! New frames are checked for brightness jumps against the frames of their
+//! sequence converted before, including those of the initial batch.
+
+use notify::event::{AccessKind, AccessMode, EventKind};
+use notify::{RecursiveMode, Watcher};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::progress::{Progress, Verbosity};
+use crate::report::{FileRecord, RunReport};
+use crate::{cancel, is_exr_file, sequence, Batch};
+
+/// How long to wait for file system events before checking pending files
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How many debounce periods a stable file with an unreadable header is
+/// given before it is converted anyway and reported as failed
+const MAX_HEADER_RETRIES: u32 = 3;
+
+/// A file that changed and waits to become stable
+struct Pending {
+    last_change: Instant,
+    size: u64,
+    modified: Option<SystemTime>,
+    header_retries: u32,
+}
+
+impl Pending {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_change: now,
+            size: 0,
+            modified: None,
+            header_retries: 0,
+        }
+    }
+
+    /// Note a change of the file at `now` and remember its current size and
+    /// mtime.
+    fn touch(&mut self, path: &Path, now: Instant) {
+        self.last_change = now;
+        if let Ok(meta) = fs::metadata(path) {
+            self.size = meta.len();
+            self.modified = meta.modified().ok();
+        }
+    }
+}
+
+/// Whether a pending file can be converted now
+#[derive(Debug, PartialEq, Eq)]
+enum Readiness {
+    Waiting,
+    Ready,
+    Gone,
+}
+
+/// Watch the source folder until the watcher shuts down or the run is
+/// interrupted, converting new and modified EXR files. `records` holds the
+/// results of the initial batch. Returns the final report.
+pub fn watch(
+    batch: &Batch,
+    progress: &Progress,
+    records: Vec<FileRecord>,
+    start_time: Instant,
+) -> io::Result<RunReport> {
+    let args = batch.args;
+    let debounce = Duration::from_secs(args.debounce_secs);
+    let summary_interval = Duration::from_secs(args.summary_interval_secs);
+
+    // Latest record per source file, so re-rendered frames replace old results
+    let mut records: BTreeMap<PathBuf, FileRecord> =
+        records.into_iter().map(|record| (PathBuf::from(&record.source), record)).collect();
+    batch.save_manifest();
+    batch.write_report(records.values().cloned().collect(), 0, start_time.elapsed())?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+    watcher
+        .watch(args.source_folder(), RecursiveMode::NonRecursive)
+        .map_err(io::Error::other)?;
+
+    if progress.verbosity() > Verbosity::Quiet {
+        println!(
+            "Watching {} for new EXR files (debounce {}s). Press Ctrl-C to stop.",
+            args.source_folder().display(),
+            args.debounce_secs
+        );
+    }
+
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    let mut converted_since_summary = 0usize;
+    let mut last_summary = Instant::now();
+    // Ready files left unconverted by an interruption
+    let mut abandoned = 0;
+
+    while !cancel::requested() {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                let relevant = matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                );
+                for path in event.paths {
+                    if matches!(event.kind, EventKind::Remove(_)) {
+                        pending.remove(&path);
+                    } else if relevant && is_candidate(&path) {
+                        let now = Instant::now();
+                        pending.entry(path.clone()).or_insert_with(|| Pending::new(now)).touch(&path, now);
+                    }
+                }
+            }
+            Ok(Err(e)) => progress.error(&format!("Warning: file watcher error: {}", e)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let mut ready = Vec::new();
+        let now = Instant::now();
+        pending.retain(|path, state| match readiness(path, state, debounce, now) {
+            Readiness::Waiting => true,
+            Readiness::Ready => {
+                ready.push(path.clone());
+                false
+            }
+            Readiness::Gone => false,
+        });
+
+        if !ready.is_empty() {
+            let converted: Vec<FileRecord> =
+                ready.par_iter().filter_map(|path| batch.convert(path, progress)).collect();
+            abandoned += ready.len() - converted.len();
+            converted_since_summary += converted.len();
+            for record in converted {
+                records.insert(PathBuf::from(&record.source), record);
+            }
+            flag_brightness_jumps(batch, progress, &mut records);
+            batch.save_manifest();
+        }
+
+        if last_summary.elapsed() >= summary_interval {
+            let (report, _) = batch.write_report(records.values().cloned().collect(), 0, start_time.elapsed())?;
+            if progress.verbosity() > Verbosity::Quiet {
+                println!(
+                    "Watch summary: {} files processed since last summary, {} pending; total: Success: {}, Skipped: {}, Failure: {}",
+                    converted_since_summary,
+                    pending.len(),
+                    report.summary.succeeded,
+                    report.summary.skipped,
+                    report.summary.failed
+                );
+            }
+            converted_since_summary = 0;
+            last_summary = Instant::now();
+        }
+    }
+
+    let remaining = pending.len() + abandoned;
+    let (report, _) = batch.write_report(records.into_values().collect(), remaining, start_time.elapsed())?;
+    Ok(report)
+}
+
+/// Compare the frames converted so far with the previous frames of their
+/// sequences, see [`sequence::flag_brightness_jumps`].
+fn flag_brightness_jumps(batch: &Batch, progress: &Progress, records: &mut BTreeMap<PathBuf, FileRecord>) {
+    let args = batch.args;
+    let mut records: Vec<&mut FileRecord> = records.values_mut().collect();
+    let border = args.render.qc_border.then_some(&batch.request);
+    let jumps = sequence::flag_brightness_jumps(&mut records, args.jump_threshold as f64, border);
+    if progress.verbosity() > Verbosity::Quiet {
+        for index in jumps {
+            progress.error(&format!("Warning: {} failed QC: brightness jump", records[index].source));
+        }
+    }
+}
+
+/// EXR files, ignoring hidden files that writers use for partial output.
+fn is_candidate(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden && is_exr_file(path)
+}
+
+/// Whether the file at `path` is ready at `now`. A change found now restarts
+/// the debounce period.
+fn readiness(path: &Path, state: &mut Pending, debounce: Duration, now: Instant) -> Readiness {
+    if now.saturating_duration_since(state.last_change) < debounce {
+        return Readiness::Waiting;
+    }
+    let Ok(meta) = fs::metadata(path) else {
+        return Readiness::Gone;
+    };
+
+    // The file is still being written if its size or mtime moved since the last check
+    let modified = meta.modified().ok();
+    if meta.len() != state.size || modified != state.modified {
+        state.size = meta.len();
+        state.modified = modified;
+        state.last_change = now;
+        return Readiness::Waiting;
+    }
+
+    if exr::meta::MetaData::read_from_file(path, false).is_err() && state.header_retries < MAX_HEADER_RETRIES {
+        state.header_retries += 1;
+        state.last_change = now;
+        return Readiness::Waiting;
+    }
+    Readiness::Ready
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::*;
+
+    const DEBOUNCE: Duration = Duration::from_secs(5);
+
+    /// A folder for the watched files, removed when dropped
+    struct Folder(PathBuf);
+
+    impl Folder {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("exr_thumbnailer_watch_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write_exr(&self, name: &str) -> PathBuf {
+            let path = self.0.join(name);
+            let channels = SpecificChannels::rgba(|_| (0.5_f32, 0.5_f32, 0.5_f32, 1.0_f32));
+            Image::from_channels((8, 8), channels).write().to_file(&path).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Folder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn files_are_ready_once_unchanged_for_the_debounce_period() {
+        let folder = Folder::new("debounce");
+        let path = folder.write_exr("shot_1001.exr");
+        let start = Instant::now();
+        let mut state = Pending::new(start);
+        state.touch(&path, start);
+
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, start + Duration::from_secs(4)), Readiness::Waiting);
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, start + DEBOUNCE), Readiness::Ready);
+    }
+
+    #[test]
+    fn a_file_that_grew_restarts_the_debounce_period() {
+        let folder = Folder::new("growing");
+        let path = folder.write_exr("shot_1001.exr");
+        let start = Instant::now();
+        let mut state = Pending::new(start);
+        state.touch(&path, start);
+        // Still being written: the last event saw a shorter file
+        state.size -= 1;
+
+        let checked = start + DEBOUNCE;
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, checked), Readiness::Waiting);
+        assert_eq!(state.last_change, checked);
+        assert_eq!(state.size, fs::metadata(&path).unwrap().len());
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, checked + Duration::from_secs(1)), Readiness::Waiting);
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, checked + DEBOUNCE), Readiness::Ready);
+    }
+
+    #[test]
+    fn unreadable_headers_are_retried_before_the_file_is_converted() {
+        let folder = Folder::new("header");
+        let path = folder.0.join("shot_1001.exr");
+        fs::write(&path, b"not an exr yet").unwrap();
+        let start = Instant::now();
+        let mut state = Pending::new(start);
+        state.touch(&path, start);
+
+        let mut now = start;
+        for retry in 1..=MAX_HEADER_RETRIES {
+            now += DEBOUNCE;
+            assert_eq!(readiness(&path, &mut state, DEBOUNCE, now), Readiness::Waiting);
+            assert_eq!(state.header_retries, retry);
+        }
+        now += DEBOUNCE;
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, now), Readiness::Ready);
+    }
+
+    #[test]
+    fn removed_files_are_gone() {
+        let folder = Folder::new("removed");
+        let path = folder.write_exr("shot_1001.exr");
+        let start = Instant::now();
+        let mut state = Pending::new(start);
+        state.touch(&path, start);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(readiness(&path, &mut state, DEBOUNCE, start + DEBOUNCE), Readiness::Gone);
+    }
+
+    #[test]
+    fn hidden_and_other_files_are_not_watched() {
+        let folder = Folder::new("candidates");
+        assert!(is_candidate(&folder.write_exr("shot_1001.exr")));
+        assert!(!is_candidate(&folder.write_exr(".shot_1001.exr")));
+        fs::write(folder.0.join("notes.txt"), b"").unwrap();
+        assert!(!is_candidate(&folder.0.join("notes.txt")));
+    }
+}
@@ -0,0 +1,25 @@
+//! EXR files shared by the integration tests.
+
+// Every test crate uses a different part of this module
+#![allow(dead_code)]
+
+use exr::prelude::*;
+use std::io::Cursor;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+/// A single-part RGBA EXR of `WIDTH` x `HEIGHT` pixels given by `pixel`.
+pub fn exr_bytes(pixel: impl Fn(Vec2<usize>) -> (f32, f32, f32, f32) + Sync) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    Image::from_channels((WIDTH, HEIGHT), SpecificChannels::rgba(pixel))
+        .write()
+        .to_buffered(Cursor::new(&mut bytes))
+        .unwrap();
+    bytes
+}
+
+/// A horizontal ramp in red and constant green.
+pub fn ramp_exr() -> Vec<u8> {
+    exr_bytes(|position| (position.x() as f32 / WIDTH as f32, 0.5, 0.0, 1.0))
+}
@@ -0,0 +1,101 @@
+//! Thumbnails of EXR files that never touch the file system.
+
+mod common;
+
+use common::{ramp_exr, HEIGHT, WIDTH};
+use exr::prelude::*;
+use exr_thumbnailer::{thumbnail_bytes, ErrorKind, OutputFormat, ThumbnailRequest, ThumbnailSize};
+use std::io::Cursor;
+
+/// A multi-part EXR with a dark `beauty` and a bright `diffuse` part.
+fn multi_part_exr() -> Vec<u8> {
+    let size = Vec2(WIDTH, HEIGHT);
+    let part = |name: &str, value: f32| {
+        Layer::new(
+            size,
+            LayerAttributes::named(name),
+            Encoding::FAST_LOSSLESS,
+            SpecificChannels::rgba(move |_: Vec2<usize>| (value, value, value, 1.0)),
+        )
+    };
+    let mut bytes = Vec::new();
+    Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), vec![part("beauty", 0.0), part("diffuse", 1.0)])
+        .write()
+        .to_buffered(Cursor::new(&mut bytes))
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn thumbnail_from_bytes_keeps_aspect_ratio_and_metadata() {
+    let exr = ramp_exr();
+    let thumbnail = ThumbnailRequest::default()
+        .size(ThumbnailSize::Height(16))
+        .thumbnail_from_bytes(&exr)
+        .unwrap();
+
+    assert_eq!((thumbnail.image.width(), thumbnail.image.height()), (32, 16));
+    assert_eq!((thumbnail.source_width, thumbnail.source_height), (WIDTH as u32, HEIGHT as u32));
+    assert_eq!(thumbnail.source_bytes, exr.len() as u64);
+    assert_eq!(thumbnail.source_hash.as_deref(), Some(exr_thumbnailer::content_hash(&exr).as_str()));
+
+    // Red ramps up from left to right, green is 0.5 through a 2.2 gamma
+    let left = thumbnail.image.get_pixel(0, 8);
+    let right = thumbnail.image.get_pixel(31, 8);
+    assert!(left[0] < right[0]);
+    assert!((185..=187).contains(&left[1]));
+    assert_eq!(left[3], 255);
+}
+
+#[test]
+fn thumbnail_from_reader_matches_bytes() {
+    let exr = ramp_exr();
+    let request = ThumbnailRequest::default().size(ThumbnailSize::Fit(20));
+    let from_bytes = request.thumbnail_from_bytes(&exr).unwrap();
+    let from_reader = request.thumbnail_from_reader(Cursor::new(&exr)).unwrap();
+
+    assert_eq!(from_reader.image, from_bytes.image);
+    assert_eq!(from_reader.source_bytes, exr.len() as u64);
+    assert_eq!(from_reader.source_hash, None);
+}
+
+#[test]
+fn encoded_buffers_decode_in_the_requested_format() {
+    let exr = ramp_exr();
+    let png = thumbnail_bytes(&exr).unwrap();
+    assert_eq!(image::guess_format(&png).unwrap(), image::ImageFormat::Png);
+    let decoded = image::load_from_memory(&png).unwrap();
+    // The default request scales to a height of 256 pixels
+    assert_eq!((decoded.width(), decoded.height()), (512, 256));
+
+    let jpeg = ThumbnailRequest::default()
+        .format(OutputFormat::Jpeg)
+        .thumbnail_from_bytes(&exr)
+        .unwrap()
+        .encode()
+        .unwrap();
+    assert_eq!(image::guess_format(&jpeg).unwrap(), image::ImageFormat::Jpeg);
+}
+
+#[test]
+fn named_part_is_selected() {
+    let exr = multi_part_exr();
+    let request = ThumbnailRequest::default().size(ThumbnailSize::Height(8));
+
+    let first = request.thumbnail_from_bytes(&exr).unwrap();
+    assert_eq!(first.layer.as_deref(), Some("beauty"));
+    assert_eq!(first.image.get_pixel(0, 0)[0], 0);
+
+    let diffuse = request.clone().layer("diffuse").thumbnail_from_bytes(&exr).unwrap();
+    assert_eq!(diffuse.layer.as_deref(), Some("diffuse"));
+    assert_eq!(diffuse.image.get_pixel(0, 0)[0], 255);
+
+    let missing = request.layer("specular").thumbnail_from_bytes(&exr).unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::MissingLayer);
+}
+
+#[test]
+fn garbage_is_a_corrupt_header() {
+    let error = thumbnail_bytes(b"not an exr file").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::CorruptHeader);
+}
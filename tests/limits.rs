@@ -0,0 +1,29 @@
+//! Timeouts and cancellation of thumbnails in progress.
+
+mod common;
+
+use common::ramp_exr;
+use exr_thumbnailer::{ErrorKind, ThumbnailRequest};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn decoding_stops_at_the_timeout() {
+    let exr = ramp_exr();
+    let request = ThumbnailRequest::default().decode_threads(1);
+    let error = request.clone().timeout(Some(Duration::ZERO)).thumbnail_from_bytes(&exr).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    assert!(request.timeout(Some(Duration::from_secs(60))).thumbnail_from_bytes(&exr).is_ok());
+}
+
+#[test]
+fn decoding_stops_when_cancelled() {
+    let exr = ramp_exr();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let request = ThumbnailRequest::default().cancel(Some(cancel.clone()));
+    assert!(request.thumbnail_from_bytes(&exr).is_ok());
+    cancel.store(true, Ordering::SeqCst);
+    let error = request.thumbnail_from_bytes(&exr).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Cancelled);
+}
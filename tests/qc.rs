@@ -0,0 +1,85 @@
+//! Invalid values, QC issues and pixel statistics of decoded EXR files.
+
+mod common;
+
+use common::{exr_bytes, ramp_exr, HEIGHT, WIDTH};
+use exr_thumbnailer::qc::{self, QcIssue};
+use exr_thumbnailer::stats;
+use exr_thumbnailer::{ThumbnailRequest, ThumbnailSize, NEGATIVE_COLOUR, NON_FINITE_COLOUR};
+
+#[test]
+fn invalid_values_are_counted_and_flagged() {
+    let exr = exr_bytes(|position| match (position.x(), position.y()) {
+        (0, 0) => (f32::NAN, 0.5, 0.5, 1.0),
+        (63, 31) => (0.5, f32::INFINITY, 0.5, 1.0),
+        (32, 16) => (0.5, 0.5, -0.5, 1.0),
+        _ => (0.5, 0.5, 0.5, 1.0),
+    });
+
+    let request = ThumbnailRequest::default().size(ThumbnailSize::Height(16));
+    let plain = request.clone().thumbnail_from_bytes(&exr).unwrap();
+    assert_eq!(plain.invalid.nan, [1, 0, 0, 0]);
+    assert_eq!(plain.invalid.pos_inf, [0, 1, 0, 0]);
+    assert_eq!(plain.invalid.negative, [0, 0, 1, 0]);
+    assert_eq!(plain.invalid.to_string(), "1 NaN (R), 1 infinite (G) and 1 negative (B) values");
+    // Invalid values do not turn their neighbourhood black or white
+    assert!(plain.image.pixels().all(|pixel| pixel.0 != NON_FINITE_COLOUR && pixel[3] == 255));
+
+    let flagged = request.flag_invalid(true).thumbnail_from_bytes(&exr).unwrap();
+    assert_eq!(flagged.image.get_pixel(0, 0).0, NON_FINITE_COLOUR);
+    assert_eq!(flagged.image.get_pixel(31, 15).0, NON_FINITE_COLOUR);
+    assert_eq!(flagged.image.get_pixel(16, 8).0, NEGATIVE_COLOUR);
+    assert_ne!(flagged.image.get_pixel(8, 8).0, NEGATIVE_COLOUR);
+}
+
+/// A grey frame with `bright` pixels at a luminance of 1000.
+fn frame_with_bright_pixels(bright: &'static [(usize, usize)]) -> Vec<u8> {
+    exr_bytes(move |position| {
+        let value = if bright.contains(&(position.x(), position.y())) { 1000.0 } else { 0.2 };
+        (value, value, value, 1.0)
+    })
+}
+
+#[test]
+fn qc_finds_fireflies_but_not_small_lights() {
+    let request = ThumbnailRequest::default().size(ThumbnailSize::Height(8));
+    let firefly = request.clone().thumbnail_from_bytes(&frame_with_bright_pixels(&[(20, 10)])).unwrap();
+    assert_eq!(firefly.stats.fireflies, 1);
+    assert_eq!(firefly.stats.issues(), [QcIssue::Fireflies]);
+
+    let light: &[(usize, usize)] = &[(40, 20), (41, 20), (42, 20), (40, 21), (41, 21), (42, 21)];
+    let light = request.thumbnail_from_bytes(&frame_with_bright_pixels(light)).unwrap();
+    assert_eq!(light.stats.fireflies, 0);
+    assert!(light.stats.issues().is_empty());
+}
+
+#[test]
+fn qc_border_marks_black_frames() {
+    let exr = exr_bytes(|_| (0.0, 0.0, 0.0, 1.0));
+
+    let thumbnail = ThumbnailRequest::default()
+        .size(ThumbnailSize::Height(16))
+        .qc_border(true)
+        .thumbnail_from_bytes(&exr)
+        .unwrap();
+    assert_eq!(thumbnail.stats.issues(), [QcIssue::Black]);
+    assert_eq!(thumbnail.image.get_pixel(0, 0).0, qc::QC_BORDER_COLOUR);
+    assert_eq!(thumbnail.image.get_pixel(16, 8).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn pixel_stats_describe_the_source_pixels() {
+    let exr = ramp_exr();
+    let plain = ThumbnailRequest::default().thumbnail_from_bytes(&exr).unwrap();
+    assert!(plain.pixel_stats.is_none());
+
+    let thumbnail = ThumbnailRequest::default().pixel_stats(true).thumbnail_from_bytes(&exr).unwrap();
+    let stats = thumbnail.pixel_stats.unwrap();
+    let [red, green, blue, _] = stats.channels;
+    assert_eq!((red.min, red.max), (0.0, 63.0 / 64.0));
+    assert!((red.mean - 63.0 / 128.0).abs() < 1e-6);
+    assert_eq!((green.mean, green.stddev), (0.5, 0.0));
+    assert_eq!((blue.min, blue.max), (0.0, 0.0));
+    assert_eq!(stats.histogram.len(), stats::HISTOGRAM_BINS);
+    assert_eq!(stats.histogram.iter().sum::<u64>(), (WIDTH * HEIGHT) as u64);
+}
@@ -0,0 +1,75 @@
+//! Filtering, burn-ins and provenance metadata of the generated thumbnails.
+
+mod common;
+
+use common::{exr_bytes, ramp_exr};
+use exr_thumbnailer::burnin::Burnin;
+use exr_thumbnailer::{Filter, OutputFormat, ThumbnailRequest, ThumbnailSize};
+use std::io::Cursor;
+
+#[test]
+fn area_filter_averages_linear_light() {
+    // Alternating black and white columns
+    let exr = exr_bytes(|position| {
+        let value = (position.x() % 2) as f32;
+        (value, value, value, 1.0)
+    });
+
+    let area = ThumbnailRequest::default()
+        .size(ThumbnailSize::Height(8))
+        .filter(Filter::Area)
+        .thumbnail_from_bytes(&exr)
+        .unwrap();
+    assert_eq!((area.image.width(), area.image.height()), (16, 8));
+    // 0.5 in linear light is 186 after the 2.2 gamma, averaging the gamma
+    // encoded 0 and 255 would give 127
+    assert!(area.image.pixels().all(|pixel| (185..=187).contains(&pixel[0])));
+}
+
+#[test]
+fn burnins_are_drawn_into_their_corner() {
+    let burnin: Burnin = "top-left:H {{x}} {resolution}".parse().unwrap();
+    let exr = ramp_exr();
+    let plain = ThumbnailRequest::default().thumbnail_from_bytes(&exr).unwrap();
+    let thumbnail = ThumbnailRequest::default().burnin(vec![burnin]).thumbnail_from_bytes(&exr).unwrap();
+    // The first column of the H at 2x, inside the margin and padding
+    assert_eq!(thumbnail.image.get_pixel(6, 6).0, [255, 255, 255, 255]);
+    // The background box darkens the image around the text
+    assert!(thumbnail.image.get_pixel(5, 5)[1] < plain.image.get_pixel(5, 5)[1]);
+    let corner = (thumbnail.image.width() - 1, thumbnail.image.height() - 1);
+    assert_eq!(thumbnail.image.get_pixel(corner.0, corner.1), plain.image.get_pixel(corner.0, corner.1));
+}
+
+#[test]
+fn provenance_is_embedded_into_the_encoded_thumbnail() {
+    let exr = ramp_exr();
+    let thumbnail = ThumbnailRequest::default().thumbnail_from_bytes(&exr).unwrap();
+    let hash = exr_thumbnailer::content_hash(&exr);
+
+    let png = png::Decoder::new(Cursor::new(thumbnail.encode().unwrap())).read_info().unwrap();
+    let text: Vec<(&str, &str)> = png
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), chunk.text.as_str()))
+        .collect();
+    assert!(text.contains(&("exrthumb:source_hash", hash.as_str())));
+    assert!(text.contains(&("exrthumb:source_width", "64")));
+    assert!(text.contains(&("exrthumb:gamma", "2.2")));
+    // In-memory sources have no path
+    assert!(!text.iter().any(|(keyword, _)| *keyword == "exrthumb:source"));
+
+    let jpeg = ThumbnailRequest::default()
+        .format(OutputFormat::Jpeg)
+        .thumbnail_from_bytes(&exr)
+        .unwrap()
+        .encode()
+        .unwrap();
+    let xmp = String::from_utf8_lossy(&jpeg);
+    assert!(xmp.contains(&format!("<exrthumb:source_hash>{}</exrthumb:source_hash>", hash)));
+    assert!(image::load_from_memory(&jpeg).is_ok());
+
+    let plain = ThumbnailRequest::default().provenance(false).thumbnail_from_bytes(&exr).unwrap();
+    assert!(plain.provenance.is_none());
+    assert!(!String::from_utf8_lossy(&plain.encode().unwrap()).contains("exrthumb"));
+}